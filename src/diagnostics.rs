@@ -0,0 +1,372 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use aide::generate::GenContext;
+use aide::openapi::Operation;
+use aide::operation::OperationOutput;
+use axum::extract::{MatchedPath, Query, Request, State};
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use schemars::JsonSchema;
+use serde::Serialize;
+use tracing::Span;
+use tracing_subscriber::reload::Handle;
+use tracing_subscriber::{EnvFilter, Registry};
+
+/// Verbosity levels cycled through by `SIGUSR1`.
+const SIGNAL_LEVELS: [&str; 3] = ["info", "debug", "trace"];
+
+/// The default log filter, used if `RUST_LOG` is not set.
+pub fn default_log_filter(level: &str) -> String {
+    // axum logs rejections from built-in extractors with the `axum::rejection`
+    // target, at `TRACE` level. `axum::rejection=trace` enables showing those events
+    format!(
+        "{}={level},tower_http={level},axum::rejection=trace",
+        env!("CARGO_CRATE_NAME")
+    )
+}
+
+/// Handle to change the log filter of the running process.
+#[derive(Clone)]
+pub struct LogFilter {
+    handle: Handle<EnvFilter, Registry>,
+    current: Arc<Mutex<String>>,
+}
+
+impl LogFilter {
+    pub fn new(handle: Handle<EnvFilter, Registry>, current: String) -> Self {
+        Self {
+            handle,
+            current: Arc::new(Mutex::new(current)),
+        }
+    }
+
+    pub fn current(&self) -> String {
+        self.current.lock().unwrap().clone()
+    }
+
+    /// Replace the log filter, using the `RUST_LOG` directive syntax.
+    pub fn set(&self, directives: &str) -> Result<(), anyhow::Error> {
+        let filter = EnvFilter::try_new(directives)?;
+        self.handle.reload(filter)?;
+        *self.current.lock().unwrap() = directives.to_string();
+        tracing::warn!("Changed log filter to '{directives}'");
+        Ok(())
+    }
+
+    /// Switch to the next of the `info`, `debug` and `trace` levels.
+    fn cycle(&self) -> Result<(), anyhow::Error> {
+        let current = self.current();
+        let next = SIGNAL_LEVELS
+            .iter()
+            .position(|level| default_log_filter(level) == current)
+            .map_or(0, |i| (i + 1) % SIGNAL_LEVELS.len());
+        self.set(&default_log_filter(SIGNAL_LEVELS[next]))
+    }
+
+    /// Cycle the log level whenever the process receives `SIGUSR1`.
+    #[cfg(unix)]
+    pub fn listen_for_signal(self) -> Result<(), anyhow::Error> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut signals = signal(SignalKind::user_defined1())?;
+        tokio::spawn(async move {
+            while signals.recv().await.is_some() {
+                if let Err(e) = self.cycle() {
+                    tracing::error!("Failed to change log level: {e}");
+                }
+            }
+        });
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn listen_for_signal(self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+/// A request that is currently being processed.
+#[derive(Serialize, JsonSchema)]
+pub struct InFlightRequest {
+    pub id: u64,
+    pub method: String,
+    pub uri: String,
+    /// Time since the request was received, in milliseconds.
+    pub elapsed_ms: u128,
+    #[serde(skip)]
+    started: Instant,
+}
+
+/// Registry of the requests that are currently being processed.
+#[derive(Clone, Default)]
+pub struct InFlight {
+    next_id: Arc<AtomicU64>,
+    requests: Arc<Mutex<BTreeMap<u64, InFlightRequest>>>,
+}
+
+/// Removes a request from the registry when it completes or is cancelled.
+struct InFlightGuard<'a> {
+    registry: &'a InFlight,
+    id: u64,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.registry.requests.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl InFlight {
+    /// Snapshot of the in-flight requests, oldest first.
+    pub fn snapshot(&self) -> Vec<InFlightRequest> {
+        self.requests
+            .lock()
+            .unwrap()
+            .values()
+            .map(|request| InFlightRequest {
+                id: request.id,
+                method: request.method.clone(),
+                uri: request.uri.clone(),
+                elapsed_ms: request.started.elapsed().as_millis(),
+                started: request.started,
+            })
+            .collect()
+    }
+
+    /// Middleware tracking every request in the registry while it is processed.
+    pub async fn track(State(registry): State<InFlight>, request: Request, next: Next) -> Response {
+        let id = registry.next_id.fetch_add(1, Ordering::Relaxed);
+        registry.requests.lock().unwrap().insert(
+            id,
+            InFlightRequest {
+                id,
+                method: request.method().to_string(),
+                uri: request.uri().to_string(),
+                elapsed_ms: 0,
+                started: Instant::now(),
+            },
+        );
+        let _guard = InFlightGuard {
+            registry: &registry,
+            id,
+        };
+        next.run(request).await
+    }
+}
+
+/// Header carrying the id of a request, taken from the client or assigned by the server.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest request id accepted from a client, longer ids are replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Number of results in a response, attached as an extension for the access log.
+#[derive(Clone, Copy)]
+pub struct ResultCount(pub usize);
+
+/// Route template that matched a request, e.g. `/geonames/id/{id}/names`, attached as an extension
+/// for the access log.
+#[derive(Clone)]
+pub struct MatchedRoute(pub Arc<str>);
+
+/// Middleware recording the matched route in the request span and the response, for the logs.
+///
+/// Routes are only matched within the router they belong to, so this has to be layered onto every
+/// router, including the nested ones.
+pub async fn record_route(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| MatchedRoute(Arc::from(path.as_str())));
+    if let Some(route) = &route {
+        Span::current().record("route", &*route.0);
+    }
+    let mut response = next.run(request).await;
+    if let Some(route) = route {
+        response.extensions_mut().insert(route);
+    }
+    response
+}
+
+/// A fresh request id: 16 hex digits, unique within the process and unlikely to repeat across
+/// processes.
+fn new_request_id() -> HeaderValue {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(NEXT.fetch_add(1, Ordering::Relaxed));
+    HeaderValue::from_str(&format!("{:016x}", hasher.finish())).unwrap()
+}
+
+/// The request id sent by the client, if it is short and printable enough to be logged.
+fn client_request_id(request: &Request) -> Option<HeaderValue> {
+    let id = request.headers().get(REQUEST_ID_HEADER)?;
+    let valid = !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.as_bytes().iter().all(u8::is_ascii_graphic);
+    valid.then(|| id.clone())
+}
+
+/// Tracing span of a request, like the default span of `TraceLayer` with the request id.
+pub fn request_span<B>(request: &axum::http::Request<B>) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+    tracing::debug_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        route = tracing::field::Empty,
+        version = ?request.version(),
+        request_id,
+    )
+}
+
+/// What the access log records about a search request, attached to the response by the handler,
+/// like the `ResultCount`, so the body is never read twice.
+#[derive(Clone, Default)]
+pub struct RequestSummary {
+    /// Search mode of a batch query or DUUI request.
+    mode: Option<String>,
+    /// Length of the query, in characters.
+    query_len: Option<usize>,
+    /// Number of queries in a batch.
+    queries: Option<usize>,
+}
+
+impl RequestSummary {
+    /// A search for `query`.
+    pub fn query(query: &str) -> Self {
+        Self {
+            query_len: Some(query.chars().count()),
+            ..Self::default()
+        }
+    }
+
+    /// A batch of `queries` searches.
+    pub fn batch(queries: usize) -> Self {
+        Self {
+            queries: Some(queries),
+            ..Self::default()
+        }
+    }
+
+    /// A request searching in `mode`.
+    pub fn mode(mode: &str) -> Self {
+        Self {
+            mode: Some(mode.to_string()),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_query(self, query: &str) -> Self {
+        Self {
+            query_len: Some(query.chars().count()),
+            ..self
+        }
+    }
+
+    pub fn with_queries(self, queries: usize) -> Self {
+        Self {
+            queries: Some(queries),
+            ..self
+        }
+    }
+
+    /// Attach the summary to the response of the request.
+    pub fn attach<R>(self, response: R) -> Summarized<R> {
+        Summarized {
+            summary: self,
+            response,
+        }
+    }
+
+    fn of_params(params: &HashMap<String, String>) -> Self {
+        Self {
+            mode: params.get("mode").cloned(),
+            query_len: params.get("query").map(|query| query.chars().count()),
+            queries: None,
+        }
+    }
+}
+
+/// A response with the `RequestSummary` of its request.
+pub struct Summarized<R> {
+    summary: RequestSummary,
+    response: R,
+}
+
+impl<R: IntoResponse> IntoResponse for Summarized<R> {
+    fn into_response(self) -> Response {
+        let mut response = self.response.into_response();
+        response.extensions_mut().insert(self.summary);
+        response
+    }
+}
+
+impl<R: OperationOutput> OperationOutput for Summarized<R> {
+    type Inner = R::Inner;
+
+    fn operation_response(
+        ctx: &mut GenContext,
+        operation: &mut Operation,
+    ) -> Option<aide::openapi::Response> {
+        R::operation_response(ctx, operation)
+    }
+
+    fn inferred_responses(
+        ctx: &mut GenContext,
+        operation: &mut Operation,
+    ) -> Vec<(Option<u16>, aide::openapi::Response)> {
+        R::inferred_responses(ctx, operation)
+    }
+}
+
+/// Middleware assigning every request an id and writing one access log line per request.
+///
+/// The id is taken from the `X-Request-Id` header if the client sent a valid one, so requests can
+/// be traced across services, and is returned in the same header of the response. The access log
+/// uses the `access` target below the crate, so it can be filtered on its own.
+pub async fn access_log(mut request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let request_id = client_request_id(&request).unwrap_or_else(new_request_id);
+    request
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, request_id.clone());
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+
+    let params = Query::<HashMap<String, String>>::try_from_uri(request.uri())
+        .map(|Query(params)| RequestSummary::of_params(&params))
+        .unwrap_or_default();
+
+    let mut response = next.run(request).await;
+    let summary = response
+        .extensions_mut()
+        .remove::<RequestSummary>()
+        .unwrap_or(params);
+    response
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, request_id.clone());
+
+    tracing::info!(
+        target: concat!(env!("CARGO_CRATE_NAME"), "::access"),
+        request_id = request_id.to_str().unwrap_or_default(),
+        %method,
+        path,
+        route = response.extensions().get::<MatchedRoute>().map(|route| &*route.0),
+        status = response.status().as_u16(),
+        mode = summary.mode,
+        query_len = summary.query_len,
+        queries = summary.queries,
+        results = response.extensions().get::<ResultCount>().map(|count| count.0),
+        duration_ms = started.elapsed().as_secs_f64() * 1000.0,
+    );
+    response
+}
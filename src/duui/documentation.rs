@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use aide::axum::IntoApiResponse;
 use axum::extract::State;
 use axum::{http::StatusCode, Json};
@@ -11,6 +13,17 @@ use crate::AppState;
 pub(crate) struct Meta {
     number_of_geonames: usize,
     fst_size: usize,
+    /// Number of entries per ISO-3166 country code.
+    countries: BTreeMap<String, usize>,
+    /// Number of entries per GeoNames feature class.
+    feature_classes: BTreeMap<String, usize>,
+    /// Number of alternate names per language. Names without a language are counted under `""`.
+    alternate_name_languages: BTreeMap<String, usize>,
+    /// Build time of the index in seconds since the Unix epoch.
+    built: u64,
+    /// Date of the GeoNames dump, as given by `--timestamp`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    geonames_date: Option<String>,
 }
 
 #[derive(Serialize, JsonSchema)]
@@ -72,6 +85,8 @@ pub(crate) struct Documentation {
 }
 
 pub(crate) async fn v1_documentation(State(state): State<AppState>) -> impl IntoApiResponse {
+    let index = state.index.load();
+    let stats = &index.stats;
     (
         StatusCode::OK,
         Json(Documentation {
@@ -79,21 +94,29 @@ pub(crate) async fn v1_documentation(State(state): State<AppState>) -> impl Into
             version: env!("CARGO_PKG_VERSION"),
             implementation_lang: Some("Rust"),
             meta: Some(Meta {
-                number_of_geonames: state.searcher.geonames.len(),
-                fst_size: state.searcher.map.len(),
+                number_of_geonames: index.searcher.geonames.len(),
+                fst_size: index.searcher.map.len(),
+                countries: stats.countries.clone(),
+                feature_classes: stats.feature_classes.clone(),
+                alternate_name_languages: stats.alternate_name_languages.clone(),
+                built: stats.built,
+                geonames_date: state.timestamp.clone(),
             }),
             // docker_container_id: Some("".to_string()),
             parameters: Parameters {
                 annotation_type: Param::typ("String", "The annotation type to extract from the source document as a fully qualified class name."),
-                return_type: Param::choices("String", "The return type: either one or all matching GeoNames.", vec!["first", "all"]),
+                return_type: Param::choices("String", "The return type: the first, all, the best or the top n matching GeoNames.", vec!["first", "all", "best", "top_n"]),
                 mode: Param::choices(
                     "String",
                     "The search mode to use.",
                     vec![
                         "find",
+                        "regex",
                         "starts_with",
                         "fuzzy",
                         "levenshtein",
+                        "cascade",
+                        "full_text",
                     ],
                 ),
                 max_dist: Param::typ("int", "Positive number of maximum Levenshtein distance between the input string and the search results."),
@@ -103,7 +126,7 @@ pub(crate) async fn v1_documentation(State(state): State<AppState>) -> impl Into
                     "An optional dictionary of (each optional) feature_class (a GeoNames feature class, e.g. 'P' for populated place), feature_code (a GeoNames feature code, e.g. 'MT' for mountains), and country_code (a GeoNames country code, e.g. 'DE' for Germany)."
                 )
             },
-            capability: Capability { supported_languages: state.languages, reproducible: true },
+            capability: Capability { supported_languages: index.searcher.datasets.languages.clone(), reproducible: true },
             // implementation_specific: todo!(),
         }),
     )
@@ -1,29 +1,50 @@
-use std::time::{self, UNIX_EPOCH};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{self, Instant, UNIX_EPOCH};
 
 use aide::axum::IntoApiResponse;
 use aide::transform::TransformOperation;
 use axum::extract::State;
-use axum::{http::StatusCode, Json};
-use fst::automaton::{Str, Subsequence};
-use fst::Automaton;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::Json;
 use schemars::JsonSchema;
 use serde::Deserialize;
 use serde_aux::prelude::*;
+use tokio::sync::mpsc;
 
-use crate::geonames::data::GeoNamesSearchResultWithDist;
+use crate::diagnostics::RequestSummary;
+use crate::geonames::data::{
+    Entry, GeoNamesEntry, GeoNamesSearchResult, GeoNamesSearchResultWithDist,
+};
+use crate::geonames::deadline::Deadline;
+use crate::geonames::geo::{haversine_km, is_valid_coordinate};
 use crate::geonames::searcher::GeoNamesSearcher;
-use crate::routes::docs::DocResults;
-use crate::routes::filter_results;
-use crate::routes::find::RequestOptsFind;
-use crate::routes::fuzzy::RequestOptsFuzzy;
-use crate::routes::levenshtein::{levenshtein_inner, RequestOptsLevenshtein};
-use crate::routes::starts_with::RequestOptsStartsWith;
+use crate::routes::cascade::{RequestCascade, RequestOptsCascade};
+use crate::routes::error::{ApiError, ErrorCode};
+use crate::routes::find::{RequestFind, RequestOptsFind};
+use crate::routes::fuzzy::{RequestFuzzy, RequestOptsFuzzy};
+use crate::routes::levenshtein::{RequestLevenshtein, RequestOptsLevenshtein};
+use crate::routes::regex::{RequestOptsRegex, RequestRegex};
+use crate::routes::starts_with::{RequestOptsStartsWith, RequestStartsWith};
+use crate::routes::stream::{accepts, event, event_stream, EVENT_STREAM};
+use crate::routes::validate::{FieldErrors, StrictJson, Valid, Validate};
+use crate::routes::{
+    _schemars_default_filter, filter_predicate, run_blocking, run_ordered, FilterResults, Search,
+    FAILED_ITEMS_HEADER,
+};
 use crate::AppState;
 
 fn _default_entity() -> Entity {
     Entity {
-        reference: 0,
+        reference: Some(0),
         text: "Großer Feldberg".to_string(),
+        begin: Some(0),
+        end: Some(15),
+        language: None,
+        options: None,
+        existing: None,
     }
 }
 
@@ -34,14 +55,130 @@ fn _lua_number_to_int(lua_number: f64) -> u32 {
 #[derive(Deserialize, JsonSchema)]
 #[schemars(default = "_default_entity")]
 pub(crate) struct Entity {
-    #[serde(deserialize_with = "deserialize_number_from_string")]
-    pub reference: u32,
+    /// Reference of the entity, returned with its annotations. Optional if the entity has offsets,
+    /// which then identify its annotations instead.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub reference: Option<u32>,
     pub text: String,
+    /// Offsets of the entity in the document, e.g. the UIMA `begin` and `end` of the annotation,
+    /// returned with its annotations and used to resolve overlapping entities.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub begin: Option<usize>,
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub end: Option<usize>,
+    /// Language of the entity, e.g. `de`. Alternate names are only matched in this language and
+    /// the `language_fallback` languages.
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Search mode and options of this entity, overriding those of the request, e.g.
+    /// `{"mode": "levenshtein", "max_dist": 1}` for short names. Not available in `full_text`
+    /// mode.
+    #[serde(default)]
+    pub options: Option<SearchMode>,
+    /// GeoNames id the entity was annotated with by a previous run, if any. Such entities are
+    /// not searched again with `skip_existing`.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub existing: Option<u64>,
+}
+
+impl Entity {
+    fn span(&self) -> Option<Span> {
+        self.begin
+            .zip(self.end)
+            .map(|(begin, end)| Span { begin, end })
+    }
+}
+
+/// How to annotate entities whose offsets overlap, e.g. "Frankfurt" inside "Frankfurt am Main".
+#[derive(Default, Clone, Copy, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OverlapResolution {
+    /// Only annotate the longest of overlapping entities, or the first of equally long ones.
+    #[default]
+    LongestSpan,
+    /// Annotate all entities, even if their annotations overlap.
+    KeepAll,
+}
+
+impl OverlapResolution {
+    /// The entities to annotate, in their order. Entities without offsets are always kept.
+    fn apply(&self, queries: Vec<Entity>) -> Vec<Entity> {
+        if let Self::KeepAll = self {
+            return queries;
+        }
+        let mut longest_first: Vec<(usize, Span)> = queries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entity)| entity.span().map(|span| (i, span)))
+            .collect();
+        longest_first.sort_by_key(|(i, span)| (Reverse(span.end - span.begin), *i));
+
+        // The kept spans do not overlap, so they are ordered by both begin and end
+        let mut kept: BTreeMap<usize, usize> = BTreeMap::new();
+        let mut dropped = vec![false; queries.len()];
+        for (i, span) in longest_first {
+            let overlaps = kept
+                .range(..span.end.max(span.begin + 1))
+                .next_back()
+                .is_some_and(|(_, &end)| end > span.begin);
+            if overlaps {
+                dropped[i] = true;
+            } else {
+                kept.insert(span.begin, span.end);
+            }
+        }
+        queries
+            .into_iter()
+            .zip(dropped)
+            .filter_map(|(entity, dropped)| (!dropped).then_some(entity))
+            .collect()
+    }
+}
+
+/// Languages to restrict the alternate-name matches of the entities to.
+#[derive(Clone)]
+struct LanguageHints {
+    document: Option<String>,
+    fallback: Vec<String>,
+}
+
+impl LanguageHints {
+    /// The filter, restricted to the alternate names in the language of the entity, or of the
+    /// document if the entity has none, and the fallback languages.
+    fn restrict(
+        &self,
+        filter: &Option<FilterResults>,
+        language: Option<&str>,
+    ) -> Option<FilterResults> {
+        let Some(language) = language.or(self.document.as_deref()) else {
+            return filter.clone();
+        };
+        let languages = std::iter::once(language)
+            .chain(self.fallback.iter().map(String::as_str))
+            .map(str::to_string)
+            .collect();
+        Some(FilterResults {
+            languages: Some(languages),
+            ..filter.clone().unwrap_or_default()
+        })
+    }
+}
+
+/// Offsets of a match in the document text, in UTF-16 code units like UIMA annotations.
+#[derive(Clone, Copy, serde::Serialize, schemars::JsonSchema)]
+pub(crate) struct Span {
+    pub begin: usize,
+    pub end: usize,
 }
 
 #[derive(serde::Serialize, schemars::JsonSchema)]
 pub(crate) struct AnnotatedEntity {
-    pub reference: u32,
+    /// Reference of the annotated entity, absent in `full_text` mode.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<u32>,
+    /// Offsets of the match in `text` in `full_text` mode, or of the entity if it has any.
+    #[serde(flatten)]
+    pub span: Option<Span>,
     #[serde(flatten)]
     pub annotation: GeoNamesSearchResultWithDist,
 }
@@ -50,6 +187,7 @@ impl AnnotatedEntity {
     pub fn annotate(entity: &Entity, annotation: GeoNamesSearchResultWithDist) -> Self {
         Self {
             reference: entity.reference,
+            span: entity.span(),
             annotation,
         }
     }
@@ -59,53 +197,558 @@ impl AnnotatedEntity {
 #[serde(tag = "mode", rename_all = "snake_case")]
 pub(crate) enum SearchMode {
     Find(RequestOptsFind),
-    // Regex(RequestOptsRegex),
+    Regex(RequestOptsRegex),
     StartsWith(RequestOptsStartsWith),
     Fuzzy(RequestOptsFuzzy),
     Levenshtein(RequestOptsLevenshtein),
+    /// Try several modes in order, stopping at the first with enough results.
+    Cascade(RequestOptsCascade),
+    /// Scan `text` for the longest GeoNames keys instead of searching pre-extracted entities.
+    FullText(RequestOptsFullText),
 }
 
-#[derive(Deserialize, JsonSchema)]
+impl SearchMode {
+    /// Name of the mode, as given in the `mode` field.
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Find(_) => "find",
+            Self::Regex(_) => "regex",
+            Self::StartsWith(_) => "starts_with",
+            Self::Fuzzy(_) => "fuzzy",
+            Self::Levenshtein(_) => "levenshtein",
+            Self::Cascade(_) => "cascade",
+            Self::FullText(_) => "full_text",
+        }
+    }
+
+    /// Run the search of this mode for the entity, with the filter restricted to its language.
+    fn search(
+        &self,
+        searcher: &GeoNamesSearcher,
+        entity: &Entity,
+        hints: &LanguageHints,
+        deadline: Deadline,
+    ) -> Result<Vec<GeoNamesSearchResultWithDist>, ApiError> {
+        let language = entity.language.as_deref();
+        match self {
+            Self::Find(options) => search_entity(
+                searcher,
+                deadline,
+                RequestFind {
+                    query: entity.text.clone(),
+                    opts: RequestOptsFind {
+                        filter: hints.restrict(&options.filter, language),
+                        ..options.clone()
+                    },
+                },
+            ),
+            Self::Regex(options) => search_entity(
+                searcher,
+                deadline,
+                RequestRegex {
+                    regex: entity.text.clone(),
+                    opts: RequestOptsRegex {
+                        filter: hints.restrict(&options.filter, language),
+                        ..options.clone()
+                    },
+                },
+            ),
+            Self::StartsWith(options) => search_entity(
+                searcher,
+                deadline,
+                RequestStartsWith {
+                    query: entity.text.clone(),
+                    opts: RequestOptsStartsWith {
+                        filter: hints.restrict(&options.filter, language),
+                        ..options.clone()
+                    },
+                },
+            ),
+            Self::Fuzzy(options) => search_entity(
+                searcher,
+                deadline,
+                RequestFuzzy {
+                    query: entity.text.clone(),
+                    opts: RequestOptsFuzzy {
+                        filter: hints.restrict(&options.filter, language),
+                        ..options.clone()
+                    },
+                },
+            ),
+            Self::Levenshtein(options) => search_entity(
+                searcher,
+                deadline,
+                RequestLevenshtein {
+                    query: entity.text.clone(),
+                    opts: RequestOptsLevenshtein {
+                        filter: hints.restrict(&options.filter, language),
+                        ..options.clone()
+                    },
+                },
+            ),
+            Self::Cascade(options) => {
+                let mut opts = options.clone();
+                opts.filter = hints.restrict(&options.filter, language);
+                // Stages without a filter of their own use the restricted filter of the cascade
+                for stage in &mut opts.stages {
+                    let filter = stage.filter_mut();
+                    if filter.is_some() {
+                        *filter = hints.restrict(filter, language);
+                    }
+                }
+                search_entity(
+                    searcher,
+                    deadline,
+                    RequestCascade {
+                        query: entity.text.clone(),
+                        opts,
+                    },
+                )
+            }
+            Self::FullText(_) => Err(ApiError::new(
+                ErrorCode::InvalidRequest,
+                "The full_text mode cannot be used for a single entity",
+            )),
+        }
+    }
+}
+
+impl Validate for SearchMode {
+    fn check(&self, errors: &mut FieldErrors) {
+        match self {
+            Self::StartsWith(options) => options.check(errors),
+            Self::Fuzzy(options) => options.check(errors),
+            Self::Levenshtein(options) => options.check(errors),
+            Self::Cascade(options) => options.check(errors),
+            Self::Find(_) | Self::Regex(_) | Self::FullText(_) => {}
+        }
+    }
+}
+
+fn search_entity<R>(
+    searcher: &GeoNamesSearcher,
+    deadline: Deadline,
+    request: R,
+) -> Result<Vec<GeoNamesSearchResultWithDist>, ApiError>
+where
+    R: Search + Validate,
+    R::Item: Into<GeoNamesSearchResultWithDist>,
+{
+    request.validate()?;
+    let page = request.search(searcher, deadline)?;
+    Ok(page.results.into_iter().map(Into::into).collect())
+}
+
+/// Options of the `full_text` mode.
+#[derive(Clone, Deserialize, JsonSchema)]
+pub(crate) struct RequestOptsFullText {
+    #[schemars(
+        default = "_schemars_default_filter",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub filter: Option<FilterResults>,
+}
+
+#[derive(Default, Clone, Copy, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub(crate) enum ResultSelection {
+    /// The first candidate in the order of the search mode.
+    #[default]
     First,
     All,
+    /// At most this many candidates, in the order of the search mode, e.g. `{"top_n": 5}`.
+    TopN(usize),
+    /// The single best candidate: the one matching most of the `context`, then one in the focus,
+    /// then the most coherent with the other entities if `coherence` is set, then the closest to
+    /// the entity, with the most preferred match type, the most populous and finally the one
+    /// with the most prominent feature class.
+    Best,
 }
 
-impl Default for ResultSelection {
-    fn default() -> Self {
-        Self::First
-    }
+/// Order of the candidates by the `best` result selection, given with their position. The
+/// position only counts if the candidates are `ordered` by their coherence.
+fn compare_best(
+    (i, a): (usize, &GeoNamesSearchResultWithDist),
+    (j, b): (usize, &GeoNamesSearchResultWithDist),
+    preferences: &Preferences,
+    ordered: bool,
+) -> Ordering {
+    let Preferences { context, focus, .. } = preferences;
+    let in_context = |r: &GeoNamesSearchResultWithDist| Reverse(context.matches(r.entry()));
+    let in_focus = |r: &GeoNamesSearchResultWithDist| !focus.contains(r.entry());
+    let coherence = |i: usize| ordered.then_some(i);
+    let distance = |r: &GeoNamesSearchResultWithDist| r.distance().unwrap_or(0.0);
+    let prominence = |r: &GeoNamesSearchResultWithDist| {
+        let entry = r.entry();
+        (
+            r.key().map(|key| key.typ().ord()),
+            Reverse(entry.population),
+            entry.feature_class_priority(),
+        )
+    };
+    in_context(a)
+        .cmp(&in_context(b))
+        .then(in_focus(a).cmp(&in_focus(b)))
+        .then(coherence(i).cmp(&coherence(j)))
+        .then(distance(a).total_cmp(&distance(b)))
+        .then(prominence(a).cmp(&prominence(b)))
 }
 
 impl ResultSelection {
-    pub fn apply<T: Into<GeoNamesSearchResultWithDist>>(
+    /// The selected candidates, in their order after applying the focus.
+    fn select<T: Into<GeoNamesSearchResultWithDist>>(
         &self,
-        entity: &Entity,
         items: Vec<T>,
-    ) -> Option<Vec<AnnotatedEntity>> {
+        preferences: &Preferences,
+    ) -> Vec<GeoNamesSearchResultWithDist> {
+        let items = preferences
+            .focus
+            .apply(items.into_iter().map(Into::into).collect());
+        let items = items.into_iter();
         match self {
-            Self::First => items
-                .into_iter()
-                .next()
-                .map(|annotation| vec![AnnotatedEntity::annotate(entity, annotation.into())]),
-            Self::All => items
+            Self::First => items.take(1).collect(),
+            Self::All => items.collect(),
+            Self::TopN(n) => items.take(*n).collect(),
+            Self::Best => items
+                .enumerate()
+                .min_by(|(i, a), (j, b)| {
+                    compare_best((*i, a), (*j, b), preferences, preferences.coherence)
+                })
+                .map(|(_, best)| best)
                 .into_iter()
-                .map(|annotation| Some(AnnotatedEntity::annotate(entity, annotation.into())))
                 .collect(),
         }
     }
+
+    /// The annotations of a key found in the text.
+    fn apply_span(
+        &self,
+        span: Span,
+        results: Vec<GeoNamesSearchResult>,
+        preferences: &Preferences,
+    ) -> Vec<AnnotatedEntity> {
+        self.select(results, preferences)
+            .into_iter()
+            .map(|annotation| AnnotatedEntity {
+                reference: None,
+                span: Some(span),
+                annotation,
+            })
+            .collect()
+    }
+
+    fn apply<T: Into<GeoNamesSearchResultWithDist>>(
+        &self,
+        entity: &Entity,
+        items: Vec<T>,
+        preferences: &Preferences,
+    ) -> Vec<AnnotatedEntity> {
+        self.select(items, preferences)
+            .into_iter()
+            .map(|annotation| AnnotatedEntity::annotate(entity, annotation))
+            .collect()
+    }
+}
+
+/// What is known about the places a document is about, to prefer candidates with the `best`
+/// result selection.
+#[derive(Default, Deserialize, JsonSchema)]
+pub(crate) struct DocumentContext {
+    /// Country code of the document's places, e.g. `DE`.
+    #[serde(default)]
+    pub country_code: Option<String>,
+    /// Code of the first-order administrative division of the document's places, e.g. `05` for
+    /// Hessen in Germany.
+    #[serde(default)]
+    pub admin1_code: Option<String>,
+}
+
+impl DocumentContext {
+    /// Number of fields of the context that the entry matches. Administrative divisions only
+    /// match within the country of the context, as their codes are only unique per country.
+    fn matches(&self, entry: &GeoNamesEntry) -> usize {
+        let country = self
+            .country_code
+            .as_ref()
+            .is_none_or(|code| entry.country_code == *code);
+        let eq = |code: &Option<String>, value: &str| code.as_deref() == Some(value);
+        usize::from(eq(&self.country_code, &entry.country_code))
+            + usize::from(country && eq(&self.admin1_code, &entry.adm1))
+    }
+}
+
+/// A circular region a corpus is about, e.g. the area covered by a regional newspaper.
+#[derive(Clone, Deserialize, JsonSchema)]
+pub(crate) struct FocusRegion {
+    /// Latitude of the center in degrees.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lat: f64,
+    /// Longitude of the center in degrees.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lon: f64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub radius_km: f64,
+}
+
+/// Candidates to prefer, or to select exclusively if `restrict` is set. Candidates are in the
+/// focus if they are within the region and the country, whichever are given.
+#[derive(Default)]
+struct Focus {
+    region: Option<FocusRegion>,
+    country: Option<String>,
+    restrict: bool,
+}
+
+impl Focus {
+    fn contains(&self, entry: &GeoNamesEntry) -> bool {
+        self.country
+            .as_ref()
+            .is_none_or(|country| entry.country_code == *country)
+            && self.region.as_ref().is_none_or(|region| {
+                let (lat, lon) = (entry.latitude as f64, entry.longitude as f64);
+                haversine_km(region.lat, region.lon, lat, lon) <= region.radius_km
+            })
+    }
+
+    /// The candidates in the focus, followed by the others unless restricted to the focus. Keeps
+    /// the order of the candidates otherwise.
+    fn apply(
+        &self,
+        mut items: Vec<GeoNamesSearchResultWithDist>,
+    ) -> Vec<GeoNamesSearchResultWithDist> {
+        if self.region.is_none() && self.country.is_none() {
+            return items;
+        }
+        if self.restrict {
+            items.retain(|item| self.contains(item.entry()));
+        } else {
+            items.sort_by_key(|item| !self.contains(item.entry()));
+        }
+        items
+    }
+}
+
+/// How to select among the candidates of the entities of a request.
+struct Preferences {
+    context: DocumentContext,
+    focus: Focus,
+    /// The candidates are ordered by their coherence with the other entities.
+    coherence: bool,
+}
+
+/// Order the candidates of each entity by their coherence with the best candidates of the other
+/// entities: first those sharing their country and first-order division with the most other
+/// entities, then those closest to the other entities on average.
+fn order_by_coherence(
+    candidates: &mut [Vec<GeoNamesSearchResultWithDist>],
+    preferences: &Preferences,
+) {
+    let anchors: Vec<Option<GeoNamesEntry>> = candidates
+        .iter()
+        .map(|items| {
+            items
+                .iter()
+                .enumerate()
+                .min_by(|(i, a), (j, b)| compare_best((*i, a), (*j, b), preferences, false))
+                .map(|(_, best)| best.entry().clone())
+        })
+        .collect();
+    for (i, items) in candidates.iter_mut().enumerate() {
+        let others: Vec<&GeoNamesEntry> = anchors
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .filter_map(|(_, anchor)| anchor.as_ref())
+            .collect();
+        if items.len() < 2 || others.is_empty() {
+            continue;
+        }
+        let coherence = |entry: &GeoNamesEntry| {
+            let shared = others
+                .iter()
+                .filter(|other| {
+                    other.country_code == entry.country_code
+                        && !entry.adm1.is_empty()
+                        && other.adm1 == entry.adm1
+                })
+                .count();
+            let distance = others
+                .iter()
+                .map(|other| {
+                    haversine_km(
+                        entry.latitude as f64,
+                        entry.longitude as f64,
+                        other.latitude as f64,
+                        other.longitude as f64,
+                    )
+                })
+                .sum::<f64>()
+                / others.len() as f64;
+            (Reverse(shared), distance)
+        };
+        let mut ranked: Vec<_> = std::mem::take(items)
+            .into_iter()
+            .map(|item| (coherence(item.entry()), item))
+            .collect();
+        ranked.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+        *items = ranked.into_iter().map(|(_, item)| item).collect();
+    }
+}
+
+/// Wait for the candidates of all entities, then select among them with `selection`, preferring
+/// candidates coherent with the other entities. Failed entities are passed on unchanged.
+fn select_coherently(
+    mut annotations: mpsc::Receiver<Annotation>,
+    selection: ResultSelection,
+    preferences: Arc<Preferences>,
+) -> mpsc::Receiver<Annotation> {
+    let (sender, receiver) = mpsc::channel(1);
+    tokio::spawn(async move {
+        let mut collected = Vec::new();
+        while let Some(annotation) = annotations.recv().await {
+            collected.push(annotation);
+        }
+        let select = move || {
+            // Candidates of the same entity, or of the same span of the text, are adjacent
+            let mut groups: Vec<(usize, Option<u32>, Option<Span>, Vec<_>)> = Vec::new();
+            for (i, annotation) in collected.iter_mut().enumerate() {
+                let Ok(annotated) = &mut annotation.annotations else {
+                    continue;
+                };
+                for entity in std::mem::take(annotated) {
+                    let same = groups.last().is_some_and(|(j, reference, span, _)| {
+                        *j == i
+                            && *reference == entity.reference
+                            && span.map(|s| (s.begin, s.end))
+                                == entity.span.map(|s| (s.begin, s.end))
+                    });
+                    if !same {
+                        groups.push((i, entity.reference, entity.span, Vec::new()));
+                    }
+                    groups.last_mut().unwrap().3.push(entity.annotation);
+                }
+            }
+            let mut candidates: Vec<_> = groups
+                .iter_mut()
+                .map(|group| std::mem::take(&mut group.3))
+                .collect();
+            order_by_coherence(&mut candidates, &preferences);
+            for ((i, reference, span, _), items) in groups.into_iter().zip(candidates) {
+                if let Ok(annotated) = &mut collected[i].annotations {
+                    annotated.extend(selection.select(items, &preferences).into_iter().map(
+                        |annotation| AnnotatedEntity {
+                            reference,
+                            span,
+                            annotation,
+                        },
+                    ));
+                }
+            }
+            collected
+        };
+        let Ok(collected) = tokio::task::spawn_blocking(select).await else {
+            return;
+        };
+        for annotation in collected {
+            if sender.send(annotation).await.is_err() {
+                return;
+            }
+        }
+    });
+    receiver
 }
 
 #[derive(Deserialize, JsonSchema)]
 pub(crate) struct RequestProcess {
+    /// The entities to annotate. Not used in `full_text` mode.
+    #[serde(default)]
     pub queries: Vec<Entity>,
+    /// The document text, required in `full_text` mode.
+    #[serde(default)]
+    pub text: Option<String>,
+    /// Language of the document, e.g. `de`, for entities without a `language` and in `full_text`
+    /// mode.
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Languages whose alternate names are matched in addition to the language of an entity or
+    /// the document, in order of preference, e.g. `["", "en"]`.
+    #[serde(default)]
+    pub language_fallback: Vec<String>,
     #[schemars(default = "ResultSelection::default")]
     pub result_selection: ResultSelection,
+    /// Context of the document, used by the `best` result selection.
+    #[serde(default)]
+    pub context: DocumentContext,
+    /// Region the document is about, to prefer candidates within `radius_km` of the point.
+    #[serde(default)]
+    pub focus: Option<FocusRegion>,
+    /// Country the document is about, to prefer candidates in it, e.g. `DE`.
+    #[serde(default)]
+    pub focus_country: Option<String>,
+    /// Only select candidates in the `focus` region and the `focus_country`, instead of
+    /// preferring them.
+    #[serde(default)]
+    pub focus_restrict: bool,
+    /// Prefer candidates that are geographically coherent with the best candidates of the other
+    /// entities of the document: in the same first-order division, or close to them. Entities
+    /// are only annotated once the candidates of all entities are known.
+    #[serde(default)]
+    pub coherence: bool,
+    /// How to annotate entities with overlapping offsets. Defaults to `longest_span`.
+    #[serde(default)]
+    pub overlaps: OverlapResolution,
+    /// Leave entities with an `existing` annotation untouched instead of searching them again,
+    /// e.g. when re-processing a corpus. They still take precedence over overlapping entities.
+    #[serde(default)]
+    pub skip_existing: bool,
+    /// Abort at the first entity that fails. By default, failures are reported in `errors`.
+    #[serde(default)]
+    pub fail_fast: bool,
     #[serde(flatten)]
     pub options: SearchMode,
 }
 
+impl Validate for RequestProcess {
+    fn check(&self, errors: &mut FieldErrors) {
+        if matches!(self.options, SearchMode::FullText(_)) && self.text.is_none() {
+            errors.add("text", "is required in full_text mode");
+        }
+        self.options.check(errors);
+        for (i, entity) in self.queries.iter().enumerate() {
+            let field = format!("queries[{i}]");
+            if entity.begin.is_some() != entity.end.is_some() {
+                errors.add(&field, "needs both begin and end");
+            } else if entity.span().is_some_and(|span| span.begin > span.end) {
+                errors.add(&field, "ends before it begins");
+            } else if entity.reference.is_none() && entity.span().is_none() {
+                errors.add(&field, "needs a reference or begin and end");
+            }
+            match &entity.options {
+                Some(SearchMode::FullText(_)) => {
+                    errors.add(&format!("{field}.mode"), "must not be full_text");
+                }
+                Some(options) => {
+                    let mut nested = FieldErrors::default();
+                    options.check(&mut nested);
+                    errors.nested(&field, nested);
+                }
+                None => {}
+            }
+        }
+        if let Some(focus) = &self.focus {
+            if !is_valid_coordinate(focus.lat, focus.lon) {
+                errors.add("focus", "lat must be in [-90, 90] and lon in [-180, 180]");
+            }
+            if focus.radius_km <= 0.0 {
+                errors.add("focus", "radius_km must be positive");
+            }
+        }
+        if let ResultSelection::TopN(0) = self.result_selection {
+            errors.add("result_selection", "top_n must be at least 1");
+        }
+    }
+}
+
 #[derive(serde::Serialize, schemars::JsonSchema)]
 pub(crate) struct DocumentModification {
     pub user: String,
@@ -139,7 +782,7 @@ impl DocumentModification {
         if let Some(timestamp) = state.timestamp.as_ref() {
             comment.push(format!("GeoNames Date: {timestamp}"));
         }
-        if let Some(languages) = state.languages.as_ref() {
+        if let Some(languages) = state.searcher().datasets.languages.as_ref() {
             comment.push(format!(
                 "Languages: {}",
                 languages
@@ -156,129 +799,306 @@ impl DocumentModification {
 #[derive(serde::Serialize, schemars::JsonSchema)]
 pub(crate) struct Results {
     pub results: Vec<AnnotatedEntity>,
+    /// Entities that could not be annotated.
+    pub errors: Vec<EntityError>,
+    /// Time taken to annotate each entity that was not skipped, in the order of the entities.
+    pub timings: Vec<EntityTiming>,
+    /// Number of entities left untouched because of their `existing` annotation.
+    pub kept: usize,
+    pub modification: DocumentModification,
+}
+
+/// An entity that could not be annotated.
+#[derive(serde::Serialize, schemars::JsonSchema)]
+pub(crate) struct EntityError {
+    /// Reference of the entity, absent in `full_text` mode or if the entity has none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<u32>,
+    /// Offsets of the entity, if it has any.
+    #[serde(flatten)]
+    pub span: Option<Span>,
+    #[serde(flatten)]
+    pub error: ApiError,
+}
+
+/// Summary sent as the final `done` event of a streamed annotation.
+#[derive(serde::Serialize, schemars::JsonSchema)]
+pub(crate) struct ProcessSummary {
+    /// Number of entities that failed or were skipped.
+    pub failed: usize,
+    /// Number of entities left untouched because of their `existing` annotation.
+    pub kept: usize,
     pub modification: DocumentModification,
 }
 
+/// The annotations of a single entity, or why it could not be annotated.
+type Annotations = Result<Vec<AnnotatedEntity>, EntityError>;
+
+/// Time taken to annotate a single entity.
+#[derive(serde::Serialize, schemars::JsonSchema)]
+pub(crate) struct EntityTiming {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<u32>,
+    #[serde(flatten)]
+    pub span: Option<Span>,
+    pub duration_ms: f64,
+}
+
+/// The outcome of a single entity, timed unless it was skipped.
+struct Annotation {
+    annotations: Annotations,
+    timing: Option<EntityTiming>,
+}
+
+/// Annotate the entities concurrently on the blocking thread pool, sending the annotations of
+/// each entity in the order of the entities as soon as it and all earlier ones are done. Stops
+/// early if the receiver is dropped.
+/// Returns the number of entities left untouched with `skip_existing`, which are not annotated.
+fn run_process(state: &AppState, request: RequestProcess) -> (mpsc::Receiver<Annotation>, usize) {
+    let state = state.clone();
+    let mut queries = request.overlaps.apply(request.queries);
+    let total = queries.len();
+    if request.skip_existing {
+        queries.retain(|entity| entity.existing.is_none());
+    }
+    let kept = total - queries.len();
+    let selection = request.result_selection;
+    let fail_fast = request.fail_fast;
+    let preferences = Arc::new(Preferences {
+        context: request.context,
+        focus: Focus {
+            region: request.focus,
+            country: request.focus_country,
+            restrict: request.focus_restrict,
+        },
+        coherence: request.coherence,
+    });
+    // With coherence, all candidates are kept until those of all entities are known
+    let (selection, final_selection) = match request.coherence {
+        true => (ResultSelection::All, Some(selection)),
+        false => (selection, None),
+    };
+    let hints = LanguageHints {
+        document: request.language,
+        fallback: request.language_fallback,
+    };
+    let annotations = match request.options {
+        SearchMode::FullText(options) => {
+            let options = RequestOptsFullText {
+                filter: hints.restrict(&options.filter, None),
+            };
+            let text = request.text.unwrap_or_default();
+            process_text(state, text, options, selection, preferences.clone())
+        }
+        mode => process(
+            state,
+            queries,
+            move |searcher, entity, deadline| {
+                let mode = entity.options.as_ref().unwrap_or(&mode);
+                mode.search(searcher, entity, &hints, deadline)
+            },
+            selection,
+            preferences.clone(),
+            fail_fast,
+        ),
+    };
+    let annotations = match final_selection {
+        Some(selection) => select_coherently(annotations, selection, preferences),
+        None => annotations,
+    };
+    (annotations, kept)
+}
+
 pub(crate) async fn v1_process(
     State(state): State<AppState>,
-    Json(request): Json<RequestProcess>,
+    headers: HeaderMap,
+    Valid(StrictJson(request)): Valid<StrictJson<RequestProcess>>,
 ) -> impl IntoApiResponse {
+    let request_summary = match &request.text {
+        Some(text) => RequestSummary::mode(request.options.name()).with_query(text),
+        None => RequestSummary::mode(request.options.name()),
+    }
+    .with_queries(request.queries.len());
     let modification = DocumentModification::with_duui_commment(&state);
+    let fail_fast = request.fail_fast;
+    let (mut annotations, kept) = run_process(&state, request);
 
-    let results = match request.options {
-        SearchMode::Find(options) => process_find(
-            &state.searcher,
-            request.queries,
-            options,
-            request.result_selection,
-        ),
-        // SearchMode::Regex(options) => todo!(),
-        SearchMode::StartsWith(options) => process_starts_with(
-            &state.searcher,
-            request.queries,
-            options,
-            request.result_selection,
-        ),
-        SearchMode::Fuzzy(options) => process_fuzzy(
-            &state.searcher,
-            request.queries,
-            options,
-            request.result_selection,
-        ),
-        SearchMode::Levenshtein(options) => process_levenshtein(
-            &state.searcher,
-            request.queries,
-            options,
-            request.result_selection,
-        ),
+    if accepts(&headers, EVENT_STREAM) {
+        return request_summary.attach(event_stream(|events| async move {
+            let mut failed = 0;
+            while let Some(annotation) = annotations.recv().await {
+                let annotation = match annotation.annotations {
+                    Ok(results) => event("results", &results),
+                    Err(error) => {
+                        failed += 1;
+                        event("error", &error)
+                    }
+                };
+                if events.send(annotation).await.is_err() {
+                    return;
+                }
+            }
+            let summary = ProcessSummary {
+                failed,
+                kept,
+                modification,
+            };
+            let _ = events.send(event("done", &summary)).await;
+        }));
+    }
+
+    let mut results = Vec::new();
+    let mut errors = Vec::new();
+    let mut timings = Vec::new();
+    while let Some(annotation) = annotations.recv().await {
+        match annotation.annotations {
+            Ok(annotated) => results.extend(annotated),
+            Err(error) => errors.push(error),
+        }
+        timings.extend(annotation.timing);
+    }
+
+    let status = match errors.first() {
+        Some(entity) if fail_fast => entity.error.status(),
+        _ => StatusCode::OK,
     };
-    (
-        StatusCode::OK,
-        Json(Results {
-            results,
-            modification,
-        }),
+    request_summary.attach(
+        (
+            status,
+            [(FAILED_ITEMS_HEADER, errors.len().to_string())],
+            Json(Results {
+                results,
+                errors,
+                timings,
+                kept,
+                modification,
+            }),
+        )
+            .into_response(),
     )
 }
 
-fn process_find(
-    searcher: &GeoNamesSearcher,
+/// Annotate each entity with the results of its search, at most `batch_concurrency`
+/// entities at a time.
+///
+/// Invalid or failing entities are reported as errors and do not affect the others, unless
+/// `fail_fast` is set, in which case all entities after the first failure are skipped.
+fn process(
+    state: AppState,
     queries: Vec<Entity>,
-    options: RequestOptsFind,
-    return_type: ResultSelection,
-) -> Vec<AnnotatedEntity> {
-    queries
-        .iter()
-        .filter_map(|entity| {
-            return_type.apply(
-                entity,
-                filter_results(searcher.find(&entity.text), &options.filter),
-            )
-        })
-        .flatten()
-        .collect()
+    search: impl Fn(
+            &GeoNamesSearcher,
+            &Entity,
+            Deadline,
+        ) -> Result<Vec<GeoNamesSearchResultWithDist>, ApiError>
+        + Send
+        + Sync
+        + 'static,
+    selection: ResultSelection,
+    preferences: Arc<Preferences>,
+    fail_fast: bool,
+) -> mpsc::Receiver<Annotation> {
+    let concurrency = state.batch_concurrency;
+    let search = Arc::new(search);
+    run_ordered(
+        queries,
+        concurrency,
+        fail_fast,
+        move |entity| {
+            let state = state.clone();
+            let search = search.clone();
+            let preferences = preferences.clone();
+            async move {
+                let (reference, span) = (entity.reference, entity.span());
+                let started = Instant::now();
+                let annotate = move |searcher: &GeoNamesSearcher, deadline: Deadline| {
+                    let results = search(searcher, &entity, deadline)?;
+                    Ok(selection.apply(&entity, results, &preferences))
+                };
+                let annotations =
+                    run_blocking(&state, annotate)
+                        .await
+                        .map_err(|error| EntityError {
+                            reference,
+                            span,
+                            error,
+                        });
+                Annotation {
+                    annotations,
+                    timing: Some(EntityTiming {
+                        reference,
+                        span,
+                        duration_ms: started.elapsed().as_secs_f64() * 1000.0,
+                    }),
+                }
+            }
+        },
+        |annotation| annotation.annotations.is_err(),
+        |entity| Annotation {
+            annotations: Err(EntityError {
+                reference: entity.reference,
+                span: entity.span(),
+                error: ApiError::new(ErrorCode::Skipped, "Skipped after an earlier entity failed"),
+            }),
+            timing: None,
+        },
+    )
 }
 
-fn process_starts_with(
-    searcher: &GeoNamesSearcher,
-    queries: Vec<Entity>,
-    options: RequestOptsStartsWith,
-    return_type: ResultSelection,
-) -> Vec<AnnotatedEntity> {
-    queries
-        .iter()
-        .filter_map(|entity| {
-            let query = Str::new(&entity.text).starts_with();
-            let results = searcher.search_with_dist(query, &entity.text, Some(options.max_dist));
-            let results = filter_results(results, &options.filter);
-            return_type.apply(entity, results)
-        })
-        .flatten()
-        .collect()
+/// Annotate the keys found in the text, on the blocking thread pool.
+fn process_text(
+    state: AppState,
+    text: String,
+    options: RequestOptsFullText,
+    selection: ResultSelection,
+    preferences: Arc<Preferences>,
+) -> mpsc::Receiver<Annotation> {
+    let (sender, receiver) = mpsc::channel(1);
+    tokio::spawn(async move {
+        let scan = move |searcher: &GeoNamesSearcher, _: Deadline| {
+            let matches = searcher.scan(&text, filter_predicate(&options.filter));
+            // Matches are in text order, so the UTF-16 offsets are counted incrementally
+            let (mut bytes, mut units) = (0, 0);
+            let mut utf16 = |offset: usize| {
+                units += text[bytes..offset].encode_utf16().count();
+                bytes = offset;
+                units
+            };
+            Ok(matches
+                .into_iter()
+                .flat_map(|found| {
+                    let span = Span {
+                        begin: utf16(found.start),
+                        end: utf16(found.end),
+                    };
+                    selection.apply_span(span, found.results, &preferences)
+                })
+                .collect())
+        };
+        let annotations = run_blocking(&state, scan)
+            .await
+            .map_err(|error| EntityError {
+                reference: None,
+                span: None,
+                error,
+            });
+        let _ = sender
+            .send(Annotation {
+                annotations,
+                timing: None,
+            })
+            .await;
+    });
+    receiver
 }
 
-fn process_fuzzy(
-    searcher: &GeoNamesSearcher,
-    queries: Vec<Entity>,
-    options: RequestOptsFuzzy,
-    return_type: ResultSelection,
-) -> Vec<AnnotatedEntity> {
-    queries
-        .iter()
-        .filter_map(|entity| {
-            let query = Subsequence::new(&entity.text);
-            let results = searcher.search_with_dist(query, &entity.text, Some(options.max_dist));
-            let results = filter_results(results, &options.filter);
-            return_type.apply(entity, results)
+pub(crate) fn v1_process_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Tag GeoNames in a list of entities given as offsets and covered text. Of entities with overlapping offsets, only the longest is annotated unless <code>overlaps</code> is <code>keep_all</code>. With <code>skip_existing</code>, entities with an <code>existing</code> annotation from a previous run are left untouched and only counted in <code>kept</code>. With <code>coherence</code>, the candidates of each entity are ordered by how close they are to the best candidates of the other entities before selecting among them. In <code>regex</code> mode, the covered text of each entity is used as the regular expression. In <code>cascade</code> mode, the <code>stages</code> are tried in order until one has at least <code>min_results</code> results, as with <code>/geonames/cascade</code>. An entity can override the mode and options of the request with its own <code>options</code>, e.g. to search short names with <code>levenshtein</code>.<br>In <code>full_text</code> mode, <code>queries</code> are not needed: the document <code>text</code> is scanned for the longest GeoNames keys starting at each word instead, and each annotation carries the <code>begin</code> and <code>end</code> offsets of its key in UTF-16 code units.<br>Entities are searched concurrently, up to <code>--batch-concurrency</code> at a time, and the time taken by each entity is listed in <code>timings</code>. The search timeout applies to each entity on its own.<br>Entities that fail are listed in <code>errors</code> with an HTTP-style status, and their number is reported in the <code>x-failed-items</code> header.<br>With <code>Accept: text/event-stream</code>, the annotations of each entity are sent as a <code>results</code> event as soon as it is done, failing entities as an <code>error</code> event, followed by a final <code>done</code> event with the number of failed entities and the document modification.")
+        .response::<200, Json<Results>>()
+        .response_with::<422, ApiError, _>(|t| {
+            t.description("The request could not be parsed, has unknown fields or invalid options, or the text is missing in full_text mode. Invalid fields are listed in the details.")
         })
-        .flatten()
-        .collect()
-}
-
-fn process_levenshtein(
-    searcher: &GeoNamesSearcher,
-    queries: Vec<Entity>,
-    options: RequestOptsLevenshtein,
-    return_type: ResultSelection,
-) -> Vec<AnnotatedEntity> {
-    queries
-        .iter()
-        .filter_map(|entity| {
-            levenshtein_inner(
-                searcher,
-                &entity.text,
-                options.state_limit,
-                options.max_dist,
-                &options.filter,
-            )
-            .ok()
-            .and_then(|results| return_type.apply(entity, results))
+        .default_response_with::<Json<Results>, _>(|t| {
+            t.description("An entity failed and `fail_fast` was set. The status is the `status` of the first error, e.g. 400 for an invalid pattern or 504 if the search exceeded the search timeout.")
         })
-        .flatten()
-        .collect()
-}
-
-pub(crate) fn v1_process_docs(op: TransformOperation) -> TransformOperation {
-    op.description("Tag GeoNames in a list of entities given as offsets and covered text.")
-        .response::<200, Json<DocResults<Vec<GeoNamesSearchResultWithDist>>>>()
 }
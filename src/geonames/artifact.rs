@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context};
+use fst::Map;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_bytes::{ByteBuf, Bytes};
+use sha2::{Digest, Sha256};
+
+use crate::geonames::completions::CompletionTable;
+use crate::geonames::data::MatchType;
+use crate::geonames::hierarchy::AdminIndex;
+use crate::geonames::localized::LocalizedNames;
+use crate::geonames::ngrams::NgramIndex;
+use crate::geonames::score::ScoreWeights;
+use crate::geonames::searcher::{BuildReport, Datasets, GeoNamesSearcher};
+use crate::geonames::shapes::CountryShapes;
+use crate::geonames::spatial::SpatialIndex;
+use crate::geonames::store::EntryStore;
+use crate::geonames::tokens::TokenIndex;
+use crate::geonames::utils::{is_remote, STDIN_PATH};
+
+/// Leading bytes of every index artifact.
+const MAGIC: &[u8; 6] = b"GNFST\0";
+
+/// Version of the artifact layout, bumped whenever the serialized index changes incompatibly.
+pub const FORMAT_VERSION: u32 = 11;
+
+/// A source file the index was built from.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SourceFile {
+    pub path: String,
+    /// Size in bytes, `None` for stdin and remote sources.
+    pub size: Option<u64>,
+    /// Hex-encoded SHA-256 of the file, `None` for stdin and remote sources.
+    pub sha256: Option<String>,
+}
+
+impl SourceFile {
+    fn new(path: &str) -> Result<Self, anyhow::Error> {
+        if path == STDIN_PATH || is_remote(path) {
+            return Ok(Self {
+                path: path.to_string(),
+                size: None,
+                sha256: None,
+            });
+        }
+        let mut file = File::open(path).with_context(|| format!("Could not open {path:?}"))?;
+        let mut hasher = Sha256::new();
+        let size = std::io::copy(&mut file, &mut hasher)?;
+        Ok(Self {
+            path: path.to_string(),
+            size: Some(size),
+            sha256: Some(format!("{:x}", hasher.finalize())),
+        })
+    }
+}
+
+/// Provenance and integrity information stored in front of the index data.
+///
+/// The manifest is also written next to the artifact as `<artifact>.manifest.json`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct IndexManifest {
+    pub format_version: u32,
+    /// Version of the `geonames-fst` crate that built the artifact.
+    pub crate_version: String,
+    /// Build time in seconds since the Unix epoch.
+    pub created: u64,
+    pub sources: Vec<SourceFile>,
+    /// Languages included from the alternate names, `None` for all languages.
+    pub languages: Option<Vec<String>>,
+    /// Size of the serialized index data in bytes.
+    pub data_size: u64,
+    /// Hex-encoded SHA-256 of the serialized index data.
+    pub data_sha256: String,
+}
+
+impl IndexManifest {
+    /// Check that the artifact was written by a compatible version of this crate.
+    ///
+    /// Versions are compatible if they agree in the left-most non-zero component, as in Cargo.
+    fn validate_version(&self) -> Result<(), anyhow::Error> {
+        fn compatible(version: &str) -> Vec<&str> {
+            let parts: Vec<&str> = version.split('.').collect();
+            match parts.iter().position(|part| *part != "0") {
+                Some(i) => parts[..=i].to_vec(),
+                None => parts,
+            }
+        }
+        let current = env!("CARGO_PKG_VERSION");
+        if compatible(&self.crate_version) != compatible(current) {
+            return Err(anyhow!(
+                "Index artifact was built by version {}, which is incompatible with version {current}",
+                self.crate_version
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct IndexDataRef<'a> {
+    fst: &'a Bytes,
+    geonames: &'a EntryStore,
+    search_matches: &'a Vec<Vec<MatchType>>,
+    completions: &'a Option<CompletionTable>,
+    ngrams: &'a Option<NgramIndex>,
+    tokens: &'a Option<TokenIndex>,
+    shapes: &'a Option<CountryShapes>,
+    admin: &'a AdminIndex,
+    localized: &'a LocalizedNames,
+    entry_keys: &'a HashMap<u64, Vec<u32>>,
+    datasets: &'a Datasets,
+    report: &'a BuildReport,
+}
+
+#[derive(Deserialize)]
+struct IndexData {
+    fst: ByteBuf,
+    geonames: EntryStore,
+    search_matches: Vec<Vec<MatchType>>,
+    completions: Option<CompletionTable>,
+    ngrams: Option<NgramIndex>,
+    tokens: Option<TokenIndex>,
+    shapes: Option<CountryShapes>,
+    admin: AdminIndex,
+    localized: LocalizedNames,
+    entry_keys: HashMap<u64, Vec<u32>>,
+    datasets: Datasets,
+    report: BuildReport,
+}
+
+/// Counts and hashes the index data read or written through it, so that the data is checked
+/// without holding all of it in memory.
+struct Checksum<T> {
+    inner: T,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<T> Checksum<T> {
+    fn new(inner: T) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    /// Size and hex-encoded SHA-256 of the data that passed through.
+    fn finish(self) -> (u64, String) {
+        (self.size, format!("{:x}", self.hasher.finalize()))
+    }
+}
+
+impl<R: Read> Read for Checksum<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Checksum<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Path of the JSON manifest written alongside an artifact.
+pub fn manifest_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".manifest.json");
+    PathBuf::from(name)
+}
+
+impl GeoNamesSearcher {
+    /// Write the index to a standalone artifact that can be served without the input files.
+    ///
+    /// The artifact consists of a magic header, the format version, and the CBOR-encoded
+    /// `IndexManifest` followed by the CBOR-encoded index data.
+    pub fn write_artifact(&self, path: &Path) -> Result<IndexManifest, anyhow::Error> {
+        let data = IndexDataRef {
+            fst: Bytes::new(self.map.as_fst().as_bytes()),
+            geonames: &self.geonames,
+            search_matches: &self.search_matches,
+            completions: &self.completions,
+            ngrams: &self.ngrams,
+            tokens: &self.tokens,
+            shapes: &self.shapes,
+            admin: &self.admin,
+            localized: &self.localized,
+            entry_keys: &self.entry_keys,
+            datasets: &self.datasets,
+            report: &self.report,
+        };
+        // The manifest in front of the data holds its size and checksum, so the data is serialized
+        // once to compute them and once more into the artifact
+        let mut checksum = Checksum::new(std::io::sink());
+        ciborium::into_writer(&data, &mut checksum)?;
+        let (data_size, data_sha256) = checksum.finish();
+
+        let sources = self
+            .datasets
+            .geonames
+            .iter()
+            .chain(self.datasets.alternate.iter())
+            .chain(self.datasets.synonyms.iter())
+            .chain(self.datasets.shapes.iter())
+            // Skip optional files that could not be ingested
+            .filter(|source| !self.report.warnings.iter().any(|w| &w.path == *source))
+            .map(|source| SourceFile::new(source))
+            .collect::<Result<Vec<_>, _>>()?;
+        let manifest = IndexManifest {
+            format_version: FORMAT_VERSION,
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            created: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            sources,
+            languages: self.datasets.languages.clone(),
+            data_size,
+            data_sha256,
+        };
+
+        let file = File::create(path).with_context(|| format!("Could not create {path:?}"))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        ciborium::into_writer(&manifest, &mut writer)?;
+        let mut checksum = Checksum::new(&mut writer);
+        ciborium::into_writer(&data, &mut checksum)?;
+        if checksum.finish() != (manifest.data_size, manifest.data_sha256.clone()) {
+            return Err(anyhow!(
+                "Index data written to {path:?} does not match its manifest"
+            ));
+        }
+        writer.flush()?;
+
+        let manifest_path = manifest_path(path);
+        let file = File::create(&manifest_path)
+            .with_context(|| format!("Could not create {manifest_path:?}"))?;
+        serde_json::to_writer_pretty(file, &manifest)?;
+
+        Ok(manifest)
+    }
+
+    /// Load an index previously written with `write_artifact`.
+    ///
+    /// Refuses artifacts written by an incompatible version and artifacts whose index data does
+    /// not match the checksum in their manifest.
+    pub fn load_artifact(path: &Path) -> Result<GeoNamesSearcher, anyhow::Error> {
+        let file = File::open(path).with_context(|| format!("Could not open {path:?}"))?;
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0u8; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(anyhow!("{path:?} is not a geonames-fst index artifact"));
+        }
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != FORMAT_VERSION {
+            return Err(anyhow!(
+                "{path:?} has artifact format version {version}, but this binary supports version {FORMAT_VERSION}"
+            ));
+        }
+
+        let manifest: IndexManifest =
+            ciborium::from_reader(&mut reader).context("Could not read artifact manifest")?;
+        manifest.validate_version()?;
+
+        // The data is checked while it is decoded. Its size and checksum are verified before the
+        // result of decoding is used, so that corrupted data is reported as such
+        let mut data_reader = Checksum::new(reader.by_ref().take(manifest.data_size));
+        let data: Result<IndexData, _> = ciborium::from_reader(&mut data_reader);
+        std::io::copy(&mut data_reader, &mut std::io::sink())?;
+        let (size, checksum) = data_reader.finish();
+        let size = size + std::io::copy(&mut reader, &mut std::io::sink())?;
+        if size != manifest.data_size {
+            return Err(anyhow!(
+                "{path:?} is corrupted: expected {} bytes of index data, found {size}",
+                manifest.data_size,
+            ));
+        }
+        if checksum != manifest.data_sha256 {
+            return Err(anyhow!(
+                "{path:?} is corrupted: index data checksum {checksum} does not match the manifest"
+            ));
+        }
+
+        let data = data.context("Could not read artifact index data")?;
+        let spatial = SpatialIndex::build(&data.geonames);
+
+        Ok(GeoNamesSearcher {
+            map: Map::new(data.fst.into_vec())?,
+            geonames: data.geonames,
+            search_matches: data.search_matches,
+            completions: data.completions,
+            ngrams: data.ngrams,
+            tokens: data.tokens,
+            shapes: data.shapes,
+            admin: data.admin,
+            spatial,
+            localized: data.localized,
+            entry_keys: data.entry_keys,
+            score_weights: ScoreWeights::default(),
+            max_results: None,
+            datasets: data.datasets,
+            report: data.report,
+            manifest: Some(manifest),
+            overlay: RwLock::default(),
+        })
+    }
+}
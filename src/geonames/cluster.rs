@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::geonames::data::GeoNamesEntry;
+use crate::geonames::geo::EARTH_RADIUS_KM;
+
+/// A group of nearby entries, for displaying many results on a map.
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct GeoCluster {
+    /// Mean latitude of the entries in the cluster.
+    pub latitude: f64,
+    /// Mean longitude of the entries in the cluster.
+    pub longitude: f64,
+    /// Number of entries in the cluster.
+    pub count: usize,
+    /// GeoNames id of the most populous entry in the cluster, e.g. to label it.
+    pub id: u64,
+    /// Name of the most populous entry in the cluster.
+    pub name: String,
+}
+
+/// Group the entries into the cells of a grid of roughly `grid_size_km` squared cells, largest
+/// cluster first.
+///
+/// Rows of cells span the same latitude everywhere, while the cells of a row are widened towards
+/// the poles so that they stay about as wide as high.
+pub fn cluster<'a>(
+    entries: impl Iterator<Item = &'a GeoNamesEntry>,
+    grid_size_km: f64,
+) -> Vec<GeoCluster> {
+    let cell_deg = (grid_size_km / EARTH_RADIUS_KM).to_degrees();
+    let mut cells: HashMap<(i64, i64), (f64, f64, usize, &GeoNamesEntry)> = HashMap::new();
+    for entry in entries {
+        let (lat, lon) = (entry.latitude as f64, entry.longitude as f64);
+        if lat.is_nan() || lon.is_nan() {
+            continue;
+        }
+        let row = ((lat + 90.0) / cell_deg).floor();
+        let row_lat = (row + 0.5) * cell_deg - 90.0;
+        let cell_lon_deg = (cell_deg / row_lat.to_radians().cos().max(1e-6)).min(360.0);
+        let col = ((lon + 180.0) / cell_lon_deg).floor();
+        cells
+            .entry((row as i64, col as i64))
+            .and_modify(|(lat_sum, lon_sum, count, top)| {
+                *lat_sum += lat;
+                *lon_sum += lon;
+                *count += 1;
+                if (entry.population, std::cmp::Reverse(entry.id))
+                    > (top.population, std::cmp::Reverse(top.id))
+                {
+                    *top = entry;
+                }
+            })
+            .or_insert((lat, lon, 1, entry));
+    }
+
+    let mut clusters: Vec<GeoCluster> = cells
+        .into_values()
+        .map(|(lat_sum, lon_sum, count, top)| GeoCluster {
+            latitude: lat_sum / count as f64,
+            longitude: lon_sum / count as f64,
+            count,
+            id: top.id,
+            name: top.name.clone(),
+        })
+        .collect();
+    clusters.sort_by(|a, b| b.count.cmp(&a.count).then(a.id.cmp(&b.id)));
+    clusters
+}
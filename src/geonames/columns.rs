@@ -0,0 +1,106 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+/// Column indices of the fields of a gazetteer TSV file.
+///
+/// Defaults to the 19-column layout of the GeoNames `geoname` table. Optional columns that are
+/// not present in a custom gazetteer can be unset by leaving their index empty, e.g. `elevation=`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnMapping {
+    pub id: usize,
+    pub name: usize,
+    /// Falls back to `name` if unset.
+    pub ascii_name: Option<usize>,
+    pub latitude: Option<usize>,
+    pub longitude: Option<usize>,
+    pub feature_class: Option<usize>,
+    pub feature_code: Option<usize>,
+    pub country_code: Option<usize>,
+    pub adm1: Option<usize>,
+    pub adm2: Option<usize>,
+    pub adm3: Option<usize>,
+    pub adm4: Option<usize>,
+    pub population: Option<usize>,
+    pub elevation: Option<usize>,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            name: 1,
+            ascii_name: Some(2),
+            latitude: Some(4),
+            longitude: Some(5),
+            feature_class: Some(6),
+            feature_code: Some(7),
+            country_code: Some(8),
+            adm1: Some(10),
+            adm2: Some(11),
+            adm3: Some(12),
+            adm4: Some(13),
+            population: Some(14),
+            elevation: Some(15),
+        }
+    }
+}
+
+impl FromStr for ColumnMapping {
+    type Err = anyhow::Error;
+
+    /// Parse a mapping like `id=0,name=2,lat=3,lon=4`, starting from the GeoNames layout.
+    ///
+    /// If the spec points to a file, the mapping is read from that file instead, with one
+    /// `field=index` pair per line (or comma-separated). Lines starting with `#` are ignored.
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let spec = if Path::new(spec).is_file() {
+            std::fs::read_to_string(spec)
+                .map_err(|e| anyhow!("Failed to read column mapping from file {spec}: {e}"))?
+        } else {
+            spec.to_string()
+        };
+
+        let mut mapping = ColumnMapping::default();
+        let pairs = spec
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with('#'))
+            .flat_map(|line| line.split(','))
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty());
+        for pair in pairs {
+            let (field, index) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid column mapping '{pair}', expected field=index"))?;
+            let index: Option<usize> = match index.trim() {
+                "" => None,
+                index => Some(
+                    index
+                        .parse()
+                        .map_err(|e| anyhow!("Invalid column index for '{field}': {e}"))?,
+                ),
+            };
+            let required = || index.ok_or_else(|| anyhow!("The '{field}' column is required"));
+            match field.trim() {
+                "id" | "geonameid" => mapping.id = required()?,
+                "name" => mapping.name = required()?,
+                "ascii_name" | "asciiname" => mapping.ascii_name = index,
+                "latitude" | "lat" => mapping.latitude = index,
+                "longitude" | "lon" => mapping.longitude = index,
+                "feature_class" => mapping.feature_class = index,
+                "feature_code" => mapping.feature_code = index,
+                "country_code" => mapping.country_code = index,
+                "adm1" | "admin1_code" => mapping.adm1 = index,
+                "adm2" | "admin2_code" => mapping.adm2 = index,
+                "adm3" | "admin3_code" => mapping.adm3 = index,
+                "adm4" | "admin4_code" => mapping.adm4 = index,
+                "population" => mapping.population = index,
+                "elevation" => mapping.elevation = index,
+                unknown => Err(anyhow!("Unknown column '{unknown}' in column mapping"))?,
+            }
+        }
+        Ok(mapping)
+    }
+}
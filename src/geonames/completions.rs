@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::geonames::data::{GeoNamesEntry, MatchType};
+use crate::geonames::store::EntryStore;
+
+/// A precomputed completion of a short prefix: the matching key and its match.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Completion {
+    pub key: String,
+    pub typ: MatchType,
+    rank: CompletionRank,
+}
+
+/// Side table holding the top-k completions for every prefix up to `max_prefix_len` characters.
+///
+/// Short prefixes like "Sa" match huge key ranges in the FST, so we rank them once at build time
+/// and answer per-keystroke queries with a single hash map lookup.
+#[derive(Serialize, Deserialize)]
+pub struct CompletionTable {
+    pub max_prefix_len: usize,
+    pub top_k: usize,
+    table: HashMap<String, Vec<Completion>>,
+}
+
+/// Rank of a completion, higher is better.
+pub(crate) type CompletionRank = (u64, u8, u8);
+
+/// Orders entries by population first, then by the prominence of their feature class, preferring
+/// main names over alternate names on ties.
+pub(crate) fn completion_rank(typ: &MatchType, entry: &GeoNamesEntry) -> CompletionRank {
+    (
+        entry.population,
+        u8::MAX - entry.feature_class_priority(),
+        u8::MAX - typ.ord(),
+    )
+}
+
+impl CompletionTable {
+    pub fn build<'a>(
+        terms: impl Iterator<Item = (&'a str, &'a Vec<MatchType>)>,
+        geonames: &EntryStore,
+        max_prefix_len: usize,
+        top_k: usize,
+    ) -> Self {
+        let mut table: HashMap<String, Vec<Completion>> = HashMap::new();
+        for (term, matches) in terms {
+            for typ in matches {
+                let Some(entry) = geonames.get(&typ.id()) else {
+                    continue;
+                };
+                let rank = completion_rank(typ, entry);
+                let ends = term.char_indices().map(|(i, _)| i).skip(1);
+                for end in ends.chain(std::iter::once(term.len())).take(max_prefix_len) {
+                    let candidates = table.entry(term[..end].to_string()).or_default();
+                    Self::insert(candidates, term, typ, rank, top_k);
+                }
+            }
+        }
+        table.shrink_to_fit();
+        Self {
+            max_prefix_len,
+            top_k,
+            table,
+        }
+    }
+
+    /// Insert a candidate into a list sorted by descending rank, keeping one match per GeoNames id.
+    pub(crate) fn insert(
+        candidates: &mut Vec<Completion>,
+        term: &str,
+        typ: &MatchType,
+        rank: CompletionRank,
+        top_k: usize,
+    ) {
+        if let Some(pos) = candidates.iter().position(|c| c.typ.id() == typ.id()) {
+            if candidates[pos].rank >= rank {
+                return;
+            }
+            candidates.remove(pos);
+        } else if candidates.len() >= top_k && candidates.last().is_some_and(|c| c.rank >= rank) {
+            return;
+        }
+        let pos = candidates.partition_point(|c| c.rank >= rank);
+        candidates.insert(
+            pos,
+            Completion {
+                key: term.to_string(),
+                typ: typ.clone(),
+                rank,
+            },
+        );
+        candidates.truncate(top_k);
+    }
+
+    /// Look up the precomputed completions for a prefix. Returns `None` if the prefix is longer than
+    /// the precomputed range, in which case the caller has to fall back to the FST.
+    pub fn get(&self, prefix: &str) -> Option<&[Completion]> {
+        if prefix.chars().count() > self.max_prefix_len {
+            return None;
+        }
+        Some(
+            self.table
+                .get(prefix)
+                .map(Vec::as_slice)
+                .unwrap_or_default(),
+        )
+    }
+
+    pub fn number_of_prefixes(&self) -> usize {
+        self.table.len()
+    }
+}
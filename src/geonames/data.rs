@@ -1,7 +1,11 @@
 use schemars::JsonSchema;
-use serde::Serialize;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
 
-#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+use crate::geonames::hierarchy::Hierarchy;
+use crate::geonames::symbol::Symbol;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct GeoNamesEntry {
     /// Unique identifier of the record
     pub id: u64,
@@ -12,29 +16,221 @@ pub struct GeoNamesEntry {
     /// Longitude of the GeoNames record
     pub longitude: f32,
     /// Feature class of the GeoNames record
-    pub feature_class: String,
+    pub feature_class: Symbol,
     /// Feature code of the GeoNames record
-    pub feature_code: String,
+    pub feature_code: Symbol,
     /// Country code of the GeoNames record
-    pub country_code: String,
+    pub country_code: Symbol,
     /// Administrative divisions of the GeoNames record, some of which may be empty.
     pub adm1: String,
     pub adm2: String,
     pub adm3: String,
     pub adm4: String,
+    /// Population of the GeoNames record, `0` if unknown.
+    pub population: u64,
     /// Elevation of the GeoNames record, if applicable.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub elevation: Option<i16>,
+    /// Wikipedia article of the GeoNames record, from the `link` alternate names.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wikipedia: Option<String>,
+    /// Wikidata item of the GeoNames record, e.g. `Q1794`, from the `wkdt` alternate names.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wikidata: Option<String>,
+}
+
+impl GeoNamesEntry {
+    /// Priority of the feature class when ranking by prominence, lower is more prominent:
+    /// administrative areas and populated places first, then regions and terrain features.
+    pub fn feature_class_priority(&self) -> u8 {
+        match self.feature_class.as_str() {
+            "A" => 0,
+            "P" => 1,
+            "L" => 2,
+            "T" => 3,
+            "H" => 4,
+            "V" => 5,
+            "S" => 6,
+            "R" => 7,
+            "U" => 8,
+            _ => u8::MAX,
+        }
+    }
+}
+
+/// A field of `GeoNamesEntry`, to return only some of the fields of each entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryField {
+    Id,
+    Name,
+    Latitude,
+    Longitude,
+    FeatureClass,
+    FeatureCode,
+    CountryCode,
+    Adm1,
+    Adm2,
+    Adm3,
+    Adm4,
+    Population,
+    Elevation,
+    Wikipedia,
+    Wikidata,
+}
+
+impl EntryField {
+    /// All fields, in the order of `GeoNamesEntry`.
+    const ALL: [EntryField; 15] = [
+        Self::Id,
+        Self::Name,
+        Self::Latitude,
+        Self::Longitude,
+        Self::FeatureClass,
+        Self::FeatureCode,
+        Self::CountryCode,
+        Self::Adm1,
+        Self::Adm2,
+        Self::Adm3,
+        Self::Adm4,
+        Self::Population,
+        Self::Elevation,
+        Self::Wikipedia,
+        Self::Wikidata,
+    ];
+
+    /// Name of the field in the serialized entry.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Id => "id",
+            Self::Name => "name",
+            Self::Latitude => "latitude",
+            Self::Longitude => "longitude",
+            Self::FeatureClass => "feature_class",
+            Self::FeatureCode => "feature_code",
+            Self::CountryCode => "country_code",
+            Self::Adm1 => "adm1",
+            Self::Adm2 => "adm2",
+            Self::Adm3 => "adm3",
+            Self::Adm4 => "adm4",
+            Self::Population => "population",
+            Self::Elevation => "elevation",
+            Self::Wikipedia => "wikipedia",
+            Self::Wikidata => "wikidata",
+        }
+    }
+}
+
+/// Serializes only the selected fields of an entry, in the order of `GeoNamesEntry`.
+pub struct SelectedEntry<'a>(pub &'a GeoNamesEntry, pub &'a [EntryField]);
+
+impl SelectedEntry<'_> {
+    fn includes(&self, field: EntryField) -> bool {
+        let SelectedEntry(entry, fields) = self;
+        fields.contains(&field)
+            && match field {
+                EntryField::Elevation => entry.elevation.is_some(),
+                EntryField::Wikipedia => entry.wikipedia.is_some(),
+                EntryField::Wikidata => entry.wikidata.is_some(),
+                _ => true,
+            }
+    }
+}
+
+impl Serialize for SelectedEntry<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use EntryField::*;
+
+        let entry = self.0;
+        let fields = EntryField::ALL
+            .into_iter()
+            .filter(|&field| self.includes(field));
+        let mut map = serializer.serialize_map(Some(fields.clone().count()))?;
+        for field in fields {
+            let name = field.name();
+            match field {
+                Id => map.serialize_entry(name, &entry.id)?,
+                Name => map.serialize_entry(name, &entry.name)?,
+                Latitude => map.serialize_entry(name, &entry.latitude)?,
+                Longitude => map.serialize_entry(name, &entry.longitude)?,
+                FeatureClass => map.serialize_entry(name, &entry.feature_class)?,
+                FeatureCode => map.serialize_entry(name, &entry.feature_code)?,
+                CountryCode => map.serialize_entry(name, &entry.country_code)?,
+                Adm1 => map.serialize_entry(name, &entry.adm1)?,
+                Adm2 => map.serialize_entry(name, &entry.adm2)?,
+                Adm3 => map.serialize_entry(name, &entry.adm3)?,
+                Adm4 => map.serialize_entry(name, &entry.adm4)?,
+                Population => map.serialize_entry(name, &entry.population)?,
+                Elevation => map.serialize_entry(name, &entry.elevation)?,
+                Wikipedia => map.serialize_entry(name, &entry.wikipedia)?,
+                Wikidata => map.serialize_entry(name, &entry.wikidata)?,
+            }
+        }
+        map.end()
+    }
 }
 
 pub trait Entry {
     fn entry(&self) -> &GeoNamesEntry;
+
+    /// The matching key, for results of a name search.
+    fn key(&self) -> Option<&MatchKey> {
+        None
+    }
+
+    /// Distance to the query: the edit distance for fuzzy name searches, or the great-circle
+    /// distance in kilometers for spatial searches.
+    fn distance(&self) -> Option<f64> {
+        None
+    }
+
+    /// Additional information requested for the result, `None` if the result cannot carry any.
+    fn extras(&self) -> Option<&ResultExtras> {
+        None
+    }
+
+    fn extras_mut(&mut self) -> Option<&mut ResultExtras> {
+        None
+    }
+
+    /// Relevance score of the result, if computed.
+    fn score(&self) -> Option<f64> {
+        self.extras().and_then(|extras| extras.score)
+    }
+}
+
+/// Optional information attached to search results on request.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, JsonSchema)]
+pub struct ResultExtras {
+    /// Relevance score between 0 and 1, combining match type, edit distance and population.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+    /// The country and administrative divisions containing the entry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hierarchy: Option<Hierarchy>,
+    /// Name of the entry in the requested display language.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// All indexed names of the entry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alternate_names: Option<Vec<MatchKey>>,
+    /// The other keys that matched the same entry, if results were deduplicated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub other_keys: Option<Vec<MatchKey>>,
+}
+
+impl Entry for GeoNamesEntry {
+    fn entry(&self) -> &GeoNamesEntry {
+        self
+    }
 }
 
 #[derive(Debug, Serialize, PartialEq, JsonSchema)]
 pub struct GeoNamesSearchResult {
     pub key: MatchKey,
     pub entry: GeoNamesEntry,
+    #[serde(flatten)]
+    pub extras: ResultExtras,
 }
 
 impl GeoNamesSearchResult {
@@ -45,6 +241,7 @@ impl GeoNamesSearchResult {
                 typ: typ.clone(),
             },
             entry: gn.clone(),
+            extras: ResultExtras::default(),
         }
     }
 }
@@ -53,6 +250,18 @@ impl Entry for GeoNamesSearchResult {
     fn entry(&self) -> &GeoNamesEntry {
         &self.entry
     }
+
+    fn key(&self) -> Option<&MatchKey> {
+        Some(&self.key)
+    }
+
+    fn extras(&self) -> Option<&ResultExtras> {
+        Some(&self.extras)
+    }
+
+    fn extras_mut(&mut self) -> Option<&mut ResultExtras> {
+        Some(&mut self.extras)
+    }
 }
 
 impl Eq for GeoNamesSearchResult {}
@@ -75,15 +284,18 @@ impl From<GeoNamesSearchResult> for GeoNamesSearchResultWithDist {
             key: val.key,
             entry: val.entry,
             distance: 0,
+            extras: val.extras,
         }
     }
 }
 
 #[derive(Debug, PartialEq, Serialize, JsonSchema)]
 pub struct GeoNamesSearchResultWithDist {
-    key: MatchKey,
-    entry: GeoNamesEntry,
-    distance: usize,
+    pub key: MatchKey,
+    pub entry: GeoNamesEntry,
+    pub distance: usize,
+    #[serde(flatten)]
+    pub extras: ResultExtras,
 }
 
 impl GeoNamesSearchResultWithDist {
@@ -95,6 +307,7 @@ impl GeoNamesSearchResultWithDist {
             },
             entry: gn.clone(),
             distance: dist,
+            extras: ResultExtras::default(),
         }
     }
 }
@@ -103,6 +316,22 @@ impl Entry for GeoNamesSearchResultWithDist {
     fn entry(&self) -> &GeoNamesEntry {
         &self.entry
     }
+
+    fn key(&self) -> Option<&MatchKey> {
+        Some(&self.key)
+    }
+
+    fn distance(&self) -> Option<f64> {
+        Some(self.distance as f64)
+    }
+
+    fn extras(&self) -> Option<&ResultExtras> {
+        Some(&self.extras)
+    }
+
+    fn extras_mut(&mut self) -> Option<&mut ResultExtras> {
+        Some(&mut self.extras)
+    }
 }
 
 impl Eq for GeoNamesSearchResultWithDist {}
@@ -124,7 +353,72 @@ impl PartialOrd for GeoNamesSearchResultWithDist {
     }
 }
 
-#[derive(Debug, Clone, Serialize, PartialEq, Eq, JsonSchema)]
+/// A lightweight search result for typeahead suggestions.
+#[derive(Debug, PartialEq, Serialize, JsonSchema)]
+pub struct GeoNamesSuggestion {
+    /// Identifier of the GeoNames record.
+    pub id: u64,
+    /// The matching name, which may be an alternate name of the entry.
+    pub name: String,
+    pub country_code: Symbol,
+    pub latitude: f32,
+    pub longitude: f32,
+}
+
+impl From<GeoNamesSearchResult> for GeoNamesSuggestion {
+    fn from(result: GeoNamesSearchResult) -> Self {
+        GeoNamesSuggestion {
+            id: result.entry.id,
+            name: result.key.name,
+            country_code: result.entry.country_code,
+            latitude: result.entry.latitude,
+            longitude: result.entry.longitude,
+        }
+    }
+}
+
+/// A "did you mean" candidate name for a possibly misspelled query.
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct SpellingSuggestion {
+    /// The suggested name, which may be an alternate name.
+    pub name: String,
+    /// Levenshtein distance to the query.
+    pub distance: usize,
+    /// Number of GeoNames entries with this name.
+    pub entries: usize,
+    /// Highest population among the entries with this name, `0` if unknown.
+    pub population: u64,
+}
+
+/// A GeoNames entry found by its location, with its distance to the query point.
+#[derive(Debug, PartialEq, Serialize, JsonSchema)]
+pub struct GeoNamesNearbyResult {
+    pub entry: GeoNamesEntry,
+    /// Great-circle distance to the query point in kilometers.
+    pub distance_km: f64,
+    #[serde(flatten)]
+    pub extras: ResultExtras,
+}
+
+impl Entry for GeoNamesNearbyResult {
+    fn entry(&self) -> &GeoNamesEntry {
+        &self.entry
+    }
+
+    fn distance(&self) -> Option<f64> {
+        Some(self.distance_km)
+    }
+
+    fn extras(&self) -> Option<&ResultExtras> {
+        Some(&self.extras)
+    }
+
+    fn extras_mut(&mut self) -> Option<&mut ResultExtras> {
+        Some(&mut self.extras)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 #[serde(tag = "type")]
 pub enum MatchType {
     /// GeoNames main name (usually English)
@@ -146,9 +440,37 @@ pub enum MatchType {
     },
     /// Alternate: other name in a specific language
     Alternate { id: u64, lang: String },
+    /// User-supplied synonym or alias from a synonyms file
+    Synonym { id: u64 },
+}
+
+/// The kind of a `MatchType`, without its data.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+pub enum MatchTypeKind {
+    Name,
+    AsciiName,
+    PreferredName,
+    ShortName,
+    Colloquial,
+    Historic,
+    Alternate,
+    Synonym,
 }
 
 impl MatchType {
+    pub fn kind(&self) -> MatchTypeKind {
+        match self {
+            MatchType::Name { .. } => MatchTypeKind::Name,
+            MatchType::AsciiName { .. } => MatchTypeKind::AsciiName,
+            MatchType::PreferredName { .. } => MatchTypeKind::PreferredName,
+            MatchType::ShortName { .. } => MatchTypeKind::ShortName,
+            MatchType::Colloquial { .. } => MatchTypeKind::Colloquial,
+            MatchType::Historic { .. } => MatchTypeKind::Historic,
+            MatchType::Alternate { .. } => MatchTypeKind::Alternate,
+            MatchType::Synonym { .. } => MatchTypeKind::Synonym,
+        }
+    }
+
     pub(crate) fn id(&self) -> u64 {
         match self {
             MatchType::Name { id } => *id,
@@ -158,6 +480,21 @@ impl MatchType {
             MatchType::Colloquial { id, .. } => *id,
             MatchType::Historic { id, .. } => *id,
             MatchType::Alternate { id, .. } => *id,
+            MatchType::Synonym { id } => *id,
+        }
+    }
+
+    /// Language of an alternate name, `None` for main names and synonyms.
+    pub(crate) fn lang(&self) -> Option<&str> {
+        match self {
+            MatchType::PreferredName { lang, .. }
+            | MatchType::ShortName { lang, .. }
+            | MatchType::Colloquial { lang, .. }
+            | MatchType::Historic { lang, .. }
+            | MatchType::Alternate { lang, .. } => Some(lang),
+            MatchType::Name { .. } | MatchType::AsciiName { .. } | MatchType::Synonym { .. } => {
+                None
+            }
         }
     }
 
@@ -170,6 +507,7 @@ impl MatchType {
             MatchType::Colloquial { .. } => 4,
             MatchType::Historic { .. } => 5,
             MatchType::Alternate { .. } => 6,
+            MatchType::Synonym { .. } => 7,
         }
     }
 }
@@ -191,13 +529,26 @@ impl PartialOrd for MatchType {
     }
 }
 
-#[derive(Debug, Serialize, PartialEq, Eq, JsonSchema)]
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, JsonSchema)]
 pub struct MatchKey {
     name: String,
     #[serde(flatten)]
     typ: MatchType,
 }
 
+impl MatchKey {
+    pub fn new(name: &str, typ: &MatchType) -> Self {
+        MatchKey {
+            name: name.to_string(),
+            typ: typ.clone(),
+        }
+    }
+
+    pub fn typ(&self) -> &MatchType {
+        &self.typ
+    }
+}
+
 impl PartialOrd for MatchKey {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
@@ -0,0 +1,102 @@
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use fst::Automaton;
+
+/// Number of automaton states a search visits between two looks at the clock.
+const CHECK_INTERVAL: usize = 4096;
+
+/// The point in time at which a search gives up, so that a slow search does not keep a thread
+/// busy after its client has been answered with a timeout.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Deadline(Option<(Instant, Duration)>);
+
+/// A search that was stopped at its deadline, after the given timeout.
+#[derive(Debug, Clone, Copy)]
+pub struct TimedOut(pub Duration);
+
+impl Deadline {
+    /// No deadline, e.g. for searches from the command line.
+    pub const NONE: Self = Self(None);
+
+    /// A deadline `timeout` from now, or none without a timeout.
+    pub fn after(timeout: Option<Duration>) -> Self {
+        Self(timeout.map(|timeout| (Instant::now() + timeout, timeout)))
+    }
+
+    /// Fails if the deadline has passed.
+    pub fn check(&self) -> Result<(), TimedOut> {
+        match self.0 {
+            Some((at, timeout)) if Instant::now() > at => Err(TimedOut(timeout)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Wrap `automaton` so that it stops matching anything once the deadline has passed.
+    pub fn guard<A: Automaton>(self, automaton: A) -> Guarded<A> {
+        Guarded {
+            automaton,
+            deadline: self,
+            visited: Cell::new(0),
+            expired: Cell::new(None),
+        }
+    }
+}
+
+/// An automaton that ends the FST stream it drives at a deadline.
+///
+/// Checking between the keys of a stream is not enough: a pattern like `.*q.*x.*z` can visit
+/// most of the FST without yielding a single key.
+pub struct Guarded<A> {
+    automaton: A,
+    deadline: Deadline,
+    visited: Cell<usize>,
+    expired: Cell<Option<TimedOut>>,
+}
+
+impl<A> Guarded<A> {
+    /// Fails if the deadline passed while streaming, in which case the stream ended early.
+    pub fn check(&self) -> Result<(), TimedOut> {
+        self.expired.get().map_or(Ok(()), Err)
+    }
+
+    fn expired(&self) -> bool {
+        if self.expired.get().is_some() {
+            return true;
+        }
+        let visited = self.visited.get();
+        self.visited.set(visited + 1);
+        if visited.is_multiple_of(CHECK_INTERVAL) {
+            self.expired.set(self.deadline.check().err());
+        }
+        self.expired.get().is_some()
+    }
+}
+
+impl<A: Automaton> Automaton for Guarded<A> {
+    type State = A::State;
+
+    fn start(&self) -> A::State {
+        self.automaton.start()
+    }
+
+    fn is_match(&self, state: &A::State) -> bool {
+        self.automaton.is_match(state)
+    }
+
+    fn can_match(&self, state: &A::State) -> bool {
+        !self.expired() && self.automaton.can_match(state)
+    }
+
+    fn will_always_match(&self, state: &A::State) -> bool {
+        self.automaton.will_always_match(state)
+    }
+
+    fn accept(&self, state: &A::State, byte: u8) -> A::State {
+        self.automaton.accept(state, byte)
+    }
+
+    fn accept_eof(&self, state: &A::State) -> Option<A::State> {
+        self.automaton.accept_eof(state)
+    }
+}
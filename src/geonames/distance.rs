@@ -0,0 +1,192 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use unicode_canonical_combining_class::{get_canonical_combining_class, CanonicalCombiningClass};
+
+/// Edit distance used to score search results against the query.
+///
+/// Distances count user-perceived characters: a base character together with any combining marks
+/// following it, so decomposed names like "Mu\u{308}nchen" are one edit away from "Munchen" just
+/// like "München".
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EditMetric {
+    /// Insertions, deletions and substitutions.
+    #[default]
+    Levenshtein,
+    /// Like `levenshtein`, but swapping two adjacent characters costs 1 instead of 2, e.g.
+    /// "Farnkfurt" is at distance 1 from "Frankfurt".
+    Damerau,
+}
+
+impl EditMetric {
+    pub fn distance(&self, a: &str, b: &str) -> usize {
+        let a = clusters(a);
+        let b = clusters(b);
+        match self {
+            EditMetric::Levenshtein => strsim::generic_levenshtein(&a, &b),
+            EditMetric::Damerau => strsim::generic_damerau_levenshtein(&a, &b),
+        }
+    }
+
+    /// The distance between `a` and `b` if it is at most `max`.
+    ///
+    /// Gives up as soon as the distance is known to exceed `max`, which is much cheaper than
+    /// `distance` for the many far away keys of a search with a small maximum distance.
+    pub fn distance_within(&self, a: &str, b: &str, max: usize) -> Option<usize> {
+        let a = clusters(a);
+        let b = clusters(b);
+        if a.len().abs_diff(b.len()) > max {
+            return None;
+        }
+        let dist = match self {
+            EditMetric::Levenshtein => bounded_levenshtein(&a, &b, max)?,
+            EditMetric::Damerau => strsim::generic_damerau_levenshtein(&a, &b),
+        };
+        (dist <= max).then_some(dist)
+    }
+}
+
+/// Levenshtein distance restricted to the diagonal band of width `max`, as any path leaving the
+/// band costs more than `max` edits. Stops once a whole row of the band exceeds `max`.
+fn bounded_levenshtein(a: &[&str], b: &[&str], max: usize) -> Option<usize> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+    if a.is_empty() || b.is_empty() {
+        return Some(a.len().max(b.len()));
+    }
+
+    // Values above `max` are capped, so cells outside of the band can hold `over`
+    let over = max + 1;
+    let n = b.len();
+    let mut prev: Vec<usize> = (0..=n).map(|j| j.min(over)).collect();
+    let mut cur = vec![over; n + 1];
+    for (i, x) in a.iter().enumerate().map(|(i, x)| (i + 1, x)) {
+        let lo = i.saturating_sub(max).max(1);
+        let hi = (i + max).min(n);
+        cur[lo - 1] = if lo == 1 { i.min(over) } else { over };
+        let mut row_min = cur[lo - 1];
+        for j in lo..=hi {
+            let cost = usize::from(*x != b[j - 1]);
+            let value = (prev[j - 1] + cost)
+                .min(prev[j] + 1)
+                .min(cur[j - 1] + 1)
+                .min(over);
+            cur[j] = value;
+            row_min = row_min.min(value);
+        }
+        if hi < n {
+            cur[hi + 1] = over;
+        }
+        if row_min > max {
+            return None;
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    Some(prev[n])
+}
+
+/// Split a string into base characters, each with the combining marks following it.
+fn clusters(s: &str) -> Vec<&str> {
+    let mut clusters = Vec::with_capacity(s.len());
+    let mut start = 0;
+    for (i, c) in s.char_indices().skip(1) {
+        if get_canonical_combining_class(c) == CanonicalCombiningClass::NotReordered {
+            clusters.push(&s[start..i]);
+            start = i;
+        }
+    }
+    if !s.is_empty() {
+        clusters.push(&s[start..]);
+    }
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORDS: [&str; 16] = [
+        "",
+        "a",
+        "ab",
+        "ba",
+        "Berlin",
+        "Berln",
+        "Bärlin",
+        "Ba\u{308}rlin",
+        "München",
+        "Mu\u{308}nchen",
+        "Munchen",
+        "Mu\u{308}\u{301}nchen",
+        "Frankfurt",
+        "Farnkfurt",
+        "東京都",
+        "京東",
+    ];
+
+    #[test]
+    fn distance_within_matches_distance() {
+        for metric in [EditMetric::Levenshtein, EditMetric::Damerau] {
+            for a in WORDS {
+                for b in WORDS {
+                    let distance = metric.distance(a, b);
+                    for max in 0..=distance + 2 {
+                        assert_eq!(
+                            metric.distance_within(a, b, max),
+                            (distance <= max).then_some(distance),
+                            "{metric:?} distance of {a:?} and {b:?} within {max}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn distance_within_matches_distance_for_all_short_strings() {
+        // Every string of up to three clusters, mixing ASCII, multi-byte and combining marks
+        let alphabet = ["a", "b", "ü", "u\u{308}"];
+        let mut strings = vec![String::new()];
+        for len in 1..=3 {
+            let longer: Vec<String> = strings
+                .iter()
+                .filter(|s| clusters(s).len() == len - 1)
+                .flat_map(|s| alphabet.iter().map(move |c| format!("{s}{c}")))
+                .collect();
+            strings.extend(longer);
+        }
+        for metric in [EditMetric::Levenshtein, EditMetric::Damerau] {
+            for a in &strings {
+                for b in &strings {
+                    let distance = metric.distance(a, b);
+                    for max in 0..=3 {
+                        assert_eq!(
+                            metric.distance_within(a, b, max),
+                            (distance <= max).then_some(distance),
+                            "{metric:?} distance of {a:?} and {b:?} within {max}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn combining_marks_are_part_of_their_base_character() {
+        let metric = EditMetric::Levenshtein;
+        assert_eq!(metric.distance("Mu\u{308}nchen", "Munchen"), 1);
+        assert_eq!(metric.distance("Mu\u{308}nchen", "Mo\u{308}nchen"), 1);
+        assert_eq!(
+            metric.distance_within("Mu\u{308}\u{301}nchen", "Munchen", 1),
+            Some(1)
+        );
+        assert_eq!(metric.distance_within("Ba\u{308}rlin", "Berlin", 0), None);
+    }
+}
@@ -0,0 +1,288 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Mean radius of the earth in kilometers.
+pub const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+/// Great-circle distance between two points in kilometers, using the haversine formula.
+pub fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// Initial bearing when travelling from the first point to the second along the great circle,
+/// in degrees clockwise from north in `[0, 360)`.
+pub fn initial_bearing_deg(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lon = (lon2 - lon1).to_radians();
+    let y = d_lon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * d_lon.cos();
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+/// Returns true if the coordinates are a valid latitude/longitude pair.
+pub fn is_valid_coordinate(lat: f64, lon: f64) -> bool {
+    (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon)
+}
+
+/// A latitude/longitude box. Boxes with `min_lon > max_lon` cross the antimeridian.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+pub struct BoundingBox {
+    pub min_lat: f64,
+    pub min_lon: f64,
+    pub max_lat: f64,
+    pub max_lon: f64,
+}
+
+impl BoundingBox {
+    /// The area covered by the web mercator ("slippy map") tile, `None` if the tile does not
+    /// exist at that zoom level.
+    pub fn from_tile(z: u8, x: u32, y: u32) -> Option<Self> {
+        if z > 30 {
+            return None;
+        }
+        let n = 1u32 << z;
+        if x >= n || y >= n {
+            return None;
+        }
+        let n = n as f64;
+        let lon = |x: u32| x as f64 / n * 360.0 - 180.0;
+        let lat = |y: u32| {
+            (std::f64::consts::PI * (1.0 - 2.0 * y as f64 / n))
+                .sinh()
+                .atan()
+                .to_degrees()
+        };
+        Some(Self {
+            min_lat: lat(y + 1),
+            min_lon: lon(x),
+            max_lat: lat(y),
+            max_lon: lon(x + 1),
+        })
+    }
+
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        let lon_inside = if self.min_lon <= self.max_lon {
+            (self.min_lon..=self.max_lon).contains(&lon)
+        } else {
+            lon >= self.min_lon || lon <= self.max_lon
+        };
+        (self.min_lat..=self.max_lat).contains(&lat) && lon_inside
+    }
+}
+
+/// The bounding box and centroid of a set of points.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, JsonSchema)]
+pub struct Extent {
+    pub min_lat: f64,
+    pub min_lon: f64,
+    pub max_lat: f64,
+    pub max_lon: f64,
+    /// Mean latitude of the points.
+    pub centroid_lat: f64,
+    /// Mean longitude of the points.
+    pub centroid_lon: f64,
+}
+
+impl Extent {
+    /// The extent of the `(lat, lon)` points, ignoring points without valid coordinates. `None`
+    /// if there are no such points. Point sets crossing the antimeridian span the whole globe.
+    pub fn of(points: impl Iterator<Item = (f64, f64)>) -> Option<Self> {
+        let mut count = 0;
+        let mut extent = Self {
+            min_lat: f64::INFINITY,
+            min_lon: f64::INFINITY,
+            max_lat: f64::NEG_INFINITY,
+            max_lon: f64::NEG_INFINITY,
+            centroid_lat: 0.0,
+            centroid_lon: 0.0,
+        };
+        for (lat, lon) in points.filter(|&(lat, lon)| is_valid_coordinate(lat, lon)) {
+            count += 1;
+            extent.min_lat = extent.min_lat.min(lat);
+            extent.min_lon = extent.min_lon.min(lon);
+            extent.max_lat = extent.max_lat.max(lat);
+            extent.max_lon = extent.max_lon.max(lon);
+            extent.centroid_lat += lat;
+            extent.centroid_lon += lon;
+        }
+        (count > 0).then(|| Self {
+            centroid_lat: extent.centroid_lat / count as f64,
+            centroid_lon: extent.centroid_lon / count as f64,
+            ..extent
+        })
+    }
+}
+
+/// A ring of `[longitude, latitude]` positions. The closing position may be omitted.
+type Ring = Vec<[f64; 2]>;
+
+/// One or more polygons, each with an outer ring followed by the rings of its holes.
+///
+/// Given either as a GeoJSON `Polygon` or `MultiPolygon` geometry, or as a WKT `POLYGON` or
+/// `MULTIPOLYGON` string. Coordinates are longitude first, and polygons crossing the
+/// antimeridian are not supported.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(try_from = "PolygonInput", into = "GeoJsonPolygon")]
+#[schemars(with = "PolygonInput")]
+pub struct Polygon {
+    polygons: Vec<Vec<Ring>>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum PolygonInput {
+    /// A WKT `POLYGON` or `MULTIPOLYGON`, e.g. `POLYGON ((8.5 50, 8.8 50, 8.8 50.2, 8.5 50.2, 8.5 50))`.
+    Wkt(String),
+    GeoJson(GeoJsonPolygon),
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", content = "coordinates")]
+enum GeoJsonPolygon {
+    Polygon(Vec<Ring>),
+    MultiPolygon(Vec<Vec<Ring>>),
+}
+
+impl TryFrom<PolygonInput> for Polygon {
+    type Error = String;
+
+    fn try_from(input: PolygonInput) -> Result<Self, Self::Error> {
+        let polygons = match input {
+            PolygonInput::Wkt(wkt) => parse_wkt(&wkt)?,
+            PolygonInput::GeoJson(GeoJsonPolygon::Polygon(rings)) => vec![rings],
+            PolygonInput::GeoJson(GeoJsonPolygon::MultiPolygon(polygons)) => polygons,
+        };
+        if polygons.is_empty() || polygons.iter().any(Vec::is_empty) {
+            return Err("polygon without rings".to_string());
+        }
+        if polygons.iter().flatten().any(|ring| ring.len() < 3) {
+            return Err("polygon ring with less than 3 positions".to_string());
+        }
+        Ok(Self { polygons })
+    }
+}
+
+impl From<Polygon> for GeoJsonPolygon {
+    fn from(polygon: Polygon) -> Self {
+        GeoJsonPolygon::MultiPolygon(polygon.polygons)
+    }
+}
+
+/// The contents of the parenthesized groups in `text`, e.g. `["a", "b"]` for `(a), (b)`.
+fn wkt_groups(text: &str) -> Option<Vec<&str>> {
+    let mut groups = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => {
+                if depth == 0 {
+                    start = i + 1;
+                }
+                depth += 1;
+            }
+            ')' => {
+                depth -= 1;
+                match depth {
+                    0 => groups.push(&text[start..i]),
+                    ..0 => return None,
+                    _ => {}
+                }
+            }
+            c if depth == 0 && c != ',' && !c.is_whitespace() => return None,
+            _ => {}
+        }
+    }
+    (depth == 0).then_some(groups)
+}
+
+fn wkt_ring(ring: &str) -> Option<Ring> {
+    ring.split(',')
+        .map(|position| {
+            let mut numbers = position.split_whitespace().map(str::parse::<f64>);
+            match (numbers.next(), numbers.next(), numbers.next()) {
+                (Some(Ok(lon)), Some(Ok(lat)), None) => Some([lon, lat]),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn wkt_polygon(polygon: &str) -> Option<Vec<Ring>> {
+    wkt_groups(polygon)?.into_iter().map(wkt_ring).collect()
+}
+
+/// Parse a WKT `POLYGON` or `MULTIPOLYGON` into its polygons.
+fn parse_wkt(wkt: &str) -> Result<Vec<Vec<Ring>>, String> {
+    let start = wkt.find('(').unwrap_or(wkt.len());
+    let (kind, body) = wkt.split_at(start);
+    let [body] = wkt_groups(body).unwrap_or_default()[..] else {
+        return Err(format!("invalid WKT polygon: {wkt}"));
+    };
+    let polygons = match kind.trim().to_ascii_uppercase().as_str() {
+        "POLYGON" => wkt_polygon(body).map(|polygon| vec![polygon]),
+        "MULTIPOLYGON" => {
+            wkt_groups(body).and_then(|polygons| polygons.into_iter().map(wkt_polygon).collect())
+        }
+        _ => None,
+    };
+    polygons.ok_or_else(|| format!("invalid WKT polygon: {wkt}"))
+}
+
+/// Returns true if the point is inside the ring, using the even-odd rule.
+fn ring_contains(ring: &Ring, lat: f64, lon: f64) -> bool {
+    let mut inside = false;
+    let mut previous = ring[ring.len() - 1];
+    for &current in ring {
+        let ([x1, y1], [x2, y2]) = (previous, current);
+        if (y1 > lat) != (y2 > lat) && lon < x1 + (lat - y1) * (x2 - x1) / (y2 - y1) {
+            inside = !inside;
+        }
+        previous = current;
+    }
+    inside
+}
+
+impl Polygon {
+    /// The smallest bounding box containing the outer rings of all polygons.
+    pub fn bounds(&self) -> BoundingBox {
+        self.polygons
+            .iter()
+            .filter_map(|rings| rings.first())
+            .flatten()
+            .fold(
+                BoundingBox {
+                    min_lat: f64::INFINITY,
+                    min_lon: f64::INFINITY,
+                    max_lat: f64::NEG_INFINITY,
+                    max_lon: f64::NEG_INFINITY,
+                },
+                |bbox, &[lon, lat]| BoundingBox {
+                    min_lat: bbox.min_lat.min(lat),
+                    min_lon: bbox.min_lon.min(lon),
+                    max_lat: bbox.max_lat.max(lat),
+                    max_lon: bbox.max_lon.max(lon),
+                },
+            )
+    }
+
+    pub fn number_of_positions(&self) -> usize {
+        self.polygons.iter().flatten().map(Vec::len).sum()
+    }
+
+    /// Returns true if the point is inside one of the polygons, but not inside one of its holes.
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        self.polygons.iter().any(|rings| {
+            rings
+                .iter()
+                .filter(|ring| ring_contains(ring, lat, lon))
+                .count()
+                % 2
+                == 1
+        })
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::geonames::data::GeoNamesEntry;
+use crate::geonames::store::EntryStore;
+
+/// The administrative parents of an entry, as far as they are part of the index.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, JsonSchema)]
+pub struct Hierarchy {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<GeoNamesEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin1: Option<GeoNamesEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin2: Option<GeoNamesEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin3: Option<GeoNamesEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin4: Option<GeoNamesEntry>,
+}
+
+/// Lookup of the GeoNames ids of countries and administrative divisions by their codes.
+///
+/// Codes are joined like in the GeoNames `admin1CodesASCII.txt` file, e.g. `DE.05.064` for the
+/// second-order division `064` of the first-order division `05` in Germany.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AdminIndex {
+    ids: HashMap<String, u64>,
+}
+
+/// Level of the feature code, `0` for countries and `1` to `4` for the administrative divisions,
+/// and its precedence among the codes of that level, lower is preferred. `None` for any other
+/// feature, including historical divisions.
+fn level(feature_code: &str) -> Option<(usize, u8)> {
+    match feature_code {
+        "PCLI" => Some((0, 0)),
+        "PCL" | "PCLD" | "PCLF" | "PCLS" => Some((0, 1)),
+        "PCLIX" => Some((0, 2)),
+        "ADM1" => Some((1, 0)),
+        "ADM2" => Some((2, 0)),
+        "ADM3" => Some((3, 0)),
+        "ADM4" => Some((4, 0)),
+        _ => None,
+    }
+}
+
+/// Codes of the country and the divisions containing the entry, from the country down.
+fn codes(entry: &GeoNamesEntry) -> Vec<String> {
+    let mut codes = vec![entry.country_code.to_string()];
+    for code in [&entry.adm1, &entry.adm2, &entry.adm3, &entry.adm4] {
+        if code.is_empty() {
+            break;
+        }
+        codes.push(format!("{}.{code}", codes.last().unwrap()));
+    }
+    codes
+}
+
+impl AdminIndex {
+    pub fn build(geonames: &EntryStore) -> Self {
+        let mut candidates: HashMap<String, (u8, u64)> = HashMap::new();
+        for entry in geonames.values().filter(|entry| entry.feature_class == "A") {
+            let Some((level, precedence)) = level(&entry.feature_code) else {
+                continue;
+            };
+            let Some(code) = codes(entry).into_iter().nth(level) else {
+                continue;
+            };
+            let candidate = (precedence, entry.id);
+            candidates
+                .entry(code)
+                .and_modify(|current| *current = candidate.min(*current))
+                .or_insert(candidate);
+        }
+        let mut ids: HashMap<String, u64> = candidates
+            .into_iter()
+            .map(|(code, (_, id))| (code, id))
+            .collect();
+        ids.shrink_to_fit();
+        Self { ids }
+    }
+
+    /// Ids of the country and the divisions containing the entry, from the country down. Missing
+    /// parents are `None`.
+    pub fn parents(&self, entry: &GeoNamesEntry) -> Vec<Option<u64>> {
+        codes(entry)
+            .iter()
+            .map(|code| self.ids.get(code).copied().filter(|&id| id != entry.id))
+            .collect()
+    }
+
+    pub fn number_of_codes(&self) -> usize {
+        self.ids.len()
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::geonames::data::MatchType;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LocalizedName {
+    lang: String,
+    name: String,
+    /// Preference among the names of the same language, lower is preferred.
+    rank: u8,
+}
+
+/// The best name of each entry per language, taken from the alternate names.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LocalizedNames {
+    names: HashMap<u64, Vec<LocalizedName>>,
+}
+
+/// Preference of an alternate name for display: preferred names first, then other alternate,
+/// short and colloquial names. `None` for main and historic names.
+fn display_rank(typ: &MatchType) -> Option<(&str, u8)> {
+    match typ {
+        MatchType::PreferredName { lang, .. } => Some((lang, 0)),
+        MatchType::Alternate { lang, .. } => Some((lang, 1)),
+        MatchType::ShortName { lang, .. } => Some((lang, 2)),
+        MatchType::Colloquial { lang, .. } => Some((lang, 3)),
+        _ => None,
+    }
+}
+
+impl LocalizedNames {
+    pub fn build<'a>(pairs: impl Iterator<Item = &'a (String, MatchType)>) -> Self {
+        let mut names: HashMap<u64, Vec<LocalizedName>> = HashMap::new();
+        for (name, typ) in pairs {
+            let Some((lang, rank)) = display_rank(typ) else {
+                continue;
+            };
+            if lang.is_empty() {
+                continue;
+            }
+            let candidates = names.entry(typ.id()).or_default();
+            match candidates.iter_mut().find(|c| c.lang == lang) {
+                Some(current) if current.rank <= rank => {}
+                Some(current) => {
+                    current.name = name.clone();
+                    current.rank = rank;
+                }
+                None => candidates.push(LocalizedName {
+                    lang: lang.to_string(),
+                    name: name.clone(),
+                    rank,
+                }),
+            }
+        }
+        names.shrink_to_fit();
+        Self { names }
+    }
+
+    /// The name of the entry in the given language, if there is one.
+    pub fn get(&self, id: u64, lang: &str) -> Option<&str> {
+        self.names
+            .get(&id)?
+            .iter()
+            .find(|name| name.lang == lang)
+            .map(|name| name.name.as_str())
+    }
+
+    pub fn number_of_entries(&self) -> usize {
+        self.names.len()
+    }
+}
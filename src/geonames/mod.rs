@@ -1,3 +1,22 @@
+pub mod artifact;
+pub mod cluster;
+pub mod columns;
+pub mod completions;
 pub mod data;
+pub mod deadline;
+pub mod distance;
+pub mod geo;
+pub mod hierarchy;
+pub mod localized;
+pub mod ngrams;
+pub mod overlay;
+pub mod score;
 pub mod searcher;
+pub mod shapes;
+pub mod spatial;
+pub mod store;
+pub mod symbol;
+pub mod tokens;
+#[cfg(feature = "gzip")]
+pub mod unzip;
 pub mod utils;
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Length of the character n-grams in the index.
+const N: usize = 3;
+
+/// Padding marking the start and end of a name, so that short names and the first and last
+/// characters still produce n-grams.
+const PADDING: char = '\u{2}';
+
+/// Inverted index from character trigrams to the keys containing them.
+///
+/// Garbled names (e.g. from OCR) may be too far from the correct name for a bounded Levenshtein
+/// automaton, but usually still share a good part of their trigrams with it. Keys are indexed
+/// case-insensitively by their FST value.
+#[derive(Serialize, Deserialize)]
+pub struct NgramIndex {
+    /// The indexed keys, by FST value.
+    keys: Vec<String>,
+    postings: HashMap<String, Vec<u32>>,
+}
+
+/// The distinct trigrams of a name, lowercased and padded.
+fn ngrams(name: &str) -> Vec<String> {
+    let chars: Vec<char> = std::iter::repeat_n(PADDING, N - 1)
+        .chain(name.chars().flat_map(char::to_lowercase))
+        .chain(std::iter::repeat_n(PADDING, N - 1))
+        .collect();
+    let mut ngrams: Vec<String> = chars
+        .windows(N)
+        .map(|window| window.iter().collect())
+        .collect();
+    ngrams.sort_unstable();
+    ngrams.dedup();
+    ngrams
+}
+
+impl NgramIndex {
+    /// Index the keys, which have to be given in FST value order.
+    pub fn build<'a>(keys: impl Iterator<Item = &'a str>) -> Self {
+        let mut index = Self {
+            keys: Vec::new(),
+            postings: HashMap::new(),
+        };
+        for (gnd, key) in keys.enumerate() {
+            for ngram in ngrams(key) {
+                index.postings.entry(ngram).or_default().push(gnd as u32);
+            }
+            index.keys.push(key.to_string());
+        }
+        index.keys.shrink_to_fit();
+        index.postings.shrink_to_fit();
+        index
+    }
+
+    /// The `limit` keys most similar to the query by their shared trigrams, as `(key, FST value)`
+    /// pairs. Keys sharing less than `min_similarity` (Jaccard index) of their trigrams with the
+    /// query are skipped.
+    pub fn candidates(&self, query: &str, min_similarity: f32, limit: usize) -> Vec<(&str, u64)> {
+        let query = ngrams(query);
+        let mut shared: HashMap<u32, usize> = HashMap::new();
+        for ngram in &query {
+            for &gnd in self.postings.get(ngram).into_iter().flatten() {
+                *shared.entry(gnd).or_default() += 1;
+            }
+        }
+
+        let mut candidates: Vec<(f32, u32)> = shared
+            .into_iter()
+            .map(|(gnd, shared)| {
+                let key_ngrams = ngrams(&self.keys[gnd as usize]).len();
+                let similarity = shared as f32 / (query.len() + key_ngrams - shared) as f32;
+                (similarity, gnd)
+            })
+            .filter(|(similarity, _)| *similarity >= min_similarity)
+            .collect();
+        candidates.sort_by(|(a_sim, a_gnd), (b_sim, b_gnd)| {
+            b_sim.total_cmp(a_sim).then(a_gnd.cmp(b_gnd))
+        });
+        candidates
+            .into_iter()
+            .take(limit)
+            .map(|(_, gnd)| (self.keys[gnd as usize].as_str(), gnd as u64))
+            .collect()
+    }
+
+    pub fn number_of_ngrams(&self) -> usize {
+        self.postings.len()
+    }
+
+    /// Rough estimate of the heap memory held by the index, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        let keys: usize = self.keys.iter().map(|key| key.capacity()).sum();
+        let postings: usize = self
+            .postings
+            .iter()
+            .map(|(ngram, gnds)| ngram.capacity() + gnds.capacity() * 4)
+            .sum();
+        keys + self.keys.capacity() * std::mem::size_of::<String>() + postings
+    }
+}
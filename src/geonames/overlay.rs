@@ -0,0 +1,150 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+
+use fst::Automaton;
+
+use crate::geonames::data::{GeoNamesEntry, MatchType};
+
+/// Entries inserted at runtime, which are searched alongside the FST until the server restarts.
+///
+/// The FST is immutable, so names of the overlay entries are kept in a sorted map instead and
+/// matched against the search automata directly.
+#[derive(Debug, Clone, Default)]
+pub struct Overlay {
+    entries: HashMap<u64, GeoNamesEntry>,
+    /// Matches of every name of the overlay entries, in byte order like the FST keys.
+    keys: BTreeMap<String, Vec<MatchType>>,
+    /// Incremented on every change, so that cached responses can be invalidated.
+    revision: u64,
+}
+
+/// Whether the automaton matches `key`, stopping early once no match is possible.
+fn is_match(query: &impl Automaton, key: &str) -> bool {
+    let mut state = query.start();
+    for &byte in key.as_bytes() {
+        if !query.can_match(&state) {
+            return false;
+        }
+        state = query.accept(&state, byte);
+    }
+    query.is_match(&state)
+}
+
+impl Overlay {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &GeoNamesEntry> {
+        self.entries.values()
+    }
+
+    /// Insert the entry under the given names, replacing an overlay entry with the same id. The
+    /// first name is indexed as its main name, all others as synonyms.
+    pub fn insert(&mut self, entry: GeoNamesEntry, names: &[String]) {
+        let id = entry.id;
+        self.remove(id);
+        for (i, name) in names.iter().enumerate() {
+            let typ = match i {
+                0 => MatchType::Name { id },
+                _ => MatchType::Synonym { id },
+            };
+            self.keys.entry(name.clone()).or_default().push(typ);
+        }
+        self.entries.insert(id, entry);
+        self.revision += 1;
+    }
+
+    /// Keep only the entries whose id satisfies `keep`, returning the number of removed entries.
+    pub fn retain(&mut self, keep: impl Fn(u64) -> bool) -> usize {
+        let removed: Vec<u64> = self
+            .entries
+            .keys()
+            .copied()
+            .filter(|&id| !keep(id))
+            .collect();
+        for &id in &removed {
+            self.remove(id);
+        }
+        if !removed.is_empty() {
+            self.revision += 1;
+        }
+        removed.len()
+    }
+
+    fn remove(&mut self, id: u64) {
+        if self.entries.remove(&id).is_some() {
+            self.keys.retain(|_, matches| {
+                matches.retain(|typ| typ.id() != id);
+                !matches.is_empty()
+            });
+        }
+    }
+
+    /// The names of the overlay entry with the given id, with how they match it.
+    pub fn names_for(&self, id: u64) -> impl Iterator<Item = (&str, &MatchType)> {
+        self.keys.iter().flat_map(move |(key, matches)| {
+            matches
+                .iter()
+                .filter(move |typ| typ.id() == id)
+                .map(move |typ| (key.as_str(), typ))
+        })
+    }
+
+    /// The matches of `key` that are accepted by `filter`.
+    pub fn get(
+        &self,
+        key: &str,
+        filter: impl Fn(&MatchType, &GeoNamesEntry) -> bool,
+    ) -> Vec<(&MatchType, &GeoNamesEntry)> {
+        self.keys
+            .get(key)
+            .map(|matches| self.filtered(matches, &filter))
+            .unwrap_or_default()
+    }
+
+    /// The keys that are prefixes of `text`, in key order.
+    pub fn prefixes_of<'a>(&'a self, text: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        // Prefixes of the text sort between its first character and the text itself
+        let first = text.chars().next().map_or(0, char::len_utf8);
+        self.keys
+            .range::<str, _>((Bound::Included(&text[..first]), Bound::Included(text)))
+            .map(|(key, _)| key.as_str())
+            .filter(move |key| text.starts_with(key))
+    }
+
+    /// The keys within `range` that match the automaton, in key order, with their matches that
+    /// are accepted by `filter`. Keys without accepted matches are skipped.
+    pub fn search<'a>(
+        &'a self,
+        query: &'a impl Automaton,
+        range: (Bound<&str>, Bound<&str>),
+        filter: impl Fn(&MatchType, &GeoNamesEntry) -> bool + 'a,
+    ) -> impl Iterator<Item = (&'a str, Vec<(&'a MatchType, &'a GeoNamesEntry)>)> + 'a {
+        self.keys
+            .range::<str, _>(range)
+            .filter(|(key, _)| is_match(query, key))
+            .map(move |(key, matches)| (key.as_str(), self.filtered(matches, &filter)))
+            .filter(|(_, matches)| !matches.is_empty())
+    }
+
+    fn filtered<'a>(
+        &'a self,
+        matches: &'a [MatchType],
+        filter: impl Fn(&MatchType, &GeoNamesEntry) -> bool,
+    ) -> Vec<(&'a MatchType, &'a GeoNamesEntry)> {
+        matches
+            .iter()
+            .map(|typ| (typ, &self.entries[&typ.id()]))
+            .filter(|(typ, gn)| filter(typ, gn))
+            .collect()
+    }
+}
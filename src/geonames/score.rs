@@ -0,0 +1,56 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+
+/// Population at which the population component of the score saturates.
+const MAX_SCORE_POPULATION: f64 = 10_000_000.0;
+
+/// Highest `MatchType::ord`, i.e. the least preferred match type.
+const MAX_MATCH_TYPE_ORD: f64 = 7.0;
+
+/// Weights of the components of the relevance score of search results.
+#[derive(Debug, Clone, Copy, Serialize, JsonSchema)]
+pub struct ScoreWeights {
+    /// Weight of the match type, main names scoring highest.
+    pub match_type: f64,
+    /// Weight of the edit distance to the query, exact matches scoring highest.
+    pub distance: f64,
+    /// Weight of the population, on a logarithmic scale.
+    pub population: f64,
+}
+
+impl Default for ScoreWeights {
+    fn default() -> Self {
+        Self {
+            match_type: 1.0,
+            distance: 1.0,
+            population: 1.0,
+        }
+    }
+}
+
+impl ScoreWeights {
+    /// Relevance score between 0 and 1, higher is better: the weighted mean of the components
+    /// that apply to the result. Results of spatial searches have no match type and results of
+    /// exact searches no edit distance.
+    pub fn score(&self, match_type_ord: Option<u8>, distance: Option<f64>, population: u64) -> f64 {
+        let components = [
+            match_type_ord.map(|ord| (self.match_type, 1.0 - ord as f64 / MAX_MATCH_TYPE_ORD)),
+            distance.map(|distance| (self.distance, 1.0 / (1.0 + distance))),
+            Some((
+                self.population,
+                ((population as f64).ln_1p() / MAX_SCORE_POPULATION.ln_1p()).min(1.0),
+            )),
+        ];
+        let (weights, total) = components
+            .into_iter()
+            .flatten()
+            .fold((0.0, 0.0), |(weights, total), (weight, value)| {
+                (weights + weight, total + weight * value)
+            });
+        if weights > 0.0 {
+            total / weights
+        } else {
+            0.0
+        }
+    }
+}
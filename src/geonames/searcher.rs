@@ -1,22 +1,429 @@
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::ops::Bound;
+use std::sync::RwLock;
 
+use fst::automaton::{Levenshtein, LevenshteinError, Str};
 use fst::{Automaton, IntoStreamer, Map, MapBuilder, Streamer};
-use levenshtein::levenshtein as levenshtein_dist;
+use rayon::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
+use crate::geonames::artifact::IndexManifest;
+use crate::geonames::columns::ColumnMapping;
+use crate::geonames::completions::{completion_rank, Completion, CompletionTable};
 use crate::geonames::data::{
-    GeoNamesEntry, GeoNamesSearchResult, GeoNamesSearchResultWithDist, MatchType,
+    GeoNamesEntry, GeoNamesNearbyResult, GeoNamesSearchResult, GeoNamesSearchResultWithDist,
+    MatchKey, MatchType, ResultExtras, SpellingSuggestion,
 };
-use crate::geonames::utils::{parse_alternate_names_file, parse_geonames_file};
+use crate::geonames::deadline::{Deadline, TimedOut};
+use crate::geonames::distance::EditMetric;
+use crate::geonames::geo::{haversine_km, BoundingBox};
+use crate::geonames::hierarchy::{AdminIndex, Hierarchy};
+use crate::geonames::localized::LocalizedNames;
+use crate::geonames::ngrams::NgramIndex;
+use crate::geonames::overlay::Overlay;
+use crate::geonames::score::ScoreWeights;
+use crate::geonames::shapes::CountryShapes;
+use crate::geonames::spatial::SpatialIndex;
+use crate::geonames::store::EntryStore;
+use crate::geonames::tokens::TokenIndex;
+use crate::geonames::utils::{
+    parse_alternate_names_file, parse_geonames_file, parse_shapes_file, parse_synonyms_file,
+    FeatureExclusions, RowErrors,
+};
+
+/// Resume a search in FST key order from the key `from`, collecting about `limit` results.
+#[derive(Debug, Clone, Copy)]
+pub struct Cursor<'a> {
+    pub from: &'a str,
+    pub limit: usize,
+}
+
+/// Which of the results of a search to collect.
+#[derive(Debug, Clone, Copy)]
+pub enum Collect<'a> {
+    /// All results, sorted.
+    All,
+    /// A page of the results in FST key order, sorted within the page.
+    Page(Cursor<'a>),
+    /// Only the first `k` results in sorted order, without sorting all the others.
+    Top(usize),
+}
+
+impl<'a> Collect<'a> {
+    fn cursor(&self) -> Option<Cursor<'a>> {
+        match self {
+            Self::Page(cursor) => Some(*cursor),
+            _ => None,
+        }
+    }
+}
+
+/// A page of search results. `next_cursor` is the first key of the next page, if there is one.
+#[derive(Debug)]
+pub struct Page<T> {
+    pub results: Vec<T>,
+    pub next_cursor: Option<String>,
+    /// Number of results before they were truncated to the result cap, if they were.
+    pub total: Option<usize>,
+}
+
+impl<T> Page<T> {
+    pub fn map<U>(self, f: impl FnOnce(Vec<T>) -> Vec<U>) -> Page<U> {
+        Page {
+            results: f(self.results),
+            next_cursor: self.next_cursor,
+            total: self.total,
+        }
+    }
+}
+
+impl<T> From<Vec<T>> for Page<T> {
+    fn from(results: Vec<T>) -> Self {
+        Page {
+            results,
+            next_cursor: None,
+            total: None,
+        }
+    }
+}
+
+/// A key found in a text by its byte offsets, with the results of the key.
+#[derive(Debug)]
+pub struct TextMatch {
+    pub start: usize,
+    pub end: usize,
+    pub results: Vec<GeoNamesSearchResult>,
+}
+
+/// Options that control how the `GeoNamesSearcher` index is built.
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    /// Continue with the main names only if an alternate names file is missing or corrupt.
+    pub alternate_optional: bool,
+    /// Skip malformed rows instead of aborting the build.
+    pub lenient: bool,
+    /// Precompute the top-k completions for all prefixes up to this many characters, `0` to disable.
+    pub completions_prefix_len: usize,
+    /// Number of completions to precompute per prefix.
+    pub completions_top_k: usize,
+    /// Build a trigram index over all keys for the n-gram search.
+    pub ngram_index: bool,
+    /// Build a word index over all keys for the token search.
+    pub token_index: bool,
+    /// Languages of individual alternate names files, overriding the global languages.
+    pub alternate_languages: BTreeMap<String, Vec<String>>,
+    /// Files of user-supplied synonyms to add to the index.
+    pub synonyms: Vec<String>,
+    /// GeoNames country shapes file for resolving the country of a point.
+    pub shapes: Option<String>,
+    /// Column layout of the main gazetteer files.
+    pub columns: ColumnMapping,
+    /// Feature classes and codes to leave out of the index.
+    pub exclusions: FeatureExclusions,
+}
+
+/// Input files the index was built from.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Datasets {
+    pub geonames: Vec<String>,
+    pub alternate: Vec<String>,
+    /// Languages considered for alternate names, `null` if all languages were included.
+    pub languages: Option<Vec<String>>,
+    /// Languages of the alternate names files that did not use the global `languages`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub alternate_languages: BTreeMap<String, Vec<String>>,
+    pub synonyms: Vec<String>,
+    pub shapes: Option<String>,
+}
+
+/// A file that could not be ingested, but did not abort the build.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BuildWarning {
+    pub path: String,
+    pub error: String,
+}
+
+/// Summary of the index build, recording any degradation of the served data.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct BuildReport {
+    /// True if some input was skipped and the index is incomplete.
+    pub degraded: bool,
+    pub warnings: Vec<BuildWarning>,
+    /// Rows read from all input files, including those skipped in lenient mode.
+    pub rows: RowErrors,
+}
+
+impl BuildReport {
+    fn warn(&mut self, path: &str, error: anyhow::Error) {
+        tracing::warn!("Skipping {path}: {error:#}");
+        self.degraded = true;
+        self.warnings.push(BuildWarning {
+            path: path.to_string(),
+            error: format!("{error:#}"),
+        });
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric()
+}
+
+/// Byte offset of the first word starting at or after `from`.
+fn next_word_start(text: &str, from: usize) -> Option<usize> {
+    let mut previous = text[..from].chars().next_back();
+    for (i, c) in text[from..].char_indices() {
+        if is_word_char(c) && !previous.is_some_and(is_word_char) {
+            return Some(from + i);
+        }
+        previous = Some(c);
+    }
+    None
+}
+
+/// Whether a word may end at byte offset `end`, i.e. it does not split a word.
+fn is_word_end(text: &str, end: usize) -> bool {
+    let previous = text[..end].chars().next_back();
+    let next = text[end..].chars().next();
+    !(previous.is_some_and(is_word_char) && next.is_some_and(is_word_char))
+}
+
+/// Tie-break rank of overlay results, which come after FST results with equal scores.
+const OVERLAY_RANK: u32 = u32::MAX;
+
+/// The results of a search with the rank of their key, to break ties between equal results.
+///
+/// For the top `k` results, a bounded max-heap is kept whose top is the worst result so far, so
+/// that a prefix query matching a large part of the index does not need to sort all its matches.
+enum Ranked<T> {
+    All(Vec<(T, u32)>),
+    Top(usize, BinaryHeap<(T, u32)>),
+}
+
+impl<T: Ord> Ranked<T> {
+    fn new(collect: Collect) -> Self {
+        match collect {
+            Collect::Top(k) => Self::Top(k, BinaryHeap::with_capacity(k + 1)),
+            _ => Self::All(Vec::new()),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::All(results) => results.len(),
+            Self::Top(_, results) => results.len(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn push(&mut self, rank: u32, result: T) {
+        match self {
+            Self::All(results) => results.push((result, rank)),
+            Self::Top(k, results) => {
+                if results.len() < *k {
+                    results.push((result, rank));
+                } else if let Some(mut worst) = results.peek_mut() {
+                    if (&result, rank) < (&worst.0, worst.1) {
+                        *worst = (result, rank);
+                    }
+                }
+            }
+        }
+    }
+
+    fn into_sorted(self) -> Vec<T> {
+        let results = match self {
+            Self::All(mut results) => {
+                results.sort();
+                results
+            }
+            Self::Top(_, results) => results.into_sorted_vec(),
+        };
+        results.into_iter().map(|(result, _)| result).collect()
+    }
+}
+
+/// The range of overlay keys on the page between `cursor` and `next_cursor`.
+fn overlay_range<'a>(
+    cursor: Option<Cursor<'a>>,
+    next_cursor: &'a Option<String>,
+) -> (Bound<&'a str>, Bound<&'a str>) {
+    (
+        cursor.map_or(Bound::Unbounded, |cursor| Bound::Included(cursor.from)),
+        next_cursor
+            .as_deref()
+            .map_or(Bound::Unbounded, Bound::Excluded),
+    )
+}
+
+/// The distance between the query and a key, or `None` if it exceeds `max_dist` (0 for any).
+fn bounded_distance(
+    metric: EditMetric,
+    query: &str,
+    key: &str,
+    max_dist: Option<u32>,
+) -> Option<usize> {
+    match max_dist {
+        Some(max) if max > 0 => metric.distance_within(query, key, max as usize),
+        _ => Some(metric.distance(query, key)),
+    }
+}
 
 pub struct GeoNamesSearcher {
     pub map: Map<Vec<u8>>,
-    pub geonames: HashMap<u64, GeoNamesEntry>,
-    search_matches: Vec<Vec<MatchType>>,
+    pub geonames: EntryStore,
+    pub(crate) search_matches: Vec<Vec<MatchType>>,
+    pub completions: Option<CompletionTable>,
+    pub ngrams: Option<NgramIndex>,
+    pub tokens: Option<TokenIndex>,
+    pub(crate) admin: AdminIndex,
+    /// Rebuilt from the entries when loading an artifact rather than stored in it.
+    pub(crate) spatial: SpatialIndex,
+    pub shapes: Option<CountryShapes>,
+    pub(crate) localized: LocalizedNames,
+    /// FST values of the keys of each entry.
+    pub(crate) entry_keys: HashMap<u64, Vec<u32>>,
+    /// Weights of the relevance score, configured when serving rather than stored in the index.
+    pub score_weights: ScoreWeights,
+    /// Most results returned by a single search, configured when serving. `None` for no cap.
+    pub max_results: Option<usize>,
+    pub datasets: Datasets,
+    pub report: BuildReport,
+    /// Manifest of the index artifact the searcher was loaded from, if any.
+    pub manifest: Option<IndexManifest>,
+    /// Entries inserted at runtime, searched alongside the FST.
+    pub overlay: RwLock<Overlay>,
 }
 
 impl GeoNamesSearcher {
+    /// Rough estimate of the heap memory held by the index, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        let entries: usize = self.geonames.capacity_bytes()
+            + self
+                .geonames
+                .values()
+                .map(|gn| {
+                    gn.name.capacity()
+                        + gn.adm1.capacity()
+                        + gn.adm2.capacity()
+                        + gn.adm3.capacity()
+                        + gn.adm4.capacity()
+                        + gn.wikipedia.as_ref().map_or(0, String::capacity)
+                        + gn.wikidata.as_ref().map_or(0, String::capacity)
+                })
+                .sum::<usize>();
+        let matches: usize = self
+            .search_matches
+            .iter()
+            .map(|m| {
+                std::mem::size_of::<Vec<MatchType>>()
+                    + m.capacity() * std::mem::size_of::<MatchType>()
+            })
+            .sum();
+        let ngrams = self.ngrams.as_ref().map_or(0, NgramIndex::memory_usage);
+        let tokens = self.tokens.as_ref().map_or(0, TokenIndex::memory_usage);
+        let entry_keys: usize = self
+            .entry_keys
+            .values()
+            .map(|gnds| std::mem::size_of::<(u64, Vec<u32>)>() + gnds.capacity() * 4)
+            .sum();
+        let spatial = self.spatial.memory_usage();
+        self.map.as_fst().size() + entries + matches + ngrams + tokens + entry_keys + spatial
+    }
+
+    /// The key with the given FST value.
+    ///
+    /// FST values are assigned in key order, so the output accumulated along any prefix is the
+    /// smallest value below it and the key can be found by a single walk from the root.
+    pub fn key(&self, gnd: u64) -> Option<String> {
+        let fst = self.map.as_fst();
+        let mut node = fst.root();
+        let mut value = 0;
+        let mut key = Vec::new();
+        loop {
+            if node.is_final() && value + node.final_output().value() == gnd {
+                return Some(String::from_utf8_lossy(&key).to_string());
+            }
+            let transition = node
+                .transitions()
+                .take_while(|t| value + t.out.value() <= gnd)
+                .last()?;
+            value += transition.out.value();
+            key.push(transition.inp);
+            node = fst.node(transition.addr);
+        }
+    }
+
+    /// The country and administrative divisions containing the entry. Divisions are only
+    /// resolved if `admin` is set, the country only if `country` is set.
+    pub fn hierarchy(&self, entry: &GeoNamesEntry, country: bool, admin: bool) -> Hierarchy {
+        let mut parents = self
+            .admin
+            .parents(entry)
+            .into_iter()
+            .map(|id| id.and_then(|id| self.geonames.get(&id)).cloned());
+        let mut next = || parents.next().flatten();
+        let country_entry = next();
+        let mut hierarchy = Hierarchy {
+            country: country.then_some(country_entry).flatten(),
+            ..Default::default()
+        };
+        if admin {
+            hierarchy.admin1 = next();
+            hierarchy.admin2 = next();
+            hierarchy.admin3 = next();
+            hierarchy.admin4 = next();
+        }
+        hierarchy
+    }
+
+    /// The name of the entry in the given language, falling back to its canonical name.
+    ///
+    /// Only languages included from the alternate names at build time are available.
+    pub fn display_name(&self, entry: &GeoNamesEntry, lang: &str) -> String {
+        self.localized
+            .get(entry.id, lang)
+            .unwrap_or(&entry.name)
+            .to_string()
+    }
+
+    /// All indexed names of the entry with the given id, including names inserted at runtime,
+    /// main names first.
+    pub fn names_for(&self, id: u64) -> Vec<MatchKey> {
+        let mut names: Vec<MatchKey> = self
+            .entry_keys
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .filter_map(|&gnd| {
+                let key = self.key(gnd as u64)?;
+                Some(
+                    self.search_matches[gnd as usize]
+                        .iter()
+                        .filter(|typ| typ.id() == id)
+                        .map(|typ| MatchKey::new(&key, typ))
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .flatten()
+            .collect();
+        let overlay = self.overlay.read().unwrap();
+        names.extend(
+            overlay
+                .names_for(id)
+                .map(|(key, typ)| MatchKey::new(key, typ)),
+        );
+        names.sort();
+        names
+    }
+
     pub fn find(&self, query: &str) -> Vec<GeoNamesSearchResult> {
-        self.map
+        let mut results: Vec<GeoNamesSearchResult> = self
+            .map
             .get(query)
             .map(|gnd| {
                 let matches = &self.search_matches[gnd as usize];
@@ -28,75 +435,574 @@ impl GeoNamesSearcher {
                     })
                     .collect()
             })
-            .unwrap_or_default()
+            .unwrap_or_default();
+        let overlay = self.overlay.read().unwrap();
+        results.extend(
+            overlay
+                .get(query, |_, _| true)
+                .into_iter()
+                .map(|(typ, gn)| GeoNamesSearchResult::new(query, typ, gn)),
+        );
+        results
     }
 
-    pub fn search(&self, query: impl Automaton) -> Vec<GeoNamesSearchResult> {
-        let mut stream = self.map.search(&query).into_stream();
+    /// Keys sharing the most trigrams with `query`, re-ranked by their edit distance to it.
+    /// Only matches accepted by `filter` are collected. Returns `None` if the searcher was built
+    /// without an n-gram index.
+    ///
+    /// At most `candidates` keys with a trigram similarity of at least `min_similarity` are
+    /// considered. Results are sorted by distance.
+    pub fn ngram_search(
+        &self,
+        query: &str,
+        metric: EditMetric,
+        max_dist: Option<u32>,
+        min_similarity: f32,
+        candidates: usize,
+        filter: impl Fn(&MatchType, &GeoNamesEntry) -> bool,
+    ) -> Option<Vec<GeoNamesSearchResultWithDist>> {
+        let index = self.ngrams.as_ref()?;
+        let mut results = Vec::new();
+        for (key, gnd) in index.candidates(query, min_similarity, candidates) {
+            let dist = metric.distance(query, key);
+            if let Some(distance) = max_dist {
+                if distance > 0 && dist > (distance as usize) {
+                    continue;
+                }
+            }
+            for (typ, gn) in self.filtered_matches(gnd, &filter) {
+                results.push(GeoNamesSearchResultWithDist::new(key, typ, gn, dist));
+            }
+        }
+        results.sort();
+        Some(results)
+    }
 
+    /// All keys containing every word of `query`, in any order. Only matches accepted by
+    /// `filter` are collected. Returns `None` if the searcher was built without a token index.
+    pub fn token_search(
+        &self,
+        query: &str,
+        filter: impl Fn(&MatchType, &GeoNamesEntry) -> bool,
+    ) -> Option<Vec<GeoNamesSearchResult>> {
+        let index = self.tokens.as_ref()?;
         let mut results = Vec::new();
-        while let Some((key, gnd)) = stream.next() {
-            let key = String::from_utf8_lossy(key).to_string();
-            let matches = &self.search_matches[gnd as usize];
-            results.extend(matches.iter().map(|typ| {
-                let gn = self.geonames.get(&typ.id()).unwrap();
-                GeoNamesSearchResult::new(&key, typ, gn)
-            }));
+        for gnd in index.matching(query) {
+            let Some(key) = self.key(gnd) else {
+                continue;
+            };
+            for (typ, gn) in self.filtered_matches(gnd, &filter) {
+                results.push(GeoNamesSearchResult::new(&key, typ, gn));
+            }
         }
         results.sort();
+        Some(results)
+    }
 
+    /// All entries within `radius_km` kilometers of the given point, nearest first.
+    pub fn nearby(&self, lat: f64, lon: f64, radius_km: f64) -> Vec<GeoNamesNearbyResult> {
+        let mut results: Vec<GeoNamesNearbyResult> = self
+            .spatial
+            .within(lat, lon, radius_km)
+            .filter_map(|id| {
+                let gn = self.geonames.get(&id).unwrap();
+                let distance_km = haversine_km(lat, lon, gn.latitude as f64, gn.longitude as f64);
+                (distance_km <= radius_km).then(|| GeoNamesNearbyResult {
+                    extras: ResultExtras::default(),
+                    entry: gn.clone(),
+                    distance_km,
+                })
+            })
+            .collect();
+        results.sort_by(|a, b| {
+            a.distance_km
+                .total_cmp(&b.distance_km)
+                .then(a.entry.id.cmp(&b.entry.id))
+        });
         results
     }
 
+    /// GeoNames id of the country containing the point, by the country shapes. `None` if no
+    /// country contains it or no shapes were loaded.
+    pub fn country_at(&self, lat: f64, lon: f64) -> Option<u64> {
+        self.shapes.as_ref()?.country_at(lat, lon)
+    }
+
+    /// The `k` entries nearest to the given point that are accepted by `filter`, nearest first.
+    pub fn knn(
+        &self,
+        lat: f64,
+        lon: f64,
+        k: usize,
+        filter: impl Fn(&GeoNamesEntry) -> bool,
+    ) -> Vec<GeoNamesNearbyResult> {
+        let mut results: Vec<GeoNamesNearbyResult> = self
+            .spatial
+            .nearest(lat, lon)
+            .map(|id| self.geonames.get(&id).unwrap())
+            .filter(|gn| filter(gn))
+            .take(k)
+            .map(|gn| GeoNamesNearbyResult {
+                extras: ResultExtras::default(),
+                entry: gn.clone(),
+                distance_km: haversine_km(lat, lon, gn.latitude as f64, gn.longitude as f64),
+            })
+            .collect();
+        results.sort_by(|a, b| {
+            a.distance_km
+                .total_cmp(&b.distance_km)
+                .then(a.entry.id.cmp(&b.entry.id))
+        });
+        results
+    }
+
+    /// All entries inside the bounding box, ordered by GeoNames id.
+    pub fn bbox(&self, bbox: &BoundingBox) -> Vec<GeoNamesEntry> {
+        let mut results: Vec<GeoNamesEntry> = self
+            .spatial
+            .in_bbox(bbox)
+            .map(|id| self.geonames.get(&id).unwrap())
+            .filter(|gn| bbox.contains(gn.latitude as f64, gn.longitude as f64))
+            .cloned()
+            .collect();
+        results.sort_by_key(|gn| gn.id);
+        results
+    }
+
+    /// The `limit` most prominent entries whose names start with the given prefix, one per GeoNames id.
+    ///
+    /// Uses the precomputed completions if available for this prefix and limit, otherwise ranks all
+    /// matching keys from the FST.
+    pub fn completions(&self, prefix: &str, limit: usize) -> Vec<GeoNamesSearchResult> {
+        let precomputed = self
+            .completions
+            .as_ref()
+            .filter(|table| limit <= table.top_k)
+            .and_then(|table| table.get(prefix));
+        let candidates: Vec<Completion> = if let Some(precomputed) = precomputed {
+            precomputed.to_vec()
+        } else {
+            let query = Str::new(prefix).starts_with();
+            let mut stream = self.map.search(&query).into_stream();
+            let mut candidates = Vec::new();
+            while let Some((key, gnd)) = stream.next() {
+                let key = String::from_utf8_lossy(key).to_string();
+                for typ in &self.search_matches[gnd as usize] {
+                    let gn = self.geonames.get(&typ.id()).unwrap();
+                    let rank = completion_rank(typ, gn);
+                    CompletionTable::insert(&mut candidates, &key, typ, rank, limit);
+                }
+            }
+            candidates
+        };
+
+        candidates
+            .iter()
+            .take(limit)
+            .map(|c| {
+                let gn = self.geonames.get(&c.typ.id()).unwrap();
+                GeoNamesSearchResult::new(&c.key, &c.typ, gn)
+            })
+            .collect()
+    }
+
+    /// Names within `max_dist` of the query, without the query itself, for "did you mean"
+    /// suggestions. Closer names come first, ties are broken by the highest population and then
+    /// by the number of entries carrying the name.
+    pub fn suggestions(
+        &self,
+        query: &str,
+        max_dist: u32,
+        state_limit: usize,
+        limit: usize,
+    ) -> Result<Vec<SpellingSuggestion>, LevenshteinError> {
+        let automaton = Levenshtein::new_with_limit(query, max_dist, state_limit)?;
+        let mut stream = self.map.search(&automaton).into_stream();
+        let mut suggestions = Vec::new();
+        while let Some((key, gnd)) = stream.next() {
+            let key = String::from_utf8_lossy(key).to_string();
+            if key == query {
+                continue;
+            }
+            let mut ids: Vec<u64> = self.search_matches[gnd as usize]
+                .iter()
+                .map(MatchType::id)
+                .collect();
+            ids.sort_unstable();
+            ids.dedup();
+            let population = ids
+                .iter()
+                .map(|id| self.geonames.get(id).unwrap().population)
+                .max()
+                .unwrap_or(0);
+            suggestions.push(SpellingSuggestion {
+                distance: EditMetric::default().distance(query, &key),
+                name: key,
+                entries: ids.len(),
+                population,
+            });
+        }
+        suggestions.sort_by(|a, b| {
+            a.distance
+                .cmp(&b.distance)
+                .then(b.population.cmp(&a.population))
+                .then(b.entries.cmp(&a.entries))
+                .then(a.name.cmp(&b.name))
+        });
+        suggestions.truncate(limit);
+        Ok(suggestions)
+    }
+
+    /// The matches of the key with the given FST value that are accepted by `filter`.
+    fn filtered_matches(
+        &self,
+        gnd: u64,
+        filter: impl Fn(&MatchType, &GeoNamesEntry) -> bool,
+    ) -> Vec<(&MatchType, &GeoNamesEntry)> {
+        self.search_matches[gnd as usize]
+            .iter()
+            .map(|typ| (typ, self.geonames.get(&typ.id()).unwrap()))
+            .filter(|(typ, gn)| filter(typ, gn))
+            .collect()
+    }
+
+    /// All results for keys matching the automaton, a single page of them, or only the top
+    /// results, depending on `collect`. Only matches accepted by `filter` are collected.
+    ///
+    /// Pages contain whole keys only, so a page may exceed the cursor's limit if a single key has
+    /// more matches than that. Results are sorted within the page. Gives up once `deadline` has
+    /// passed.
+    pub fn search(
+        &self,
+        query: impl Automaton,
+        collect: Collect,
+        deadline: Deadline,
+        filter: impl Fn(&MatchType, &GeoNamesEntry) -> bool,
+    ) -> Result<Page<GeoNamesSearchResult>, TimedOut> {
+        let query = deadline.guard(query);
+        let cursor = collect.cursor();
+        let mut stream = match cursor {
+            Some(cursor) => self.map.search(&query).ge(cursor.from).into_stream(),
+            None => self.map.search(&query).into_stream(),
+        };
+
+        let mut results = Ranked::new(collect);
+        let mut next_cursor = None;
+        while let Some((key, gnd)) = stream.next() {
+            let key = String::from_utf8_lossy(key).to_string();
+            // FST values are assigned in byte order
+            let rank = gnd as u32;
+            let matches = self.filtered_matches(gnd, &filter);
+            if cursor
+                .is_some_and(|c| !results.is_empty() && results.len() + matches.len() > c.limit)
+            {
+                next_cursor = Some(key);
+                break;
+            }
+            for (typ, gn) in matches {
+                results.push(rank, GeoNamesSearchResult::new(&key, typ, gn));
+            }
+        }
+        query.check()?;
+        let overlay = self.overlay.read().unwrap();
+        for (key, matches) in overlay.search(&query, overlay_range(cursor, &next_cursor), &filter) {
+            for (typ, gn) in matches {
+                results.push(OVERLAY_RANK, GeoNamesSearchResult::new(key, typ, gn));
+            }
+        }
+
+        Ok(Page {
+            results: results.into_sorted(),
+            next_cursor,
+            total: None,
+        })
+    }
+
+    /// Run `search` for each of the queries on the rayon thread pool, returning the outputs in the
+    /// order of the queries. The index is only read, so the queries share it without locking,
+    /// apart from the read lock of the overlay.
+    pub fn search_many<Q, T>(
+        &self,
+        queries: Vec<Q>,
+        search: impl Fn(&Self, Q) -> T + Sync,
+    ) -> Vec<T>
+    where
+        Q: Send,
+        T: Send,
+    {
+        queries
+            .into_par_iter()
+            .map(|query| search(self, query))
+            .collect()
+    }
+
+    /// Like `search`, but hands each match to `emit` as it comes off the FST stream instead of
+    /// collecting and sorting all results, so results arrive in key order. Stops as soon as `emit`
+    /// returns `false`, and gives up once `deadline` has passed.
+    pub fn search_each(
+        &self,
+        query: impl Automaton,
+        deadline: Deadline,
+        filter: impl Fn(&MatchType, &GeoNamesEntry) -> bool,
+        mut emit: impl FnMut(&str, &MatchType, &GeoNamesEntry) -> bool,
+    ) -> Result<(), TimedOut> {
+        let query = deadline.guard(query);
+        let overlay = self.overlay.read().unwrap();
+        let mut overlay = overlay
+            .search(&query, (Bound::Unbounded, Bound::Unbounded), &filter)
+            .peekable();
+        let mut emit_all = |key: &str, mut matches: Vec<(&MatchType, &GeoNamesEntry)>| {
+            matches.sort_by_key(|(typ, _)| *typ);
+            matches.into_iter().all(|(typ, gn)| emit(key, typ, gn))
+        };
+
+        let mut stream = self.map.search(&query).into_stream();
+        while let Some((key, gnd)) = stream.next() {
+            let key = String::from_utf8_lossy(key);
+            // Both are in byte order, so overlay keys are merged in before the first larger key
+            while let Some((overlay_key, matches)) =
+                overlay.next_if(|(overlay_key, _)| overlay_key.as_bytes() < key.as_bytes())
+            {
+                if !emit_all(overlay_key, matches) {
+                    return Ok(());
+                }
+            }
+            if !emit_all(&key, self.filtered_matches(gnd, &filter)) {
+                return Ok(());
+            }
+        }
+        query.check()?;
+        for (key, matches) in overlay {
+            if !emit_all(key, matches) {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands each key matched by `query` to `emit` in key order, without its matches. Keys of the
+    /// overlay are included, each key is emitted once. Stops as soon as `emit` returns `false`.
+    pub fn keys_each(&self, query: impl Automaton, mut emit: impl FnMut(&str) -> bool) {
+        let overlay = self.overlay.read().unwrap();
+        let mut overlay = overlay
+            .search(&query, (Bound::Unbounded, Bound::Unbounded), |_, _| true)
+            .map(|(key, _)| key)
+            .peekable();
+
+        let mut stream = self.map.search(&query).into_stream();
+        while let Some((key, _)) = stream.next() {
+            let key = String::from_utf8_lossy(key);
+            while let Some(overlay_key) =
+                overlay.next_if(|overlay_key| overlay_key.as_bytes() < key.as_bytes())
+            {
+                if !emit(overlay_key) {
+                    return;
+                }
+            }
+            overlay.next_if(|overlay_key| overlay_key.as_bytes() == key.as_bytes());
+            if !emit(&key) {
+                return;
+            }
+        }
+        for key in overlay {
+            if !emit(key) {
+                return;
+            }
+        }
+    }
+
+    /// The keys occurring in `text`, scanning it from left to right for the longest key starting
+    /// at each word. Keys have to end at a word boundary and found keys do not overlap. Keys
+    /// without matches accepted by `filter` are skipped in favor of shorter ones.
+    pub fn scan(
+        &self,
+        text: &str,
+        filter: impl Fn(&MatchType, &GeoNamesEntry) -> bool,
+    ) -> Vec<TextMatch> {
+        let overlay = self.overlay.read().unwrap();
+        let fst = self.map.as_fst();
+        let mut found = Vec::new();
+        let mut from = 0;
+        while let Some(start) = next_word_start(text, from) {
+            let rest = &text[start..];
+            // Byte lengths of the keys that are prefixes of the rest, with their FST values
+            let mut keys: Vec<(usize, Option<u64>)> = Vec::new();
+            let (mut node, mut value) = (fst.root(), 0);
+            for (i, &byte) in rest.as_bytes().iter().enumerate() {
+                let Some(index) = node.find_input(byte) else {
+                    break;
+                };
+                let transition = node.transition(index);
+                value += transition.out.value();
+                node = fst.node(transition.addr);
+                if node.is_final() && is_word_end(rest, i + 1) {
+                    keys.push((i + 1, Some(value + node.final_output().value())));
+                }
+            }
+            keys.extend(
+                overlay
+                    .prefixes_of(rest)
+                    .map(str::len)
+                    .filter(|&len| is_word_end(rest, len))
+                    .map(|len| (len, None)),
+            );
+            keys.sort_by_key(|&(len, _)| Reverse(len));
+
+            let longest = keys.chunk_by(|a, b| a.0 == b.0).find_map(|keys| {
+                let len = keys[0].0;
+                let key = &rest[..len];
+                let mut matches = overlay.get(key, &filter);
+                for gnd in keys.iter().filter_map(|(_, gnd)| *gnd) {
+                    matches.extend(self.filtered_matches(gnd, &filter));
+                }
+                matches.sort_by_key(|(typ, _)| *typ);
+                let results: Vec<_> = matches
+                    .into_iter()
+                    .map(|(typ, gn)| GeoNamesSearchResult::new(key, typ, gn))
+                    .collect();
+                (!results.is_empty()).then_some((len, results))
+            });
+            from = match longest {
+                Some((len, results)) => {
+                    found.push(TextMatch {
+                        start,
+                        end: start + len,
+                        results,
+                    });
+                    start + len
+                }
+                None => start + rest.chars().next().map_or(1, char::len_utf8),
+            };
+        }
+        found
+    }
+
+    /// Like `search`, but with the edit distance of each key to `raw` under `metric`.
+    #[allow(clippy::too_many_arguments)]
     pub fn search_with_dist(
         &self,
         query: impl Automaton,
         raw: &str,
+        metric: EditMetric,
         max_dist: Option<u32>,
-    ) -> Vec<GeoNamesSearchResultWithDist> {
-        let mut stream = self.map.search(&query).into_stream();
-        let mut results = Vec::new();
+        collect: Collect,
+        deadline: Deadline,
+        filter: impl Fn(&MatchType, &GeoNamesEntry) -> bool,
+    ) -> Result<Page<GeoNamesSearchResultWithDist>, TimedOut> {
+        let query = deadline.guard(query);
+        let cursor = collect.cursor();
+        let mut stream = match cursor {
+            Some(cursor) => self.map.search(&query).ge(cursor.from).into_stream(),
+            None => self.map.search(&query).into_stream(),
+        };
+        let mut results = Ranked::new(collect);
+        let mut next_cursor = None;
         while let Some((key, gnd)) = stream.next() {
             let key = String::from_utf8_lossy(key).to_string();
-            let dist = levenshtein_dist(raw, &key);
-            if let Some(distance) = max_dist {
-                if distance > 0 && dist > (distance as usize) {
-                    continue;
-                }
+            let Some(dist) = bounded_distance(metric, raw, &key, max_dist) else {
+                continue;
+            };
+            // FST values are assigned in byte order
+            let rank = gnd as u32;
+            let matches = self.filtered_matches(gnd, &filter);
+            if cursor
+                .is_some_and(|c| !results.is_empty() && results.len() + matches.len() > c.limit)
+            {
+                next_cursor = Some(key);
+                break;
             }
-            let matches = &self.search_matches[gnd as usize];
-            for typ in matches {
-                let gn: &GeoNamesEntry = self.geonames.get(&typ.id()).unwrap();
-                results.push(GeoNamesSearchResultWithDist::new(&key, typ, gn, dist));
+            for (typ, gn) in matches {
+                results.push(rank, GeoNamesSearchResultWithDist::new(&key, typ, gn, dist));
+            }
+        }
+        query.check()?;
+        let overlay = self.overlay.read().unwrap();
+        for (key, matches) in overlay.search(&query, overlay_range(cursor, &next_cursor), &filter) {
+            let Some(dist) = bounded_distance(metric, raw, key, max_dist) else {
+                continue;
+            };
+            for (typ, gn) in matches {
+                results.push(
+                    OVERLAY_RANK,
+                    GeoNamesSearchResultWithDist::new(key, typ, gn, dist),
+                );
             }
         }
-        results.sort();
 
-        results
+        Ok(Page {
+            results: results.into_sorted(),
+            next_cursor,
+            total: None,
+        })
     }
 
     pub fn build(
         gn_paths: Vec<String>,
         gn_alternate_paths: Option<&Vec<String>>,
         gn_alternate_languages: Option<&Vec<String>>,
+        options: &BuildOptions,
     ) -> Result<GeoNamesSearcher, anyhow::Error> {
+        let datasets = Datasets {
+            geonames: gn_paths.clone(),
+            alternate: gn_alternate_paths.cloned().unwrap_or_default(),
+            languages: gn_alternate_languages.cloned(),
+            alternate_languages: options.alternate_languages.clone(),
+            synonyms: options.synonyms.clone(),
+            shapes: options.shapes.clone(),
+        };
+        let mut report = BuildReport::default();
+        let mut row_errors = RowErrors::new(options.lenient);
+
         tracing::info!("Reading GeoNames from {} files", gn_paths.len());
         let mut query_pairs: Vec<(String, MatchType)> = Vec::new();
         let mut geonames: HashMap<u64, GeoNamesEntry> = HashMap::new();
         for path in gn_paths {
-            parse_geonames_file(&path, &mut query_pairs, &mut geonames)?;
+            parse_geonames_file(
+                &path,
+                &mut query_pairs,
+                &mut geonames,
+                &options.columns,
+                &options.exclusions,
+                &mut row_errors,
+            )?;
         }
         tracing::info!("Read {} GeoNames", query_pairs.len());
+        if row_errors.excluded > 0 {
+            tracing::info!(
+                "Excluded {} GeoNames by feature class or code",
+                row_errors.excluded
+            );
+        }
 
         if let Some(paths) = gn_alternate_paths {
             tracing::info!("Reading alternate GeoNames from {} files", paths.len());
             for path in paths {
-                parse_alternate_names_file(
+                // Parse into a scratch buffer first, so a file that fails halfway
+                // through does not leave a partial set of names behind.
+                let mut alternate_pairs = Vec::new();
+                let mut links = Vec::new();
+                match parse_alternate_names_file(
                     path,
-                    &mut query_pairs,
+                    &mut alternate_pairs,
+                    &mut links,
                     &geonames,
-                    gn_alternate_languages,
-                )?;
+                    options
+                        .alternate_languages
+                        .get(path)
+                        .or(gn_alternate_languages),
+                    &mut row_errors,
+                ) {
+                    Ok(()) => {
+                        query_pairs.append(&mut alternate_pairs);
+                        for (id, link) in links {
+                            if let Some(entry) = geonames.get_mut(&id) {
+                                link.apply(entry);
+                            }
+                        }
+                    }
+                    Err(error) if options.alternate_optional => report.warn(path, error),
+                    Err(error) => return Err(error),
+                }
             }
             tracing::info!(
                 "Read {} search terms (including alternate names)",
@@ -104,6 +1010,47 @@ impl GeoNamesSearcher {
             );
         }
 
+        if !options.synonyms.is_empty() {
+            tracing::info!("Reading synonyms from {} files", options.synonyms.len());
+            for path in &options.synonyms {
+                parse_synonyms_file(path, &mut query_pairs, &geonames, &mut row_errors)?;
+            }
+            tracing::info!(
+                "Read {} search terms (including synonyms)",
+                query_pairs.len()
+            );
+        }
+        let geonames = EntryStore::from(geonames);
+
+        if options.lenient {
+            tracing::info!(
+                "Skipped {} of {} rows: {:?}",
+                row_errors.skipped,
+                row_errors.rows,
+                row_errors.categories
+            );
+        }
+        let shapes = match &options.shapes {
+            Some(path) => {
+                tracing::info!("Reading country shapes from {path}");
+                let mut shapes = Vec::new();
+                parse_shapes_file(path, &mut shapes, &mut row_errors)?;
+                let shapes = CountryShapes::new(shapes);
+                tracing::info!("Read {} country shapes", shapes.number_of_shapes());
+                Some(shapes)
+            }
+            None => None,
+        };
+
+        report.degraded |= row_errors.skipped > 0;
+        report.rows = row_errors;
+
+        let localized = LocalizedNames::build(query_pairs.iter());
+        tracing::info!(
+            "Collected localized names of {} entries",
+            localized.number_of_entries()
+        );
+
         tracing::info!("Sorting GeoNames");
         query_pairs.sort_by(|a, b| a.0.cmp(&b.0));
 
@@ -127,6 +1074,73 @@ impl GeoNamesSearcher {
             }
         }
 
+        let mut entry_keys: HashMap<u64, Vec<u32>> = HashMap::new();
+        for (gnd, matches) in search_matches.iter().enumerate() {
+            for typ in matches {
+                let gnds = entry_keys.entry(typ.id()).or_default();
+                if gnds.last() != Some(&(gnd as u32)) {
+                    gnds.push(gnd as u32);
+                }
+            }
+        }
+
+        let completions = if options.completions_prefix_len > 0 {
+            tracing::info!(
+                "Precomputing top-{} completions for prefixes up to {} characters",
+                options.completions_top_k,
+                options.completions_prefix_len
+            );
+            let table = CompletionTable::build(
+                search_terms
+                    .iter()
+                    .map(String::as_str)
+                    .zip(search_matches.iter()),
+                &geonames,
+                options.completions_prefix_len,
+                options.completions_top_k,
+            );
+            tracing::info!(
+                "Precomputed completions for {} prefixes",
+                table.number_of_prefixes()
+            );
+            Some(table)
+        } else {
+            None
+        };
+
+        let ngrams = if options.ngram_index {
+            tracing::info!("Building trigram index");
+            let index = NgramIndex::build(search_terms.iter().map(String::as_str));
+            tracing::info!(
+                "Built trigram index with {} trigrams",
+                index.number_of_ngrams()
+            );
+            Some(index)
+        } else {
+            None
+        };
+
+        let tokens = if options.token_index {
+            tracing::info!("Building token index");
+            let index = TokenIndex::build(search_terms.iter().map(String::as_str));
+            tracing::info!("Built token index with {} tokens", index.number_of_tokens());
+            Some(index)
+        } else {
+            None
+        };
+
+        let admin = AdminIndex::build(&geonames);
+        tracing::info!(
+            "Indexed {} countries and administrative divisions",
+            admin.number_of_codes()
+        );
+
+        let spatial = SpatialIndex::build(&geonames);
+        tracing::info!(
+            "Built spatial index with {} points",
+            spatial.number_of_points()
+        );
+
         tracing::info!("Building FST");
         let bytes = {
             let mut build = MapBuilder::memory();
@@ -144,6 +1158,20 @@ impl GeoNamesSearcher {
             map,
             geonames,
             search_matches,
+            completions,
+            ngrams,
+            tokens,
+            admin,
+            spatial,
+            shapes,
+            localized,
+            entry_keys,
+            score_weights: ScoreWeights::default(),
+            max_results: None,
+            datasets,
+            report,
+            manifest: None,
+            overlay: RwLock::default(),
         })
     }
 }
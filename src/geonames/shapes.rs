@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+
+use crate::geonames::geo::{BoundingBox, Polygon};
+
+/// A country shape and its bounding box.
+#[derive(Debug, Serialize, Deserialize)]
+struct CountryShape {
+    id: u64,
+    bounds: BoundingBox,
+    polygon: Polygon,
+}
+
+/// Country polygons from GeoNames' `shapes_simplified_low.json`, for resolving the country of a
+/// point. Unlike the nearest populated place, the polygons give the right country near borders
+/// and coastlines.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CountryShapes {
+    shapes: Vec<CountryShape>,
+}
+
+impl CountryShapes {
+    pub fn new(shapes: Vec<(u64, Polygon)>) -> Self {
+        let mut shapes: Vec<CountryShape> = shapes
+            .into_iter()
+            .map(|(id, polygon)| CountryShape {
+                id,
+                bounds: polygon.bounds(),
+                polygon,
+            })
+            .collect();
+        shapes.sort_by_key(|shape| shape.id);
+        Self { shapes }
+    }
+
+    /// GeoNames id of the country containing the point. If the simplified shapes of several
+    /// countries contain it, the one with the smallest bounding box is preferred, as enclaves are
+    /// not always cut out of the surrounding country.
+    pub fn country_at(&self, lat: f64, lon: f64) -> Option<u64> {
+        let area =
+            |bbox: &BoundingBox| (bbox.max_lat - bbox.min_lat) * (bbox.max_lon - bbox.min_lon);
+        self.shapes
+            .iter()
+            .filter(|shape| shape.bounds.contains(lat, lon) && shape.polygon.contains(lat, lon))
+            .min_by(|a, b| area(&a.bounds).total_cmp(&area(&b.bounds)))
+            .map(|shape| shape.id)
+    }
+
+    pub fn number_of_shapes(&self) -> usize {
+        self.shapes.len()
+    }
+
+    /// Rough estimate of the heap memory held by the shapes, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.shapes
+            .iter()
+            .map(|shape| {
+                std::mem::size_of::<CountryShape>()
+                    + shape.polygon.number_of_positions() * std::mem::size_of::<[f64; 2]>()
+            })
+            .sum()
+    }
+}
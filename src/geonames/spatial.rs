@@ -0,0 +1,147 @@
+use rstar::primitives::GeomWithData;
+use rstar::{RTree, AABB};
+
+use crate::geonames::geo::{is_valid_coordinate, BoundingBox, EARTH_RADIUS_KM};
+use crate::geonames::store::EntryStore;
+
+/// An entry's position on the unit sphere, with its GeoNames id.
+type Point = GeomWithData<[f32; 3], u64>;
+
+/// R-tree over the coordinates of all entries, for radius, bounding box and nearest-neighbour
+/// queries without scanning every entry.
+///
+/// Coordinates are stored as points on the unit sphere, so that the straight-line distance
+/// between two points increases with their great-circle distance and the antimeridian needs no
+/// special treatment. Entries without valid coordinates are not indexed.
+pub struct SpatialIndex {
+    tree: RTree<Point>,
+}
+
+/// Cartesian coordinates of a latitude/longitude pair on the unit sphere.
+fn to_cartesian(lat: f64, lon: f64) -> [f32; 3] {
+    let (lat, lon) = (lat.to_radians(), lon.to_radians());
+    [
+        (lat.cos() * lon.cos()) as f32,
+        (lat.cos() * lon.sin()) as f32,
+        lat.sin() as f32,
+    ]
+}
+
+/// Range of `f` over the interval of angles in degrees, given the angles where `f` has its
+/// extrema.
+fn range(from: f64, to: f64, f: impl Fn(f64) -> f64, extrema: &[f64]) -> (f64, f64) {
+    extrema
+        .iter()
+        .copied()
+        .filter(|angle| (from..=to).contains(angle))
+        .chain([from, to])
+        .map(|angle| f(angle.to_radians()))
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| {
+            (min.min(value), max.max(value))
+        })
+}
+
+/// Smallest box on the unit sphere containing all points of the bounding box.
+fn envelope(bbox: &BoundingBox) -> AABB<[f32; 3]> {
+    // Unwrap boxes crossing the antimeridian, the trigonometric functions do not care
+    let max_lon = if bbox.min_lon <= bbox.max_lon {
+        bbox.max_lon
+    } else {
+        bbox.max_lon + 360.0
+    };
+    let lon_extrema = [-180.0, -90.0, 0.0, 90.0, 180.0, 270.0, 360.0, 450.0, 540.0];
+    let (cos_lat_min, cos_lat_max) = range(bbox.min_lat, bbox.max_lat, f64::cos, &[0.0]);
+    let (cos_lon_min, cos_lon_max) = range(bbox.min_lon, max_lon, f64::cos, &lon_extrema);
+    let (sin_lon_min, sin_lon_max) = range(bbox.min_lon, max_lon, f64::sin, &lon_extrema);
+
+    // cos(lat) is never negative, so the extremes of the products are at the extremes of the
+    // factors
+    let products = |min: f64, max: f64| {
+        [
+            cos_lat_min * min,
+            cos_lat_min * max,
+            cos_lat_max * min,
+            cos_lat_max * max,
+        ]
+        .into_iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), value| {
+            (lo.min(value), hi.max(value))
+        })
+    };
+    let (x_min, x_max) = products(cos_lon_min, cos_lon_max);
+    let (y_min, y_max) = products(sin_lon_min, sin_lon_max);
+    let (z_min, z_max) = (
+        bbox.min_lat.to_radians().sin(),
+        bbox.max_lat.to_radians().sin(),
+    );
+
+    // Widen the box slightly to make up for the lower precision of the stored points
+    let margin = 1e-6;
+    AABB::from_corners(
+        [
+            (x_min - margin) as f32,
+            (y_min - margin) as f32,
+            (z_min - margin) as f32,
+        ],
+        [
+            (x_max + margin) as f32,
+            (y_max + margin) as f32,
+            (z_max + margin) as f32,
+        ],
+    )
+}
+
+/// Squared straight-line distance on the unit sphere between two points that are `radius_km`
+/// apart on the surface of the earth.
+fn chord_2(radius_km: f64) -> f32 {
+    let angle = (radius_km / EARTH_RADIUS_KM).min(std::f64::consts::PI);
+    let chord = 2.0 * (angle / 2.0).sin();
+    // Widen the radius slightly to make up for the lower precision of the stored points
+    ((chord + 1e-6) * (chord + 1e-6)) as f32
+}
+
+impl SpatialIndex {
+    pub fn build(geonames: &EntryStore) -> Self {
+        let points: Vec<Point> = geonames
+            .values()
+            .filter(|gn| is_valid_coordinate(gn.latitude as f64, gn.longitude as f64))
+            .map(|gn| Point::new(to_cartesian(gn.latitude as f64, gn.longitude as f64), gn.id))
+            .collect();
+        Self {
+            tree: RTree::bulk_load(points),
+        }
+    }
+
+    /// Ids of the entries that may be within `radius_km` of the point. Callers have to check
+    /// the exact distance, as the candidates include entries slightly outside of the radius.
+    pub fn within(&self, lat: f64, lon: f64, radius_km: f64) -> impl Iterator<Item = u64> + '_ {
+        self.tree
+            .locate_within_distance(to_cartesian(lat, lon), chord_2(radius_km))
+            .map(|point| point.data)
+    }
+
+    /// Ids of the entries that may be inside the bounding box. Callers have to check the exact
+    /// position, as the candidates include entries slightly outside of the box.
+    pub fn in_bbox(&self, bbox: &BoundingBox) -> impl Iterator<Item = u64> + '_ {
+        self.tree
+            .locate_in_envelope(envelope(bbox))
+            .map(|point| point.data)
+    }
+
+    /// Ids of all indexed entries, nearest to the point first.
+    pub fn nearest(&self, lat: f64, lon: f64) -> impl Iterator<Item = u64> + '_ {
+        self.tree
+            .nearest_neighbor_iter(to_cartesian(lat, lon))
+            .map(|point| point.data)
+    }
+
+    pub fn number_of_points(&self) -> usize {
+        self.tree.size()
+    }
+
+    /// Rough estimate of the heap memory held by the index, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        // Leaves plus roughly one inner node per leaf node of up to six children
+        self.tree.size() * std::mem::size_of::<Point>() * 7 / 6
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::geonames::data::GeoNamesEntry;
+
+/// The entries of the index, stored contiguously in the order of their ids.
+///
+/// Lookups binary search a dense array of the ids, which is far smaller than a hash map of the
+/// entries and keeps the probes of `get` within a few cache lines.
+#[derive(Debug, Clone, Default)]
+pub struct EntryStore {
+    ids: Vec<u64>,
+    entries: Vec<GeoNamesEntry>,
+}
+
+impl EntryStore {
+    pub fn get(&self, id: &u64) -> Option<&GeoNamesEntry> {
+        let i = self.ids.binary_search(id).ok()?;
+        Some(&self.entries[i])
+    }
+
+    pub fn contains_key(&self, id: &u64) -> bool {
+        self.ids.binary_search(id).is_ok()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// The entries in the order of their ids.
+    pub fn values(&self) -> std::slice::Iter<'_, GeoNamesEntry> {
+        self.entries.iter()
+    }
+
+    /// Heap memory held by the id and entry arrays, without the strings of the entries.
+    pub fn capacity_bytes(&self) -> usize {
+        self.ids.capacity() * std::mem::size_of::<u64>()
+            + self.entries.capacity() * std::mem::size_of::<GeoNamesEntry>()
+    }
+}
+
+impl From<HashMap<u64, GeoNamesEntry>> for EntryStore {
+    fn from(geonames: HashMap<u64, GeoNamesEntry>) -> Self {
+        let mut entries: Vec<(u64, GeoNamesEntry)> = geonames.into_iter().collect();
+        entries.sort_unstable_by_key(|(id, _)| *id);
+        let (ids, entries) = entries.into_iter().unzip();
+        Self { ids, entries }
+    }
+}
+
+// Serialized as a map from ids to entries, as in artifacts of earlier versions
+impl Serialize for EntryStore {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.ids.iter().zip(&self.entries))
+    }
+}
+
+impl<'de> Deserialize<'de> for EntryStore {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        HashMap::<u64, GeoNamesEntry>::deserialize(deserializer).map(Self::from)
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Inverted index from the words of each key to the keys containing them.
+///
+/// Allows matching multi-word queries regardless of word order, e.g. "Main Frankfurt" matches
+/// "Frankfurt am Main". Keys are indexed case-insensitively by their FST value.
+#[derive(Serialize, Deserialize)]
+pub struct TokenIndex {
+    postings: HashMap<String, Vec<u32>>,
+}
+
+/// The distinct lowercased words of a name.
+pub fn tokenize(name: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = name
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    tokens.sort_unstable();
+    tokens.dedup();
+    tokens
+}
+
+impl TokenIndex {
+    /// Index the keys, which have to be given in FST value order.
+    pub fn build<'a>(keys: impl Iterator<Item = &'a str>) -> Self {
+        let mut postings: HashMap<String, Vec<u32>> = HashMap::new();
+        for (gnd, key) in keys.enumerate() {
+            for token in tokenize(key) {
+                postings.entry(token).or_default().push(gnd as u32);
+            }
+        }
+        postings.shrink_to_fit();
+        Self { postings }
+    }
+
+    /// FST values of the keys containing all words of the query, in any order. Returns nothing
+    /// if the query has no words.
+    pub fn matching(&self, query: &str) -> Vec<u64> {
+        let mut lists: Vec<&Vec<u32>> = Vec::new();
+        for token in tokenize(query) {
+            match self.postings.get(&token) {
+                Some(list) => lists.push(list),
+                None => return Vec::new(),
+            }
+        }
+        // Intersect starting from the rarest word
+        lists.sort_by_key(|list| list.len());
+        let Some((first, rest)) = lists.split_first() else {
+            return Vec::new();
+        };
+        first
+            .iter()
+            .filter(|gnd| rest.iter().all(|list| list.binary_search(gnd).is_ok()))
+            .map(|&gnd| gnd as u64)
+            .collect()
+    }
+
+    pub fn number_of_tokens(&self) -> usize {
+        self.postings.len()
+    }
+
+    /// Rough estimate of the heap memory held by the index, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.postings
+            .iter()
+            .map(|(token, gnds)| token.capacity() + gnds.capacity() * 4)
+            .sum()
+    }
+}
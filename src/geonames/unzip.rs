@@ -0,0 +1,84 @@
+use std::io::{self, BufRead, Read};
+
+use anyhow::{anyhow, bail, Context};
+use flate2::bufread::DeflateDecoder;
+
+const LOCAL_FILE_HEADER: [u8; 4] = *b"PK\x03\x04";
+const DATA_DESCRIPTOR: [u8; 4] = *b"PK\x07\x08";
+/// General purpose flag of entries whose sizes follow their data instead of the header.
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+
+/// Stream the member `name` out of a zip archive, like `DE.txt` out of the `DE.zip` dumps of
+/// GeoNames, without seeking, so that archives can be read while they are downloaded.
+///
+/// The local headers of the entries are read in order and the entries before `name` are skipped.
+/// Deflated entries end by themselves, so entries whose sizes only follow their data are
+/// supported too.
+pub fn open_member(
+    mut reader: impl BufRead + 'static,
+    name: &str,
+) -> anyhow::Result<Box<dyn Read>> {
+    loop {
+        let mut signature = [0; 4];
+        reader
+            .read_exact(&mut signature)
+            .context("Truncated zip archive")?;
+        if signature != LOCAL_FILE_HEADER {
+            // The central directory follows the last entry
+            bail!(
+                "The zip archive contains no {name}, unpack it and pass the file to read instead"
+            );
+        }
+        let mut header = [0; 26];
+        reader
+            .read_exact(&mut header)
+            .context("Truncated zip archive")?;
+        let u16_at = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]);
+        let (flags, method) = (u16_at(2), u16_at(4));
+        let compressed_size = u32::from_le_bytes(header[14..18].try_into().unwrap());
+        let mut file_name = vec![0; usize::from(u16_at(22))];
+        reader.read_exact(&mut file_name)?;
+        io::copy(
+            &mut (&mut reader).take(u64::from(u16_at(24))),
+            &mut io::sink(),
+        )?;
+        let file_name = String::from_utf8_lossy(&file_name);
+
+        let descriptor = flags & FLAG_DATA_DESCRIPTOR != 0;
+        if file_name == name {
+            tracing::info!("Reading {name} from zip archive");
+            return match method {
+                METHOD_DEFLATED => Ok(Box::new(DeflateDecoder::new(reader))),
+                METHOD_STORED if !descriptor => {
+                    Ok(Box::new(reader.take(u64::from(compressed_size))))
+                }
+                method => Err(anyhow!(
+                    "Unsupported compression method {method} of {name} in the zip archive"
+                )),
+            };
+        }
+
+        match method {
+            METHOD_DEFLATED => {
+                io::copy(&mut DeflateDecoder::new(&mut reader), &mut io::sink())?;
+            }
+            METHOD_STORED if !descriptor => {
+                io::copy(
+                    &mut (&mut reader).take(u64::from(compressed_size)),
+                    &mut io::sink(),
+                )?;
+            }
+            method => bail!("Cannot skip {file_name} stored with compression method {method}"),
+        }
+        if descriptor {
+            // CRC-32 and sizes, optionally preceded by a signature
+            let skip = match reader.fill_buf()?.starts_with(&DATA_DESCRIPTOR) {
+                true => 16,
+                false => 12,
+            };
+            io::copy(&mut (&mut reader).take(skip), &mut io::sink())?;
+        }
+    }
+}
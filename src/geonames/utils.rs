@@ -1,10 +1,13 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::f32;
 use std::io::{BufReader, Read};
 use std::path::Path;
 use std::{collections::HashMap, fs::File};
-use std::f32;
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
+use csv::StringRecord;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use tracing::{event, Level};
 
 #[cfg(feature = "bzip2")]
@@ -13,14 +16,55 @@ use bzip2_rs::DecoderReader as Bzip2Decoder;
 use flate2::bufread::GzDecoder;
 #[cfg(feature = "xz")]
 use xz::bufread::XzDecoder;
+#[cfg(feature = "zstd")]
+use zstd::stream::read::Decoder as ZstdDecoder;
 
+use super::columns::ColumnMapping;
 use super::data::{GeoNamesEntry, MatchType};
+use super::geo::Polygon;
+use super::symbol::Symbol;
+
+/// Path sentinel for reading (uncompressed) data from stdin.
+pub const STDIN_PATH: &str = "-";
+
+/// Returns true if the path is an HTTP(S) URL rather than a local file.
+pub fn is_remote(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+#[cfg(feature = "http")]
+fn open_remote(url: &str) -> anyhow::Result<Box<dyn Read>> {
+    tracing::info!("Downloading {url}");
+    let response = ureq::get(url)
+        .call()
+        .map_err(|e| anyhow!("Could not download {url}: {e}"))?;
+    Ok(Box::new(response.into_reader()))
+}
+
+#[cfg(not(feature = "http"))]
+fn open_remote(url: &str) -> anyhow::Result<Box<dyn Read>> {
+    Err(anyhow!(
+        "This binary was not compiled with the http feature enabled! Cannot read {url}."
+    ))
+}
 
 pub fn get_reader(path: &Path) -> anyhow::Result<Box<dyn Read>> {
-    let file = File::open(path).expect("Could not open file");
-    let buf_reader: BufReader<File> = BufReader::new(file);
+    if path == Path::new(STDIN_PATH) {
+        // Streams are expected to be decompressed already, e.g. `zcat allCountries.zip | grep ...`
+        return Ok(Box::new(BufReader::new(std::io::stdin())));
+    }
 
-    let extension = match Path::new(path).extension() {
+    let source: Box<dyn Read> = match path.to_str() {
+        Some(url) if is_remote(url) => open_remote(url)?,
+        _ => Box::new(File::open(path).map_err(|e| anyhow!("Could not open file {path:?}: {e}"))?),
+    };
+    let buf_reader = BufReader::new(source);
+
+    // Query parameters of URLs are not part of the extension
+    let file_name = path
+        .to_str()
+        .map(|p| p.split(['?', '#']).next().unwrap_or(p));
+    let extension = match file_name.and_then(|p| Path::new(p).extension()) {
         None => "<none>",
         Some(ext) => ext.to_str().unwrap(),
     };
@@ -28,26 +72,51 @@ pub fn get_reader(path: &Path) -> anyhow::Result<Box<dyn Read>> {
         // No compression, the only path that is always supported
         "txt" | "<none>" => Ok(Box::new(buf_reader)),
 
-        // GeoNames dumps come in zip files, which in turn may contain multiple files.
-        // We require the user to unpack the zip file first, passing only the required files into the program.
-        "zip" => Err(anyhow!("Unpacked GeoNames dump files are not supported! Please unpack the GeoNames zip file first.")),
+        // GeoNames dumps come in zip files that also contain a readme, e.g. DE.zip with DE.txt,
+        // so only the member named like the archive is read
+        #[cfg(feature = "gzip")]
+        "zip" => {
+            let stem = file_name
+                .and_then(|p| Path::new(p).file_stem())
+                .and_then(|s| s.to_str());
+            let member = format!("{}.txt", stem.unwrap_or_default());
+            super::unzip::open_member(buf_reader, &member)
+                .with_context(|| format!("Could not read {path:?}"))
+        }
+        #[cfg(not(feature = "gzip"))]
+        "zip" => Err(anyhow!(
+            "This binary was not compiled with the gzip feature enabled! Cannot read {path:?}."
+        )),
 
         #[cfg(feature = "bzip2")]
         "bz2" => Ok(Box::new(Bzip2Decoder::new(buf_reader))),
         #[cfg(not(feature = "bzip2"))]
-        "bz2" => Err(anyhow!("This binary was not compiled with the bzip2 feature enabled! Cannot read {path:?}.")),
-        
+        "bz2" => Err(anyhow!(
+            "This binary was not compiled with the bzip2 feature enabled! Cannot read {path:?}."
+        )),
+
         #[cfg(feature = "gzip")]
         "gz" => Ok(Box::new(GzDecoder::new(buf_reader))),
         #[cfg(not(feature = "gzip"))]
-        "gz" => Err(anyhow!("This binary was not compiled with the gzip feature enabled! Cannot read {path:?}.")),
+        "gz" => Err(anyhow!(
+            "This binary was not compiled with the gzip feature enabled! Cannot read {path:?}."
+        )),
 
         #[cfg(feature = "xz")]
         "xz" => Ok(Box::new(XzDecoder::new(buf_reader))),
         #[cfg(not(feature = "xz"))]
-        "xz" => Err(anyhow!("This binary was not compiled with the xz feature enabled! Cannot read {path:?}.")),
+        "xz" => Err(anyhow!(
+            "This binary was not compiled with the xz feature enabled! Cannot read {path:?}."
+        )),
 
-        // If the extension is not known 
+        #[cfg(feature = "zstd")]
+        "zst" => Ok(Box::new(ZstdDecoder::with_buffer(buf_reader)?)),
+        #[cfg(not(feature = "zstd"))]
+        "zst" => Err(anyhow!(
+            "This binary was not compiled with the zstd feature enabled! Cannot read {path:?}."
+        )),
+
+        // If the extension is not known
         unknown => {
             event!(
                     Level::WARN,
@@ -59,8 +128,12 @@ pub fn get_reader(path: &Path) -> anyhow::Result<Box<dyn Read>> {
                         "bz2",
                         #[cfg(feature = "gzip")]
                         "gz",
+                        #[cfg(feature = "gzip")]
+                        "zip",
                         #[cfg(feature = "xz")]
                         "xz",
+                        #[cfg(feature = "zstd")]
+                        "zst",
                     ].join(", ")
                 );
             Ok(Box::new(buf_reader))
@@ -68,10 +141,127 @@ pub fn get_reader(path: &Path) -> anyhow::Result<Box<dyn Read>> {
     }
 }
 
+/// Rows read from the input files, and the rows skipped in lenient mode by error category.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct RowErrors {
+    #[serde(skip)]
+    lenient: bool,
+    pub rows: usize,
+    pub skipped: usize,
+    pub categories: BTreeMap<String, usize>,
+    /// Rows of the main files with an already read geoname id, which replace the earlier entry.
+    #[serde(default)]
+    pub duplicate_ids: usize,
+    /// Rows of the main files left out because of their feature class or code.
+    #[serde(default)]
+    pub excluded: usize,
+}
+
+impl RowErrors {
+    pub fn new(lenient: bool) -> Self {
+        Self {
+            lenient,
+            ..Default::default()
+        }
+    }
+
+    /// Record a malformed row. Fails with the given error unless in lenient mode.
+    fn skip(&mut self, error: anyhow::Error) -> Result<(), anyhow::Error> {
+        if !self.lenient {
+            return Err(error);
+        }
+        self.skipped += 1;
+        *self.categories.entry(error.to_string()).or_default() += 1;
+        Ok(())
+    }
+}
+
+/// Feature classes and codes of the entries to leave out of the index.
+#[derive(Debug, Clone, Default)]
+pub struct FeatureExclusions {
+    pub classes: Vec<String>,
+    pub codes: Vec<String>,
+}
+
+impl FeatureExclusions {
+    fn excludes(&self, entry: &GeoNamesEntry) -> bool {
+        let contains =
+            |values: &[String], value: &str| values.iter().any(|v| v.eq_ignore_ascii_case(value));
+        contains(&self.classes, &entry.feature_class) || contains(&self.codes, &entry.feature_code)
+    }
+}
+
+/// Wrap a CSV error into a row error, using the kind of error as its category.
+fn malformed_row(error: csv::Error) -> anyhow::Error {
+    let category = match error.kind() {
+        csv::ErrorKind::UnequalLengths { .. } => "unexpected number of fields",
+        csv::ErrorKind::Utf8 { .. } => "invalid UTF-8",
+        _ => "malformed row",
+    };
+    anyhow::Error::new(error).context(category)
+}
+
+fn parse_geonames_record(
+    record: &StringRecord,
+    columns: &ColumnMapping,
+) -> Result<(GeoNamesEntry, String), anyhow::Error> {
+    let get = |column: Option<usize>| column.and_then(|i| record.get(i));
+
+    let id: u64 = record
+        .get(columns.id)
+        .ok_or(anyhow!("no geoname_id"))?
+        .parse()
+        .context("invalid geoname_id")?;
+    let name: String = record
+        .get(columns.name)
+        .ok_or(anyhow!("no name"))?
+        .to_string();
+    let name_ascii: String = match columns.ascii_name {
+        Some(i) => record.get(i).ok_or(anyhow!("no ascii name"))?.to_string(),
+        None => name.clone(),
+    };
+
+    let latitude: f32 = parse_float_else_nan(get(columns.latitude));
+    let longitude: f32 = parse_float_else_nan(get(columns.longitude));
+    let feature_class = Symbol::new(get(columns.feature_class).unwrap_or("<missing>"));
+    let feature_code = Symbol::new(get(columns.feature_code).unwrap_or("<missing>"));
+    let country_code = Symbol::new(get(columns.country_code).unwrap_or("<missing>"));
+    let adm1 = get(columns.adm1).unwrap_or("").to_string();
+    let adm2 = get(columns.adm2).unwrap_or("").to_string();
+    let adm3 = get(columns.adm3).unwrap_or("").to_string();
+    let adm4 = get(columns.adm4).unwrap_or("").to_string();
+    let population: u64 = get(columns.population)
+        .and_then(|i| i.parse().ok())
+        .unwrap_or(0);
+    let elevation: Option<i16> = get(columns.elevation).and_then(|i| i.parse().ok());
+
+    let entry = GeoNamesEntry {
+        id,
+        name,
+        latitude,
+        longitude,
+        feature_class,
+        feature_code,
+        country_code,
+        adm1,
+        adm2,
+        adm3,
+        adm4,
+        population,
+        elevation,
+        wikipedia: None,
+        wikidata: None,
+    };
+    Ok((entry, name_ascii))
+}
+
 pub(crate) fn parse_geonames_file(
     path: &str,
     query_pairs: &mut Vec<(String, MatchType)>,
     geonames: &mut HashMap<u64, GeoNamesEntry>,
+    columns: &ColumnMapping,
+    exclusions: &FeatureExclusions,
+    row_errors: &mut RowErrors,
 ) -> Result<(), anyhow::Error> {
     let reader: Box<dyn Read> = get_reader(Path::new(path))?;
 
@@ -80,54 +270,127 @@ pub(crate) fn parse_geonames_file(
         .from_reader(reader);
 
     for row in rdr.records() {
-        let record = row?;
-
-        let id: u64 = record.get(0).ok_or(anyhow!("no geoname_id"))?.parse()?;
-        let name: String = record.get(1).ok_or(anyhow!("no name"))?.to_string();
-        let name_ascii: String = record.get(2).ok_or(anyhow!("no ascii name"))?.to_string();
-
-        let latitude: f32 = parse_float_else_nan(record.get(4));
-        let longitude: f32 = parse_float_else_nan(record.get(5));
-        let feature_class: String = record.get(6).unwrap_or("<missing>").to_string();
-        let feature_code: String = record.get(7).unwrap_or("<missing>").to_string();
-        let country_code: String = record.get(8).unwrap_or("<missing>").to_string();
-        let adm1 = record.get(10).unwrap_or("").to_string();
-        let adm2 = record.get(11).unwrap_or("").to_string();
-        let adm3 = record.get(12).unwrap_or("").to_string();
-        let adm4 = record.get(13).unwrap_or("").to_string();
-        let elevation: Option<i16> = record.get(15).and_then(|i| i.parse().ok());
-
-        if name_ascii != name {
+        row_errors.rows += 1;
+        let parsed = row
+            .map_err(malformed_row)
+            .and_then(|record| parse_geonames_record(&record, columns));
+        let (entry, name_ascii) = match parsed {
+            Ok(parsed) => parsed,
+            Err(error) => {
+                row_errors.skip(error)?;
+                continue;
+            }
+        };
+        if exclusions.excludes(&entry) {
+            row_errors.excluded += 1;
+            continue;
+        }
+        let id = entry.id;
+
+        if name_ascii != entry.name {
             query_pairs.push((name_ascii, MatchType::AsciiName { id }));
         }
-        query_pairs.push((name.clone(), MatchType::Name { id }));
-
-        geonames.insert(
-            id,
-            GeoNamesEntry {
-                id,
-                name,
-                latitude,
-                longitude,
-                feature_class,
-                feature_code,
-                country_code,
-                adm1,
-                adm2,
-                adm3,
-                adm4,
-                elevation,
-            },
-        );
+        query_pairs.push((entry.name.clone(), MatchType::Name { id }));
+
+        if geonames.insert(id, entry).is_some() {
+            row_errors.duplicate_ids += 1;
+        }
     }
     Ok(())
 }
 
+/// A link of a GeoNames entry to another knowledge base, from the `link` and `wkdt` rows of the
+/// alternate names, which are not names to search for.
+#[derive(Debug)]
+pub(crate) enum EntryLink {
+    Wikipedia(String),
+    Wikidata(String),
+}
+
+impl EntryLink {
+    /// Set the link on the entry, keeping the first of each kind except that English Wikipedia
+    /// articles take precedence over those in other languages.
+    pub(crate) fn apply(self, entry: &mut GeoNamesEntry) {
+        let is_english = |url: &str| url.contains("//en.wikipedia.org/");
+        match self {
+            Self::Wikipedia(url) => {
+                if entry
+                    .wikipedia
+                    .as_ref()
+                    .is_none_or(|current| is_english(&url) && !is_english(current))
+                {
+                    entry.wikipedia = Some(url);
+                }
+            }
+            Self::Wikidata(item) => {
+                entry.wikidata.get_or_insert(item);
+            }
+        }
+    }
+}
+
+/// A row of an alternate names file.
+enum AlternateRecord {
+    Name(String, MatchType),
+    Link(u64, EntryLink),
+}
+
+fn parse_alternate_names_record(
+    record: &StringRecord,
+    geonames: &HashMap<u64, GeoNamesEntry>,
+    include_languages: Option<&HashSet<&String>>,
+) -> Result<Option<AlternateRecord>, anyhow::Error> {
+    let lang: String = record.get(2).ok_or(anyhow!("no language"))?.to_string();
+    let is_link = matches!(lang.as_str(), "link" | "wkdt");
+    if !is_link && include_languages.is_some_and(|set| !set.contains(&lang)) {
+        return Ok(None);
+    }
+
+    let id: u64 = record
+        .get(1)
+        .ok_or(anyhow!("no geoname_id"))?
+        .parse()
+        .context("invalid geoname_id")?;
+
+    if !geonames.contains_key(&id) {
+        return Ok(None);
+    }
+
+    let name: String = record.get(3).ok_or(anyhow!("no name"))?.to_string();
+    if is_link {
+        let link = match lang.as_str() {
+            "wkdt" => EntryLink::Wikidata(name),
+            _ if name.contains("wikipedia.org/") => EntryLink::Wikipedia(name),
+            // Links to other sites
+            _ => return Ok(None),
+        };
+        return Ok(Some(AlternateRecord::Link(id, link)));
+    }
+
+    let preferred: bool = record.get(4).ok_or(anyhow!("no preferred"))?.eq("1");
+    let short: bool = record.get(5).ok_or(anyhow!("no short"))?.eq("1");
+    let colloquial: bool = record.get(6).ok_or(anyhow!("no colloquial"))?.eq("1");
+    let historic: bool = record.get(7).ok_or(anyhow!("no historic"))?.eq("1");
+    let from: String = record.get(8).unwrap_or("").to_string();
+    let to: String = record.get(9).unwrap_or("").to_string();
+
+    let typ = match (preferred, short, colloquial, historic) {
+        (true, false, false, false) => MatchType::PreferredName { id, lang },
+        (false, true, false, false) => MatchType::ShortName { id, lang },
+        (false, false, true, false) => MatchType::Colloquial { id, lang },
+        (false, false, false, true) => MatchType::Historic { id, lang, from, to },
+        _ => MatchType::Alternate { id, lang },
+    };
+    Ok(Some(AlternateRecord::Name(name, typ)))
+}
+
 pub(crate) fn parse_alternate_names_file(
     path: &str,
     query_pairs: &mut Vec<(String, MatchType)>,
+    links: &mut Vec<(u64, EntryLink)>,
     geonames: &HashMap<u64, GeoNamesEntry>,
     include_languages: Option<&Vec<String>>,
+    row_errors: &mut RowErrors,
 ) -> Result<(), anyhow::Error> {
     let reader: Box<dyn Read> = get_reader(Path::new(path))?;
 
@@ -138,48 +401,102 @@ pub(crate) fn parse_alternate_names_file(
     let include_languages: Option<HashSet<&String>> = include_languages.map(HashSet::from_iter);
 
     for row in rdr.records() {
-        let record = row?;
-
-        let lang: String = record.get(2).ok_or(anyhow!("no language"))?.to_string();
-        if include_languages
-            .as_ref()
-            .is_some_and(|set| !set.contains(&lang))
-        {
-            continue;
+        row_errors.rows += 1;
+        let parsed = row.map_err(malformed_row).and_then(|record| {
+            parse_alternate_names_record(&record, geonames, include_languages.as_ref())
+        });
+        match parsed {
+            Ok(Some(AlternateRecord::Name(name, typ))) => query_pairs.push((name, typ)),
+            Ok(Some(AlternateRecord::Link(id, link))) => links.push((id, link)),
+            Ok(None) => continue,
+            Err(error) => row_errors.skip(error)?,
         }
+    }
+    Ok(())
+}
 
-        let id: u64 = record.get(1).ok_or(anyhow!("no geoname_id"))?.parse()?;
+fn parse_synonyms_record(
+    record: &StringRecord,
+    geonames: &HashMap<u64, GeoNamesEntry>,
+) -> Result<Option<(String, MatchType)>, anyhow::Error> {
+    let name: String = record.get(0).ok_or(anyhow!("no synonym"))?.to_string();
+    let id: u64 = record
+        .get(1)
+        .ok_or(anyhow!("no geoname_id"))?
+        .trim()
+        .parse()
+        .context("invalid geoname_id")?;
 
-        if !geonames.contains_key(&id) {
-            continue;
+    if !geonames.contains_key(&id) {
+        return Ok(None);
+    }
+    Ok(Some((name, MatchType::Synonym { id })))
+}
+
+/// Read a headerless synonyms file with one `synonym<TAB>geoname_id` pair per line. Synonyms of
+/// entries that are not part of the index are ignored.
+pub(crate) fn parse_synonyms_file(
+    path: &str,
+    query_pairs: &mut Vec<(String, MatchType)>,
+    geonames: &HashMap<u64, GeoNamesEntry>,
+    row_errors: &mut RowErrors,
+) -> Result<(), anyhow::Error> {
+    let reader: Box<dyn Read> = get_reader(Path::new(path))?;
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        .flexible(true)
+        .comment(Some(b'#'))
+        .from_reader(reader);
+
+    for row in rdr.records() {
+        row_errors.rows += 1;
+        let parsed = row
+            .map_err(malformed_row)
+            .and_then(|record| parse_synonyms_record(&record, geonames));
+        match parsed {
+            Ok(Some(pair)) => query_pairs.push(pair),
+            Ok(None) => continue,
+            Err(error) => row_errors.skip(error)?,
         }
+    }
+    Ok(())
+}
 
-        let lang = lang.to_string();
-        let name: String = record.get(3).ok_or(anyhow!("no name"))?.to_string();
+fn parse_shapes_record(record: &StringRecord) -> Result<(u64, Polygon), anyhow::Error> {
+    let id: u64 = record
+        .get(0)
+        .ok_or(anyhow!("no geoname_id"))?
+        .parse()
+        .context("invalid geoname_id")?;
+    let polygon: Polygon = serde_json::from_str(record.get(1).ok_or(anyhow!("no geoJSON"))?)
+        .context("invalid geoJSON")?;
+    Ok((id, polygon))
+}
 
-        let preferred: bool = record.get(4).ok_or(anyhow!("no preferred"))?.eq("1");
-        let short: bool = record.get(5).ok_or(anyhow!("no short"))?.eq("1");
-        let colloquial: bool = record.get(6).ok_or(anyhow!("no colloquial"))?.eq("1");
-        let historic: bool = record.get(7).ok_or(anyhow!("no historic"))?.eq("1");
-        let from: String = record.get(8).unwrap_or("").to_string();
-        let to: String = record.get(9).unwrap_or("").to_string();
+/// Read a GeoNames shapes file such as `shapes_simplified_low.json`, which despite its name
+/// contains a `geoNameId<TAB>geoJSON` pair per row.
+pub(crate) fn parse_shapes_file(
+    path: &str,
+    shapes: &mut Vec<(u64, Polygon)>,
+    row_errors: &mut RowErrors,
+) -> Result<(), anyhow::Error> {
+    let reader: Box<dyn Read> = get_reader(Path::new(path))?;
 
-        match (preferred, short, colloquial, historic) {
-            (true, false, false, false) => {
-                query_pairs.push((name, MatchType::PreferredName { id, lang }));
-            }
-            (false, true, false, false) => {
-                query_pairs.push((name, MatchType::ShortName { id, lang }));
-            }
-            (false, false, true, false) => {
-                query_pairs.push((name, MatchType::Colloquial { id, lang }));
-            }
-            (false, false, false, true) => {
-                query_pairs.push((name, MatchType::Historic { id, lang, from, to }));
-            }
-            _ => {
-                query_pairs.push((name, MatchType::Alternate { id, lang }));
-            }
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .quoting(false)
+        .from_reader(reader);
+
+    for row in rdr.records() {
+        row_errors.rows += 1;
+        match row
+            .map_err(malformed_row)
+            .and_then(|record| parse_shapes_record(&record))
+        {
+            Ok(shape) => shapes.push(shape),
+            Err(error) => row_errors.skip(error)?,
         }
     }
     Ok(())
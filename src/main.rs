@@ -1,48 +1,199 @@
+pub mod diagnostics;
 pub mod geonames;
 pub mod routes;
+pub mod served;
 
 #[cfg(feature = "duui")]
 pub mod duui;
 
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
-use aide::axum::routing::get;
+use aide::axum::routing::{get, get_with};
 use aide::axum::IntoApiResponse;
 use aide::{axum::ApiRouter, openapi::OpenApi};
 use anyhow::anyhow;
 use axum::http::StatusCode;
-use axum::Extension;
-use clap::{command, Parser};
+use axum::{middleware, Extension};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
 
 #[cfg(feature = "geonames_routes")]
 use routes::geonames_routes;
 use tower_http::trace::TraceLayer;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+use tracing_subscriber::{reload, EnvFilter};
 
-use crate::geonames::searcher::GeoNamesSearcher;
+use crate::diagnostics::{
+    access_log, default_log_filter, record_route, request_span, InFlight, LogFilter,
+};
+use crate::geonames::columns::ColumnMapping;
+use crate::geonames::deadline::Deadline;
+use crate::geonames::score::ScoreWeights;
+use crate::geonames::searcher::{BuildOptions, BuildReport, Datasets, GeoNamesSearcher};
+use crate::geonames::utils::{is_remote, FeatureExclusions, STDIN_PATH};
+use crate::routes::admin::admin_routes;
+use crate::routes::batch::{BatchItem, BatchQuery};
+use crate::routes::capabilities::{capabilities, capabilities_docs, Capabilities};
 use crate::routes::docs::docs_routes;
+use crate::routes::error::problem_details;
+use crate::routes::facets::Facets;
+use crate::routes::stats::{stats, stats_docs, IndexStats};
+use crate::served::{ServedIndex, SharedIndex};
 
 #[cfg(feature = "duui")]
 use crate::duui::duui_routes;
 
 #[derive(Clone)]
 struct AppState {
-    searcher: Arc<GeoNamesSearcher>,
-    #[cfg(feature = "duui")]
-    languages: Option<Vec<String>>,
+    /// The served index, which may be replaced while the server runs.
+    index: SharedIndex,
+    log_filter: LogFilter,
+    in_flight: InFlight,
+    /// Maximum time a single search may take, `None` to wait indefinitely.
+    #[cfg(any(feature = "geonames_routes", feature = "duui"))]
+    search_timeout: Option<Duration>,
+    /// Max age of cacheable responses in seconds.
+    #[cfg(feature = "geonames_routes")]
+    cache_max_age_s: u64,
+    /// Bearer token required by the protected admin routes, `None` to disable them.
+    admin_token: Option<Arc<str>>,
+    /// Number of queries of a batch or entities of a DUUI request searched at the same time.
+    #[cfg(any(feature = "geonames_routes", feature = "duui"))]
+    batch_concurrency: usize,
     #[cfg(feature = "duui")]
     timestamp: Option<String>,
 }
 
+impl AppState {
+    /// The searcher of the currently served index.
+    fn searcher(&self) -> Arc<GeoNamesSearcher> {
+        self.index.load().searcher.clone()
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
-struct Args {
-    #[clap(help = "Paths to GeoNames files")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = LogFormat::Pretty,
+        env = "GEONAMES_LOG_FORMAT",
+        help = "Format of the log lines: human-readable, or one JSON object per line with the fields of the event and its request, e.g. for Loki or ELK."
+    )]
+    log_format: LogFormat,
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        help = "Level of the logs of the service and its HTTP layer. Defaults to `debug`, or the `RUST_LOG` directives if set, which these flags override."
+    )]
+    log_level: Option<LogLevel>,
+    #[clap(
+        short,
+        long,
+        global = true,
+        action = clap::ArgAction::Count,
+        conflicts_with = "quiet",
+        help = "Log one level more verbosely than --log-level per use, e.g. `-v` for `trace`."
+    )]
+    verbose: u8,
+    #[clap(
+        short,
+        long,
+        global = true,
+        action = clap::ArgAction::Count,
+        help = "Log one level less verbosely than --log-level per use, e.g. `-q` for `info`, which keeps the access log but silences the per-request debug logs, or `-qq` for warnings only."
+    )]
+    quiet: u8,
+}
+
+impl Cli {
+    /// The log filter directives selected by the flags, if any were given.
+    fn log_directives(&self) -> Option<String> {
+        if self.log_level.is_none() && self.verbose == 0 && self.quiet == 0 {
+            return None;
+        }
+        let level = self.log_level.unwrap_or(LogLevel::Debug) as i16;
+        let level = (level + i16::from(self.verbose) - i16::from(self.quiet))
+            .clamp(LogLevel::Error as i16, LogLevel::Trace as i16);
+        let level = LogLevel::value_variants()[level as usize];
+        Some(default_log_filter(&level.to_string()))
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl std::fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = self.to_possible_value().expect("no level is skipped");
+        f.write_str(value.get_name())
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum LogFormat {
+    Pretty,
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Build the index (or load a prebuilt one) and serve the API.
+    Serve(ServeArgs),
+    /// Build the index and write it to a standalone artifact.
+    Build(BuildArgs),
+    /// Build the index (or load a prebuilt one) and run searches offline, printing the outcome of
+    /// each query as a JSON line like the items of a batch request, or its results as TSV.
+    /// Queries are searched in parallel on all cores, but printed in the order they were given.
+    Query(QueryArgs),
+    /// Read all input files without serving, e.g. to check a new dump before deploying it, and
+    /// print a report of the rows, malformed rows, duplicate ids and the size of the index.
+    /// Exits with an error if any row or file had to be skipped.
+    Validate(ValidateArgs),
+    /// Print a completion script for the given shell, e.g. `geonames-fst completions bash >
+    /// /etc/bash_completion.d/geonames-fst`.
+    Completions(CompletionsArgs),
+}
+
+/// Input files and options for building the index.
+#[derive(clap::Args, Debug, Clone)]
+struct IndexArgs {
+    #[clap(help = "Paths or HTTP(S) URLs of GeoNames files, use `-` to read from stdin")]
     paths: Vec<String>,
-    #[clap(short, long, help = "Paths to `alternateNames` files")]
+    #[clap(
+        short,
+        long,
+        help = "Paths or HTTP(S) URLs of `alternateNames` files, use `-` to read from stdin. Append `:de,deu` to consider other languages for a file than the global `--languages`."
+    )]
     alternate: Option<Vec<String>>,
+    #[clap(
+        long,
+        help = "Continue with the main names only if an `alternateNames` file is missing or corrupt."
+    )]
+    alternate_optional: bool,
+    #[clap(
+        long,
+        help = "Skip malformed rows in the input files and report them, instead of aborting."
+    )]
+    lenient: bool,
     #[clap(
         short,
         long,
@@ -53,17 +204,285 @@ struct Args {
     languages: Vec<String>,
     #[clap(long, help = "Include all languages in the alternate name resolution.")]
     all_languages: bool,
-    #[clap(long, default_value = "0.0.0.0")]
+    #[clap(
+        long,
+        default_value = "0",
+        help = "Precompute the top-k completions for all prefixes up to this many characters (0 to disable)."
+    )]
+    completions_prefix_len: usize,
+    #[clap(
+        long,
+        default_value = "10",
+        help = "Number of completions to precompute per prefix."
+    )]
+    completions_top_k: usize,
+    #[clap(
+        long,
+        help = "Build a trigram index for the n-gram search of heavily garbled names."
+    )]
+    ngram_index: bool,
+    #[clap(
+        long,
+        help = "Build a word index for the token search of multi-word names in any order."
+    )]
+    token_index: bool,
+    #[clap(
+        long,
+        help = "Paths or HTTP(S) URLs of synonym files with one `synonym<TAB>geoname_id` pair per line."
+    )]
+    synonyms: Vec<String>,
+    #[clap(
+        long,
+        value_delimiter = ',',
+        help = "Feature classes to leave out of the index, e.g. `H,S`."
+    )]
+    exclude_feature_classes: Vec<String>,
+    #[clap(
+        long,
+        value_delimiter = ',',
+        help = "Feature codes to leave out of the index, e.g. `PPLX`."
+    )]
+    exclude_feature_codes: Vec<String>,
+    #[clap(
+        long,
+        help = "Path or HTTP(S) URL of a GeoNames country shapes file, e.g. `shapes_simplified_low.json`, for resolving the country of a point."
+    )]
+    shapes: Option<String>,
+    #[clap(
+        long,
+        help = "Column mapping for non-GeoNames gazetteers, e.g. `id=0,name=2,lat=3,lon=4,ascii_name=`, or a file containing one."
+    )]
+    columns: Option<ColumnMapping>,
+    #[clap(
+        long,
+        help = "Write the statistics of the index, which are also logged after the build, to this JSON file."
+    )]
+    stats_out: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ServeArgs {
+    #[clap(flatten)]
+    index: IndexArgs,
+    #[clap(
+        long = "index",
+        conflicts_with = "paths",
+        help = "Serve a prebuilt index artifact written by the `build` command."
+    )]
+    index_path: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Paths or HTTP(S) URLs of GeoNames files of a small index to serve right away, e.g. `cities15000.txt`, while the full index is built in the background and replaces it when ready."
+    )]
+    startup: Vec<String>,
+    #[clap(
+        long,
+        default_value = "0.0.0.0",
+        help = "Host to bind to, unless a listening socket is passed by systemd socket activation (`LISTEN_FDS`)."
+    )]
     host: String,
     #[clap(long, default_value = "8000")]
     port: u16,
-    #[clap(long, default_value = "4")]
+    #[clap(
+        long,
+        default_value = "4",
+        help = "Handle requests on this many runtime threads (0 for the number of CPUs). Searches run on a separate pool of blocking threads."
+    )]
     workers: usize,
+    #[clap(
+        long,
+        default_value = "1.0",
+        help = "Weight of the match type in the relevance score of search results."
+    )]
+    score_match_type_weight: f64,
+    #[clap(
+        long,
+        default_value = "1.0",
+        help = "Weight of the edit distance in the relevance score of search results."
+    )]
+    score_distance_weight: f64,
+    #[clap(
+        long,
+        default_value = "1.0",
+        help = "Weight of the population in the relevance score of search results."
+    )]
+    score_population_weight: f64,
+    #[cfg(any(feature = "geonames_routes", feature = "duui"))]
+    #[clap(
+        long,
+        default_value = "10000",
+        help = "Abort searches taking longer than this many milliseconds with 504 Gateway Timeout (0 to disable)."
+    )]
+    search_timeout_ms: u64,
+    #[clap(
+        long,
+        default_value = "10000",
+        help = "Return at most this many results per search, reporting `truncated` and the total number of results if there were more (0 to disable)."
+    )]
+    max_results: usize,
+    #[cfg(any(feature = "geonames_routes", feature = "duui"))]
+    #[clap(
+        long,
+        default_value = "0",
+        help = "Search this many queries of a batch request at the same time (0 for the number of CPUs)."
+    )]
+    batch_concurrency: usize,
+    #[cfg(feature = "geonames_routes")]
+    #[clap(
+        long,
+        default_value = "300",
+        help = "Let clients and HTTP caches reuse the responses of GET search routes for this many seconds (0 to always revalidate)."
+    )]
+    cache_max_age_s: u64,
+    #[clap(
+        long,
+        env = "GEONAMES_ADMIN_TOKEN",
+        hide_env_values = true,
+        help = "Bearer token for the admin routes that change the service or expose other requests: POST /admin/entries, PUT /admin/loglevel and GET /admin/diagnostics. These routes are disabled without a token."
+    )]
+    admin_token: Option<String>,
     #[cfg(feature = "duui")]
     #[clap(long)]
     timestamp: Option<String>,
 }
 
+#[derive(clap::Args, Debug)]
+struct BuildArgs {
+    #[clap(flatten)]
+    index: IndexArgs,
+    #[clap(
+        short,
+        long,
+        help = "Path of the index artifact to write, e.g. `index.gnfst`."
+    )]
+    out: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct QueryArgs {
+    #[clap(
+        value_name = "QUERY",
+        help = "The query to search, followed by the input paths, e.g. `query Frxnkfxrt DE.txt`. Use `-` to read the queries from stdin, one per line. With --query, all positional arguments are input paths."
+    )]
+    text: Option<String>,
+    #[clap(flatten)]
+    index: IndexArgs,
+    #[clap(
+        long = "index",
+        conflicts_with = "paths",
+        help = "Search a prebuilt index artifact written by the `build` command."
+    )]
+    index_path: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Queries to search, instead of the positional query. Read from stdin, one per line, if neither is given."
+    )]
+    query: Vec<String>,
+    #[clap(
+        short,
+        long,
+        default_value = "find",
+        help = "Search mode, as in batch requests, e.g. `find`, `starts_with`, `levenshtein` or `cascade`."
+    )]
+    mode: String,
+    #[clap(
+        long,
+        help = "Options of the search mode as a JSON object, e.g. `{\"max_dist\": 1}`."
+    )]
+    options: Option<String>,
+    #[clap(
+        long,
+        help = "Maximum edit distance of the `starts_with`, `fuzzy` and `levenshtein` modes, overriding --options."
+    )]
+    max_dist: Option<u32>,
+    #[clap(
+        long,
+        default_value = "10000",
+        help = "Return at most this many results per query (0 to disable)."
+    )]
+    max_results: usize,
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = QueryFormat::Json,
+        help = "Print the outcome of each query as a JSON line, or each result as a tab-separated row."
+    )]
+    format: QueryFormat,
+}
+
+#[derive(clap::Args, Debug)]
+struct ValidateArgs {
+    #[clap(flatten)]
+    index: IndexArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct CompletionsArgs {
+    #[clap(value_enum, help = "The shell to generate completions for.")]
+    shell: clap_complete::Shell,
+}
+
+/// Report of the `validate` command.
+#[derive(Serialize)]
+struct ValidationReport {
+    datasets: Datasets,
+    /// Rows read and skipped, by error category, and files that could not be read.
+    report: BuildReport,
+    number_of_geonames: usize,
+    number_of_keys: usize,
+    fst_bytes: usize,
+    /// Estimated heap footprint of the index when served.
+    memory_bytes: usize,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum QueryFormat {
+    Json,
+    Tsv,
+}
+
+/// Columns of the `tsv` output of the `query` command: the query, then these result fields.
+const TSV_COLUMNS: [&str; 11] = [
+    "id",
+    "name",
+    "key",
+    "distance",
+    "score",
+    "latitude",
+    "longitude",
+    "feature_class",
+    "feature_code",
+    "country_code",
+    "adm1",
+];
+
+/// Write the results of a query as tab-separated rows, logging its error if it failed.
+fn write_tsv(out: &mut impl Write, text: &str, item: &BatchItem) -> Result<(), anyhow::Error> {
+    if let Some(error) = &item.error {
+        tracing::warn!("Query {text:?} failed: {}", error.message);
+    }
+    // Through the JSON text, which keeps the `f32` coordinates as written
+    let item: serde_json::Value = serde_json::from_slice(&serde_json::to_vec(item)?)?;
+    let results = item["results"].as_array().into_iter().flatten();
+    let cell = |value: &serde_json::Value| match value {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(value) => value.replace(['\t', '\n', '\r'], " "),
+        value => value.to_string(),
+    };
+    for result in results {
+        let mut row = vec![cell(&text.into())];
+        for column in TSV_COLUMNS {
+            row.push(cell(match column {
+                "key" => &result["key"]["name"],
+                "distance" | "score" => &result[column],
+                field => &result["entry"][field],
+            }));
+        }
+        writeln!(out, "{}", row.join("\t"))?;
+    }
+    Ok(())
+}
+
 async fn get_version() -> impl IntoApiResponse {
     (
         StatusCode::OK,
@@ -71,55 +490,325 @@ async fn get_version() -> impl IntoApiResponse {
     )
 }
 
-async fn serve(args: Args) -> Result<(), anyhow::Error> {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                // axum logs rejections from built-in extractors with the `axum::rejection`
-                // target, at `TRACE` level. `axum::rejection=trace` enables showing those events
-                format!(
-                    "{}=debug,tower_http=debug,axum::rejection=trace",
-                    env!("CARGO_CRATE_NAME")
-                )
-                .into()
-            }),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+/// Split the languages off an alternate names path given as `path:de,deu`.
+///
+/// The suffix is only taken as languages if it contains no path separators, so that URLs and
+/// paths with colons in them are kept intact, e.g. `http://example.com:8080/alternate.txt`.
+fn split_languages(spec: &str) -> (&str, Option<Vec<String>>) {
+    let Some((path, languages)) = spec.rsplit_once(':') else {
+        return (spec, None);
+    };
+    let is_port = is_remote(spec) && languages.chars().all(|c| c.is_ascii_digit());
+    if path.is_empty() || languages.is_empty() || languages.contains(['/', '\\']) || is_port {
+        return (spec, None);
+    }
+    let languages = languages.split(',').map(|s| s.trim().to_string()).collect();
+    (path, Some(languages))
+}
 
-    let mut paths = Vec::new();
-    for path in args.paths.iter() {
-        if std::fs::metadata(path)?.is_dir() {
-            for entry in std::fs::read_dir(path)? {
-                let entry = entry?;
-                if entry.file_type()?.is_file() {
-                    paths.push(entry.path().to_string_lossy().to_string());
-                }
-            }
-        } else {
-            paths.push(path.to_string());
+impl IndexArgs {
+    /// Resolve the input paths, expanding directories into the files they contain.
+    /// Also returns the languages of the alternate names files that override the global ones.
+    #[allow(clippy::type_complexity)]
+    fn resolve_paths(
+        &self,
+    ) -> Result<
+        (
+            Vec<String>,
+            Option<Vec<String>>,
+            BTreeMap<String, Vec<String>>,
+        ),
+        anyhow::Error,
+    > {
+        let alternate = self.alternate.as_ref().map(|alternate| {
+            alternate
+                .iter()
+                .map(|spec| split_languages(spec))
+                .collect::<Vec<_>>()
+        });
+        let stdin_paths = self
+            .paths
+            .iter()
+            .map(String::as_str)
+            .chain(alternate.iter().flatten().map(|(path, _)| *path))
+            .filter(|path| *path == STDIN_PATH)
+            .count();
+        if stdin_paths > 1 {
+            Err(anyhow!("stdin (`{STDIN_PATH}`) may only be given once"))?;
         }
-    }
 
-    let alternate_paths = if let Some(alternate) = args.alternate.as_ref() {
-        let mut alternate_paths = Vec::new();
-        for path in alternate.iter() {
-            if std::fs::metadata(path)?.is_dir() {
+        let mut paths = Vec::new();
+        for path in self.paths.iter() {
+            if path == STDIN_PATH || is_remote(path) {
+                paths.push(path.to_string());
+            } else if std::fs::metadata(path)?.is_dir() {
                 for entry in std::fs::read_dir(path)? {
                     let entry = entry?;
                     if entry.file_type()?.is_file() {
-                        alternate_paths.push(entry.path().to_string_lossy().to_string());
+                        paths.push(entry.path().to_string_lossy().to_string());
                     }
                 }
             } else {
-                alternate_paths.push(path.to_string());
+                paths.push(path.to_string());
             }
         }
-        Some(alternate_paths)
-    } else {
-        None
+
+        let mut alternate_languages = BTreeMap::new();
+        let alternate_paths = if let Some(alternate) = alternate {
+            let mut alternate_paths = Vec::new();
+            for (path, languages) in alternate {
+                let start = alternate_paths.len();
+                if path == STDIN_PATH || is_remote(path) {
+                    alternate_paths.push(path.to_string());
+                } else if self.alternate_optional && std::fs::metadata(path).is_err() {
+                    // Let the searcher record the missing file in its build report
+                    alternate_paths.push(path.to_string());
+                } else if std::fs::metadata(path)?.is_dir() {
+                    for entry in std::fs::read_dir(path)? {
+                        let entry = entry?;
+                        if entry.file_type()?.is_file() {
+                            alternate_paths.push(entry.path().to_string_lossy().to_string());
+                        }
+                    }
+                } else {
+                    alternate_paths.push(path.to_string());
+                }
+                if let Some(languages) = languages {
+                    for path in &alternate_paths[start..] {
+                        alternate_languages.insert(path.clone(), languages.clone());
+                    }
+                }
+            }
+            Some(alternate_paths)
+        } else {
+            None
+        };
+
+        Ok((paths, alternate_paths, alternate_languages))
+    }
+
+    /// The languages to include from the alternate names, `None` for all languages.
+    fn languages(&self) -> Option<Vec<String>> {
+        if self.all_languages | self.languages.is_empty() {
+            None
+        } else {
+            Some(self.languages.iter().map(|s| s.to_string()).collect())
+        }
+    }
+
+    fn build(&self) -> Result<GeoNamesSearcher, anyhow::Error> {
+        let (paths, alternate_paths, alternate_languages) = self.resolve_paths()?;
+        let languages = self.languages();
+
+        tracing::info!("Building GeoNamesSearcher");
+        let searcher = GeoNamesSearcher::build(
+            paths,
+            alternate_paths.as_ref(),
+            languages.as_ref(),
+            &BuildOptions {
+                alternate_optional: self.alternate_optional,
+                lenient: self.lenient,
+                completions_prefix_len: self.completions_prefix_len,
+                completions_top_k: self.completions_top_k,
+                ngram_index: self.ngram_index,
+                token_index: self.token_index,
+                alternate_languages,
+                synonyms: self.synonyms.clone(),
+                shapes: self.shapes.clone(),
+                columns: self.columns.clone().unwrap_or_default(),
+                exclusions: FeatureExclusions {
+                    classes: self.exclude_feature_classes.clone(),
+                    codes: self.exclude_feature_codes.clone(),
+                },
+            },
+        )?;
+        tracing::info!("Built GeoNamesSearcher");
+
+        let stats = IndexStats::new(&searcher, &Facets::new(searcher.geonames.values()));
+        stats.log();
+        if let Some(path) = &self.stats_out {
+            std::fs::write(path, serde_json::to_vec_pretty(&stats)?)
+                .map_err(|e| anyhow!("Failed to write the index statistics to {path:?}: {e}"))?;
+        }
+        Ok(searcher)
+    }
+}
+
+/// Build the index, or load the prebuilt index artifact at `index_path`.
+fn load_searcher(
+    index: &IndexArgs,
+    index_path: Option<&Path>,
+) -> Result<GeoNamesSearcher, anyhow::Error> {
+    let Some(index_path) = index_path else {
+        return index.build();
+    };
+    tracing::info!("Loading index artifact from {index_path:?}");
+    let searcher = GeoNamesSearcher::load_artifact(index_path)?;
+    if let Some(manifest) = searcher.manifest.as_ref() {
+        tracing::info!(
+            "Loaded index artifact built by version {} at {} from {} sources",
+            manifest.crate_version,
+            manifest.created,
+            manifest.sources.len()
+        );
+    }
+    Ok(searcher)
+}
+
+/// Set up logging to stdout, or to stderr if stdout is reserved for output.
+///
+/// The `directives` of the log level flags take precedence over `RUST_LOG`.
+fn init_tracing(directives: Option<String>, format: LogFormat, to_stderr: bool) -> LogFilter {
+    let directives = directives
+        .or_else(|| {
+            std::env::var(EnvFilter::DEFAULT_ENV)
+                .ok()
+                .filter(|directives| EnvFilter::try_new(directives).is_ok())
+        })
+        .unwrap_or_else(|| default_log_filter("debug"));
+    let (filter, handle) = reload::Layer::new(EnvFilter::new(&directives));
+    let writer = match to_stderr {
+        true => BoxMakeWriter::new(std::io::stderr),
+        false => BoxMakeWriter::new(std::io::stdout),
+    };
+    let layer = match format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer().with_writer(writer).boxed(),
+        // The fields of the request span, e.g. its id and route, are flattened into each event
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_writer(writer)
+            .boxed(),
     };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layer)
+        .init();
+    LogFilter::new(handle, directives)
+}
 
+fn build(args: BuildArgs) -> Result<(), anyhow::Error> {
+    let searcher = args.index.build()?;
+    tracing::info!("Writing index artifact to {:?}", args.out);
+    let manifest = searcher.write_artifact(&args.out)?;
+    tracing::info!(
+        "Wrote index artifact to {:?} ({} bytes, sha256 {})",
+        args.out,
+        manifest.data_size,
+        manifest.data_sha256
+    );
+    Ok(())
+}
+
+/// Number of queries of the `query` command searched in parallel before writing their results.
+const QUERY_CHUNK: usize = 256;
+
+fn query(mut args: QueryArgs) -> Result<(), anyhow::Error> {
+    let mut options: serde_json::Map<_, _> = match args.options.as_deref() {
+        Some(options) => serde_json::from_str(options)
+            .map_err(|e| anyhow!("--options must be a JSON object: {e}"))?,
+        None => serde_json::Map::new(),
+    };
+    if let Some(max_dist) = args.max_dist {
+        options.insert("max_dist".to_string(), max_dist.into());
+    }
+    // With --query, the positional query is the first input path
+    match args.text.take() {
+        Some(text) if !args.query.is_empty() => args.index.paths.insert(0, text),
+        Some(text) if text != STDIN_PATH => args.query.push(text),
+        _ => {}
+    }
+    if args.index_path.is_some() && !args.index.paths.is_empty() {
+        Err(anyhow!("Input paths cannot be used with --index"))?;
+    }
+    let from_stdin = args.query.is_empty();
+    if from_stdin && args.index.paths.iter().any(|path| path == STDIN_PATH) {
+        Err(anyhow!(
+            "Pass the queries with --query when reading the index from stdin (`{STDIN_PATH}`)"
+        ))?;
+    }
+
+    let mut searcher = load_searcher(&args.index, args.index_path.as_deref())?;
+    searcher.max_results = (args.max_results > 0).then_some(args.max_results);
+
+    let queries: Box<dyn Iterator<Item = std::io::Result<String>>> = match from_stdin {
+        true => Box::new(std::io::stdin().lines()),
+        false => Box::new(args.query.into_iter().map(Ok)),
+    };
+    let mut out = std::io::stdout().lock();
+    if let QueryFormat::Tsv = args.format {
+        writeln!(out, "query\t{}", TSV_COLUMNS.join("\t"))?;
+    }
+    let (mut total, mut failed) = (0, 0);
+    let mut queries = queries.peekable();
+    while queries.peek().is_some() {
+        // Search in chunks, so that results of queries from stdin are written as they come in
+        let chunk = queries
+            .by_ref()
+            .take(QUERY_CHUNK)
+            .map(|text| {
+                let text = text?;
+                let query = BatchQuery::from_text(&args.mode, &text, &options)
+                    .map_err(|e| anyhow!("Invalid query {text:?}: {e}"))?;
+                Ok((text, query))
+            })
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+        let items = searcher.search_many(chunk, |searcher, (text, query)| {
+            (
+                text,
+                query
+                    .search(searcher, Deadline::NONE)
+                    .unwrap_or_else(BatchItem::failed),
+            )
+        });
+        for (text, item) in items {
+            total += 1;
+            failed += usize::from(item.error.is_some());
+            match args.format {
+                QueryFormat::Json => {
+                    serde_json::to_writer(&mut out, &item)?;
+                    writeln!(out)?;
+                }
+                QueryFormat::Tsv => write_tsv(&mut out, &text, &item)?,
+            }
+        }
+    }
+    if failed > 0 {
+        Err(anyhow!("{failed} of {total} queries failed"))?;
+    }
+    Ok(())
+}
+
+fn validate(mut args: ValidateArgs) -> Result<(), anyhow::Error> {
+    // Count all malformed rows instead of stopping at the first
+    args.index.lenient = true;
+    let searcher = args.index.build()?;
+    let validation = ValidationReport {
+        datasets: searcher.datasets.clone(),
+        report: searcher.report.clone(),
+        number_of_geonames: searcher.geonames.len(),
+        number_of_keys: searcher.map.len(),
+        fst_bytes: searcher.map.as_fst().as_bytes().len(),
+        memory_bytes: searcher.memory_usage(),
+    };
+    serde_json::to_writer_pretty(std::io::stdout().lock(), &validation)?;
+    println!();
+
+    let report = &validation.report;
+    if report.degraded || report.rows.duplicate_ids > 0 {
+        Err(anyhow!(
+            "Found {} malformed rows, {} duplicate ids and {} unreadable files",
+            report.rows.skipped,
+            report.rows.duplicate_ids,
+            report.warnings.len()
+        ))?;
+    }
+    Ok(())
+}
+
+async fn serve(args: ServeArgs, log_filter: LogFilter) -> Result<(), anyhow::Error> {
     #[cfg(feature = "duui")]
     let timestamp = if let Some(ts) = args.timestamp {
         if Path::new(&ts).exists() {
@@ -135,56 +824,216 @@ async fn serve(args: Args) -> Result<(), anyhow::Error> {
         None
     };
 
-    let languages = if args.all_languages | args.languages.is_empty() {
-        None
+    let score_weights = ScoreWeights {
+        match_type: args.score_match_type_weight,
+        distance: args.score_distance_weight,
+        population: args.score_population_weight,
+    };
+    let max_results = (args.max_results > 0).then_some(args.max_results);
+    let configure = move |mut searcher: GeoNamesSearcher| {
+        searcher.score_weights = score_weights;
+        searcher.max_results = max_results;
+        searcher
+    };
+
+    // Either serve the full index once it is ready, or a small one until then
+    let (searcher, preliminary) = if args.startup.is_empty() {
+        let searcher = load_searcher(&args.index, args.index_path.as_deref())?;
+        (searcher, false)
     } else {
-        Some(args.languages.iter().map(|s| s.to_string()).collect())
+        tracing::info!("Building startup index from {} files", args.startup.len());
+        let startup = IndexArgs {
+            paths: args.startup.clone(),
+            alternate: None,
+            ..args.index.clone()
+        };
+        (startup.build()?, true)
     };
 
-    tracing::info!("Building GeoNamesSearcher");
     let app_state = AppState {
-        searcher: Arc::new(GeoNamesSearcher::build(
-            paths,
-            alternate_paths.as_ref(),
-            languages.as_ref(),
-        )?),
-        #[cfg(feature = "duui")]
-        languages,
+        index: SharedIndex::new(ServedIndex::new(
+            configure(searcher),
+            Vec::new(),
+            preliminary,
+        )),
+        log_filter: log_filter.clone(),
+        in_flight: InFlight::default(),
+        #[cfg(any(feature = "geonames_routes", feature = "duui"))]
+        search_timeout: (args.search_timeout_ms > 0)
+            .then(|| Duration::from_millis(args.search_timeout_ms)),
+        #[cfg(feature = "geonames_routes")]
+        cache_max_age_s: args.cache_max_age_s,
+        admin_token: args.admin_token.map(Arc::from),
+        #[cfg(any(feature = "geonames_routes", feature = "duui"))]
+        batch_concurrency: match args.batch_concurrency {
+            0 => std::thread::available_parallelism().map_or(1, usize::from),
+            concurrency => concurrency,
+        },
         #[cfg(feature = "duui")]
         timestamp,
     };
-    tracing::info!("Built GeoNamesSearcher");
+    log_filter.listen_for_signal()?;
+    let (host, port) = (args.host.clone(), args.port);
 
     let mut api = OpenApi::default();
 
     let app = ApiRouter::new()
         .route("/", get(get_version))
-        .nest_api_service("/docs", docs_routes(app_state.clone()));
+        .api_route("/capabilities", get_with(capabilities, capabilities_docs))
+        .api_route("/stats", get_with(stats, stats_docs))
+        .nest_api_service(
+            "/docs",
+            docs_routes(app_state.clone()).layer(middleware::from_fn(record_route)),
+        )
+        .nest_api_service(
+            "/admin",
+            admin_routes(app_state.clone()).layer(middleware::from_fn(record_route)),
+        );
 
     #[cfg(feature = "geonames_routes")]
-    let app = app.nest_api_service("/geonames", geonames_routes(app_state.clone()));
+    let app = app.nest_api_service(
+        "/geonames",
+        geonames_routes(app_state.clone()).layer(middleware::from_fn(record_route)),
+    );
 
     #[cfg(feature = "duui")]
-    let app = app.nest_api_service("/v1", duui_routes(app_state.clone()));
+    let app = app.nest_api_service(
+        "/v1",
+        duui_routes(app_state.clone()).layer(middleware::from_fn(record_route)),
+    );
+
+    let app = app.finish_api(&mut api);
+
+    app_state.index.set_routes(Capabilities::routes(&api));
+    app_state.index.load().capabilities.log();
+
+    if preliminary {
+        let shared = app_state.index.clone();
+        // A detached thread rather than a blocking task, which would keep the runtime from
+        // shutting down until the build is done
+        std::thread::Builder::new()
+            .name("index-build".to_string())
+            .spawn(move || {
+                tracing::info!("Building full index in the background");
+                match load_searcher(&args.index, args.index_path.as_deref()) {
+                    Ok(searcher) => {
+                        shared.replace(configure(searcher));
+                        tracing::info!("Replaced startup index with full index");
+                    }
+                    Err(error) => {
+                        tracing::error!(
+                            "Failed to build full index, keeping startup index: {error:#}"
+                        )
+                    }
+                }
+            })?;
+    }
 
     let app = app
-        .finish_api(&mut api)
         .layer(Extension(api))
-        .layer(TraceLayer::new_for_http())
+        .layer(middleware::from_fn(problem_details))
+        .layer(middleware::from_fn_with_state(
+            app_state.in_flight.clone(),
+            InFlight::track,
+        ))
+        .layer(middleware::from_fn(record_route))
+        .layer(TraceLayer::new_for_http().make_span_with(request_span))
+        .layer(middleware::from_fn(access_log))
         .with_state(app_state);
 
-    let listener = tokio::net::TcpListener::bind(format!("{}:{}", args.host, args.port)).await?;
-    axum::serve(listener, app).await?;
+    let listener = listen(&host, port).await?;
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+    tracing::info!("Server stopped");
     Ok(())
 }
 
+/// Take over the first socket passed by systemd socket activation, or bind to `host:port`.
+///
+/// With an inherited socket, the service can be restarted without refusing connections, as
+/// systemd keeps accepting them in the meantime.
+async fn listen(host: &str, port: u16) -> Result<tokio::net::TcpListener, anyhow::Error> {
+    if let Some(listener) = listenfd::ListenFd::from_env().take_tcp_listener(0)? {
+        tracing::info!("Listening on inherited socket {}", listener.local_addr()?);
+        listener.set_nonblocking(true)?;
+        return Ok(tokio::net::TcpListener::from_std(listener)?);
+    }
+    let listener = tokio::net::TcpListener::bind(format!("{host}:{port}")).await?;
+    tracing::info!("Listening on {}", listener.local_addr()?);
+    Ok(listener)
+}
+
+/// How long to wait for blocking tasks after the server stopped before exiting anyway.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Resolves on SIGINT or SIGTERM, letting the server finish the in-flight requests and close the
+/// listener before exiting.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for SIGINT: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut signals) => {
+                signals.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("Received SIGINT, shutting down"),
+        _ = terminate => tracing::info!("Received SIGTERM, shutting down"),
+    }
+}
+
 fn main() -> Result<(), anyhow::Error> {
-    let args = Args::parse();
-
-    tokio::runtime::Builder::new_current_thread()
-        .worker_threads(args.workers)
-        .enable_all()
-        .build()
-        .unwrap()
-        .block_on(async { serve(args).await })
+    let cli = Cli::parse();
+    let log_filter = init_tracing(
+        cli.log_directives(),
+        cli.log_format,
+        matches!(
+            cli.command,
+            Command::Query(_) | Command::Validate(_) | Command::Completions(_)
+        ),
+    );
+
+    match cli.command {
+        Command::Serve(args) => {
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(match args.workers {
+                    0 => std::thread::available_parallelism().map_or(1, usize::from),
+                    workers => workers,
+                })
+                .enable_all()
+                .build()
+                .unwrap();
+            let result = runtime.block_on(async { serve(args, log_filter).await });
+            // Searches still running on the blocking pool have no client left to answer
+            runtime.shutdown_timeout(SHUTDOWN_TIMEOUT);
+            result
+        }
+        Command::Build(args) => build(args),
+        Command::Query(args) => query(args),
+        Command::Validate(args) => validate(args),
+        Command::Completions(args) => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(args.shell, &mut command, name, &mut std::io::stdout());
+            Ok(())
+        }
+    }
 }
@@ -0,0 +1,381 @@
+use aide::axum::routing::{get_with, post_with};
+use aide::axum::{ApiRouter, IntoApiResponse};
+use axum::extract::State;
+use axum::http::header::AUTHORIZATION;
+use axum::http::HeaderMap;
+use axum::{http::StatusCode, Json};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::error::{ApiError, ErrorCode};
+use crate::diagnostics::InFlightRequest;
+use crate::geonames::artifact::IndexManifest;
+use crate::geonames::data::GeoNamesEntry;
+use crate::geonames::geo::is_valid_coordinate;
+use crate::geonames::searcher::{BuildReport, GeoNamesSearcher};
+use crate::AppState;
+
+pub(crate) fn admin_routes(state: AppState) -> ApiRouter {
+    ApiRouter::new()
+        .api_route(
+            "/stats",
+            get_with(admin_stats, |op| {
+                op.description("Build report of the loaded index, including any degradation, and the manifest of the index artifact it was loaded from.")
+                    .response::<200, Json<AdminStats>>()
+            }),
+        )
+        .api_route(
+            "/loglevel",
+            get_with(get_log_level, |op| {
+                op.description("The current log filter.")
+                    .response::<200, Json<LogLevel>>()
+            })
+            .put_with(set_log_level, |op| {
+                op.description("Change the log filter at runtime, using the <code>RUST_LOG</code> directive syntax, e.g. <code>geonames_fst=trace</code>.<br>Sending <code>SIGUSR1</code> to the process cycles through the <code>info</code>, <code>debug</code> and <code>trace</code> levels instead.<br>Requires <code>Authorization: Bearer &lt;token&gt;</code> with the token set by <code>--admin-token</code>.")
+                    .response::<200, Json<LogLevel>>()
+                    .response_with::<400, ApiError, _>(|t| {
+                        t.description("The log filter is invalid.")
+                    })
+                    .response_with::<401, ApiError, _>(|t| {
+                        t.description("The bearer token is missing or wrong.")
+                    })
+                    .response_with::<403, ApiError, _>(|t| {
+                        t.description("The server was started without an admin token.")
+                    })
+                    .response_with::<422, ApiError, _>(|t| {
+                        t.description("The request could not be parsed.")
+                    })
+            }),
+        )
+        .api_route(
+            "/diagnostics",
+            get_with(diagnostics, |op| {
+                op.description("Runtime diagnostics for debugging stuck or slow instances: the state of each runtime worker thread, runtime tasks, in-flight requests and index cache statistics.<br>Requires <code>Authorization: Bearer &lt;token&gt;</code> with the token set by <code>--admin-token</code>.")
+                    .response::<200, Json<Diagnostics>>()
+                    .response_with::<401, ApiError, _>(|t| {
+                        t.description("The bearer token is missing or wrong.")
+                    })
+                    .response_with::<403, ApiError, _>(|t| {
+                        t.description("The server was started without an admin token.")
+                    })
+            }),
+        )
+        .api_route(
+            "/entries",
+            post_with(insert_entries, |op| {
+                op.description("Insert custom entries into an in-memory overlay that is searched alongside the index, e.g. to patch in missing local places without a rebuild. Entries with the id of an earlier overlay entry replace it. The overlay is lost when the server restarts.<br>Overlay entries are found by the name searches, but not by the spatial, completion, n-gram and token routes.<br>Requires <code>Authorization: Bearer &lt;token&gt;</code> with the token set by <code>--admin-token</code>.")
+                    .response::<200, Json<InsertedEntries>>()
+                    .response_with::<400, ApiError, _>(|t| {
+                        t.description("An entry is invalid. No entries were inserted.")
+                    })
+                    .response_with::<401, ApiError, _>(|t| {
+                        t.description("The bearer token is missing or wrong.")
+                    })
+                    .response_with::<403, ApiError, _>(|t| {
+                        t.description("The server was started without an admin token.")
+                    })
+                    .response_with::<409, ApiError, _>(|t| {
+                        t.description("An entry has the id of a GeoNames entry of the index. No entries were inserted.")
+                    })
+                    .response_with::<422, ApiError, _>(|t| {
+                        t.description("The request could not be parsed.")
+                    })
+            }),
+        )
+        .with_state(state)
+}
+
+#[derive(Serialize, JsonSchema)]
+pub(crate) struct AdminStats {
+    build: BuildReport,
+    /// Only present if the index was loaded from a prebuilt artifact.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    manifest: Option<IndexManifest>,
+}
+
+async fn admin_stats(State(state): State<AppState>) -> impl IntoApiResponse {
+    let searcher = state.searcher();
+    (
+        StatusCode::OK,
+        Json(AdminStats {
+            build: searcher.report.clone(),
+            manifest: searcher.manifest.clone(),
+        }),
+    )
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub(crate) struct LogLevel {
+    /// Log filter directives, e.g. `geonames_fst=debug,tower_http=info`.
+    filter: String,
+}
+
+async fn get_log_level(State(state): State<AppState>) -> impl IntoApiResponse {
+    (
+        StatusCode::OK,
+        Json(LogLevel {
+            filter: state.log_filter.current(),
+        }),
+    )
+}
+
+async fn set_log_level(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<LogLevel>,
+) -> Result<impl IntoApiResponse, ApiError> {
+    authorize(&state, &headers)?;
+    match state.log_filter.set(&request.filter) {
+        Ok(()) => Ok((StatusCode::OK, Json(request))),
+        Err(e) => Err(ApiError::new(
+            ErrorCode::InvalidRequest,
+            format!("Invalid log filter: {e}"),
+        )),
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum WorkerState {
+    /// The worker is running tasks or looking for tasks to run.
+    Busy,
+    /// The worker is parked, waiting for new tasks.
+    Parked,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub(crate) struct WorkerDiagnostics {
+    state: WorkerState,
+    /// Time the worker spent running tasks since the server started, in milliseconds.
+    busy_ms: u128,
+    /// Number of times the worker parked since the server started.
+    parks: u64,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub(crate) struct RuntimeDiagnostics {
+    /// Number of worker threads of the async runtime.
+    workers: usize,
+    /// State of each worker thread. A worker that stays busy without its `parks` increasing is
+    /// stuck on a task that does not yield, e.g. a search run outside of the blocking pool.
+    worker_states: Vec<WorkerDiagnostics>,
+    /// Number of tasks that are currently alive on the runtime.
+    alive_tasks: usize,
+    /// Number of tasks waiting in the runtime's global queue.
+    queued_tasks: usize,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub(crate) struct CacheDiagnostics {
+    /// Number of prefixes in the precomputed completion table, `null` if disabled.
+    completion_prefixes: Option<usize>,
+    /// Rough estimate of the heap memory held by the index, in bytes.
+    index_memory_bytes: usize,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub(crate) struct Diagnostics {
+    runtime: RuntimeDiagnostics,
+    /// Requests currently being processed, oldest first. Includes this request.
+    in_flight: Vec<InFlightRequest>,
+    cache: CacheDiagnostics,
+    log_filter: String,
+}
+
+async fn diagnostics(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoApiResponse, ApiError> {
+    // Lists the URIs of all requests in flight and walks the whole index for its memory usage
+    authorize(&state, &headers)?;
+    let metrics = tokio::runtime::Handle::current().metrics();
+    let searcher = state.searcher();
+    Ok((
+        StatusCode::OK,
+        Json(Diagnostics {
+            runtime: RuntimeDiagnostics {
+                workers: metrics.num_workers(),
+                worker_states: (0..metrics.num_workers())
+                    .map(|worker| WorkerDiagnostics {
+                        // The count is odd while the worker is parked
+                        state: match metrics.worker_park_unpark_count(worker) % 2 {
+                            0 => WorkerState::Busy,
+                            _ => WorkerState::Parked,
+                        },
+                        busy_ms: metrics.worker_total_busy_duration(worker).as_millis(),
+                        parks: metrics.worker_park_count(worker),
+                    })
+                    .collect(),
+                alive_tasks: metrics.num_alive_tasks(),
+                queued_tasks: metrics.global_queue_depth(),
+            },
+            in_flight: state.in_flight.snapshot(),
+            cache: CacheDiagnostics {
+                completion_prefixes: searcher
+                    .completions
+                    .as_ref()
+                    .map(|table| table.number_of_prefixes()),
+                index_memory_bytes: searcher.memory_usage(),
+            },
+            log_filter: state.log_filter.current(),
+        }),
+    ))
+}
+
+/// Check the bearer token of a request to an admin route that changes the state of the service
+/// or exposes details of other requests.
+fn authorize(state: &AppState, headers: &HeaderMap) -> Result<(), ApiError> {
+    let Some(token) = &state.admin_token else {
+        return Err(ApiError::new(
+            ErrorCode::Forbidden,
+            "This admin route is disabled, start the service with --admin-token",
+        ));
+    };
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    // Compare digests, so that the time taken does not reveal how much of the token matched
+    let matches = bearer.is_some_and(|bearer| Sha256::digest(bearer) == Sha256::digest(&**token));
+    if !matches {
+        return Err(ApiError::new(
+            ErrorCode::Unauthorized,
+            "Missing or invalid bearer token",
+        ));
+    }
+    Ok(())
+}
+
+/// A place to add to the overlay.
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct CustomEntry {
+    /// Identifier of the entry, which must not be used by a GeoNames entry of the index.
+    id: u64,
+    /// Names to search the entry by. The first one is its canonical name, the others are
+    /// indexed as synonyms.
+    names: Vec<String>,
+    latitude: f32,
+    longitude: f32,
+    /// GeoNames feature class, e.g. `P` for populated places.
+    feature_class: String,
+    /// GeoNames feature code, e.g. `PPL`.
+    feature_code: String,
+    /// ISO-3166 country code.
+    country_code: String,
+    #[serde(default)]
+    adm1: String,
+    #[serde(default)]
+    adm2: String,
+    #[serde(default)]
+    adm3: String,
+    #[serde(default)]
+    adm4: String,
+    #[serde(default)]
+    population: u64,
+    #[serde(default)]
+    elevation: Option<i16>,
+    /// URL of the Wikipedia article.
+    #[serde(default)]
+    wikipedia: Option<String>,
+    /// Wikidata item, e.g. `Q1794`.
+    #[serde(default)]
+    wikidata: Option<String>,
+}
+
+impl CustomEntry {
+    fn validate(&self, searcher: &GeoNamesSearcher) -> Result<(), ApiError> {
+        if searcher.geonames.contains_key(&self.id) {
+            return Err(ApiError::new(
+                ErrorCode::Conflict,
+                format!("Entry {} is part of the index", self.id),
+            ));
+        }
+        if self.names.is_empty() || self.names.iter().any(String::is_empty) {
+            return Err(ApiError::new(
+                ErrorCode::InvalidRequest,
+                format!(
+                    "Entry {} needs at least one name, and no empty names",
+                    self.id
+                ),
+            ));
+        }
+        if !is_valid_coordinate(self.latitude as f64, self.longitude as f64) {
+            return Err(ApiError::new(
+                ErrorCode::InvalidRequest,
+                format!(
+                    "Invalid coordinates of entry {}: latitude must be in [-90, 90] and longitude in [-180, 180]",
+                    self.id
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn into_entry(self) -> (GeoNamesEntry, Vec<String>) {
+        let entry = GeoNamesEntry {
+            id: self.id,
+            name: self.names[0].clone(),
+            latitude: self.latitude,
+            longitude: self.longitude,
+            feature_class: self.feature_class.into(),
+            feature_code: self.feature_code.into(),
+            country_code: self.country_code.into(),
+            adm1: self.adm1,
+            adm2: self.adm2,
+            adm3: self.adm3,
+            adm4: self.adm4,
+            population: self.population,
+            elevation: self.elevation,
+            wikipedia: self.wikipedia,
+            wikidata: self.wikidata,
+        };
+        (entry, self.names)
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct InsertEntries {
+    entries: Vec<CustomEntry>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub(crate) struct InsertedEntries {
+    inserted: usize,
+    /// Number of entries in the overlay after the insert.
+    overlay_entries: usize,
+}
+
+async fn insert_entries(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<InsertEntries>,
+) -> Result<impl IntoApiResponse, ApiError> {
+    authorize(&state, &headers)?;
+    // Hold on to the served index, so that an upgrade does not replace it halfway through
+    state.index.with_current(|index| {
+        request
+            .entries
+            .iter()
+            .try_for_each(|entry| entry.validate(&index.searcher))?;
+
+        let inserted = request.entries.len();
+        let mut overlay = index.searcher.overlay.write().unwrap();
+        for entry in request.entries {
+            let (entry, names) = entry.into_entry();
+            overlay.insert(entry, &names);
+        }
+        tracing::info!(
+            inserted,
+            overlay_entries = overlay.len(),
+            "Inserted overlay entries"
+        );
+        Ok((
+            StatusCode::OK,
+            Json(InsertedEntries {
+                inserted,
+                overlay_entries: overlay.len(),
+            }),
+        ))
+    })
+}
@@ -0,0 +1,85 @@
+use aide::axum::IntoApiResponse;
+use aide::transform::TransformOperation;
+use axum::extract::{Query, State};
+use axum::Json;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_aux::prelude::*;
+
+use super::error::ApiError;
+use super::validate::{FieldErrors, Valid, Validate};
+use super::{cap, run_blocking, Response};
+use crate::diagnostics::RequestSummary;
+use crate::geonames::data::GeoNamesSuggestion;
+use crate::geonames::deadline::Deadline;
+use crate::geonames::searcher::GeoNamesSearcher;
+use crate::AppState;
+
+fn _schemars_default_prefix() -> String {
+    "Frank".to_string()
+}
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct RequestAutocomplete {
+    /// The text typed so far.
+    #[validate(length(min = 1))]
+    #[schemars(default = "_schemars_default_prefix")]
+    pub query: String,
+    /// Maximum number of suggestions, at most the result cap of the service. Defaults to 10.
+    #[serde(
+        default = "default_u32::<10>",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub limit: u32,
+}
+
+impl Validate for RequestAutocomplete {
+    fn check(&self, errors: &mut FieldErrors) {
+        errors.query("query", &self.query);
+    }
+}
+
+pub(crate) async fn autocomplete(
+    State(state): State<AppState>,
+    Valid(Json(request)): Valid<Json<RequestAutocomplete>>,
+) -> impl IntoApiResponse {
+    let summary = RequestSummary::query(&request.query);
+    summary.attach(complete(state, request).await)
+}
+
+/// Like `autocomplete`, but with the parameters in the query string, so that the suggestions can
+/// be cached by HTTP caches.
+pub(crate) async fn autocomplete_get(
+    State(state): State<AppState>,
+    Valid(Query(request)): Valid<Query<RequestAutocomplete>>,
+) -> impl IntoApiResponse {
+    complete(state, request).await
+}
+
+async fn complete(
+    state: AppState,
+    request: RequestAutocomplete,
+) -> Result<Json<Response<GeoNamesSuggestion>>, ApiError> {
+    let search = move |searcher: &GeoNamesSearcher, _: Deadline| {
+        Ok(searcher
+            .completions(
+                &request.query,
+                cap(request.limit as usize, searcher.max_results),
+            )
+            .into_iter()
+            .map(GeoNamesSuggestion::from)
+            .collect::<Vec<_>>())
+    };
+    run_blocking(&state, search)
+        .await
+        .map(|results| Json(Response::results(results)))
+}
+
+pub(crate) fn autocomplete_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Typeahead suggestions for the text typed so far: GeoNames entries whose names start with the query, one per GeoNames id, ranked by population and feature class.<br>Returns only the id, matching name, country and coordinates of each entry. GET responses carry an <code>ETag</code> and <code>Cache-Control</code>, so that clients and HTTP caches can reuse them.")
+        .response::<200, Json<Response<GeoNamesSuggestion>>>()
+        .response_with::<304, (), _>(|t| {
+            t.description("The suggestions did not change since the ETag given in If-None-Match.")
+        })
+        .response_with::<422, ApiError, _>(|t| t.description("The query was empty or too long."))
+        .response_with::<504, ApiError, _>(|t| t.description("The search exceeded the search timeout."))
+}
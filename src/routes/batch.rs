@@ -0,0 +1,405 @@
+#[cfg(feature = "geonames_routes")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "geonames_routes")]
+use aide::axum::IntoApiResponse;
+#[cfg(feature = "geonames_routes")]
+use aide::transform::TransformOperation;
+#[cfg(feature = "geonames_routes")]
+use axum::extract::State;
+#[cfg(feature = "geonames_routes")]
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+#[cfg(feature = "geonames_routes")]
+use axum::response::IntoResponse;
+#[cfg(feature = "geonames_routes")]
+use axum::Json;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize, Serializer};
+#[cfg(feature = "geonames_routes")]
+use tokio::sync::mpsc;
+
+use super::cascade::RequestCascade;
+use super::contains::RequestContains;
+use super::error::ApiError;
+#[cfg(feature = "geonames_routes")]
+use super::error::ErrorCode;
+use super::find::RequestFind;
+#[cfg(feature = "geonames_routes")]
+use super::format::ResponseFormat;
+use super::fuzzy::RequestFuzzy;
+use super::glob::RequestGlob;
+use super::levenshtein::RequestLevenshtein;
+use super::ngram::RequestNgram;
+use super::regex::RequestRegex;
+use super::select::{SelectFields, Selected};
+use super::starts_with::RequestStartsWith;
+#[cfg(feature = "geonames_routes")]
+use super::stream::{accepts, event, event_stream, EVENT_STREAM};
+use super::tokens::RequestTokens;
+#[cfg(feature = "geonames_routes")]
+use super::validate::Valid;
+#[cfg(feature = "geonames_routes")]
+use super::validate::MAX_BATCH_QUERIES;
+use super::validate::{FieldErrors, Validate};
+use super::Search;
+#[cfg(feature = "geonames_routes")]
+use super::FAILED_ITEMS_HEADER;
+#[cfg(feature = "geonames_routes")]
+use super::{run_blocking, run_ordered};
+#[cfg(feature = "geonames_routes")]
+use crate::diagnostics::RequestSummary;
+use crate::geonames::data::{EntryField, GeoNamesSearchResult, GeoNamesSearchResultWithDist};
+use crate::geonames::deadline::Deadline;
+use crate::geonames::searcher::{GeoNamesSearcher, Page};
+#[cfg(feature = "geonames_routes")]
+use crate::AppState;
+
+/// A single query of a batch, using the same parameters as the respective route.
+#[derive(Deserialize, JsonSchema)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub(crate) enum BatchQuery {
+    Find(RequestFind),
+    Regex(RequestRegex),
+    StartsWith(RequestStartsWith),
+    Contains(RequestContains),
+    Glob(RequestGlob),
+    Fuzzy(RequestFuzzy),
+    Levenshtein(RequestLevenshtein),
+    Cascade(RequestCascade),
+    Ngram(RequestNgram),
+    Tokens(RequestTokens),
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(untagged)]
+pub(crate) enum BatchResults {
+    Results(Vec<GeoNamesSearchResult>),
+    ResultsWithDist(Vec<GeoNamesSearchResultWithDist>),
+    /// Results with only the requested entry fields.
+    Selected(#[schemars(with = "serde_json::Value")] SelectedResults),
+}
+
+impl SelectFields for BatchResults {
+    fn serialize_selected<S: Serializer>(
+        &self,
+        fields: &[EntryField],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Results(results) => results.serialize_selected(fields, serializer),
+            Self::ResultsWithDist(results) => results.serialize_selected(fields, serializer),
+            Self::Selected(results) => results.serialize(serializer),
+        }
+    }
+}
+
+/// Results of a query that restricted the entry fields.
+pub(crate) struct SelectedResults {
+    results: Box<BatchResults>,
+    fields: Vec<EntryField>,
+}
+
+impl Serialize for SelectedResults {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Selected(&*self.results, &self.fields).serialize(serializer)
+    }
+}
+
+impl BatchQuery {
+    /// A query of the given mode searching for `text`, with the options of the respective route,
+    /// e.g. for searches from the command line.
+    pub(crate) fn from_text(
+        mode: &str,
+        text: &str,
+        options: &serde_json::Map<String, serde_json::Value>,
+    ) -> Result<Self, serde_json::Error> {
+        let field = match mode {
+            "regex" => "regex",
+            "glob" => "pattern",
+            _ => "query",
+        };
+        let mut query = options.clone();
+        query.insert("mode".to_string(), mode.into());
+        query.insert(field.to_string(), text.into());
+        serde_json::from_value(query.into())
+    }
+
+    /// The entry fields requested by the query, if restricted.
+    fn fields(&self) -> Option<&[EntryField]> {
+        let extras = match self {
+            Self::Find(request) => &request.opts.extras,
+            Self::Regex(request) => &request.opts.extras,
+            Self::StartsWith(request) => &request.opts.extras,
+            Self::Contains(request) => &request.opts.extras,
+            Self::Glob(request) => &request.opts.extras,
+            Self::Fuzzy(request) => &request.opts.extras,
+            Self::Levenshtein(request) => &request.opts.extras,
+            // The stages of a cascade select their own fields
+            Self::Cascade(_) => return None,
+            Self::Ngram(request) => &request.opts.extras,
+            Self::Tokens(request) => &request.opts.extras,
+        };
+        extras.fields.as_deref()
+    }
+
+    /// Validate and run the query, returning its results as a successful item.
+    pub(crate) fn search(
+        &self,
+        searcher: &GeoNamesSearcher,
+        deadline: Deadline,
+    ) -> Result<BatchItem, ApiError> {
+        self.validate()?;
+
+        fn item<T>(page: Page<T>, results: fn(Vec<T>) -> BatchResults) -> BatchItem {
+            BatchItem {
+                status: StatusCode::OK.as_u16(),
+                results: Some(results(page.results)),
+                next_cursor: page.next_cursor,
+                truncated: page.total.is_some(),
+                total: page.total,
+                error: None,
+                duration_ms: None,
+            }
+        }
+
+        let mut item = match self {
+            Self::Find(request) => item(request.search(searcher, deadline)?, BatchResults::Results),
+            Self::Regex(request) => {
+                item(request.search(searcher, deadline)?, BatchResults::Results)
+            }
+            Self::StartsWith(request) => item(
+                request.search(searcher, deadline)?,
+                BatchResults::ResultsWithDist,
+            ),
+            Self::Contains(request) => item(
+                request.search(searcher, deadline)?,
+                BatchResults::ResultsWithDist,
+            ),
+            Self::Glob(request) => item(request.search(searcher, deadline)?, BatchResults::Results),
+            Self::Fuzzy(request) => item(
+                request.search(searcher, deadline)?,
+                BatchResults::ResultsWithDist,
+            ),
+            Self::Levenshtein(request) => item(
+                request.search(searcher, deadline)?,
+                BatchResults::ResultsWithDist,
+            ),
+            Self::Cascade(request) => item(
+                request.search(searcher, deadline)?,
+                BatchResults::ResultsWithDist,
+            ),
+            Self::Ngram(request) => item(
+                request.search(searcher, deadline)?,
+                BatchResults::ResultsWithDist,
+            ),
+            Self::Tokens(request) => {
+                item(request.search(searcher, deadline)?, BatchResults::Results)
+            }
+        };
+
+        if let Some(fields) = self.fields() {
+            item.results = item.results.map(|results| {
+                BatchResults::Selected(SelectedResults {
+                    results: Box::new(results),
+                    fields: fields.to_vec(),
+                })
+            });
+        }
+        Ok(item)
+    }
+}
+
+impl Validate for BatchQuery {
+    fn check(&self, errors: &mut FieldErrors) {
+        match self {
+            Self::Find(request) => request.check(errors),
+            Self::Regex(request) => request.check(errors),
+            Self::StartsWith(request) => request.check(errors),
+            Self::Contains(request) => request.check(errors),
+            Self::Glob(request) => request.check(errors),
+            Self::Fuzzy(request) => request.check(errors),
+            Self::Levenshtein(request) => request.check(errors),
+            Self::Cascade(request) => request.check(errors),
+            Self::Ngram(request) => request.check(errors),
+            Self::Tokens(request) => request.check(errors),
+        }
+    }
+}
+
+#[cfg(feature = "geonames_routes")]
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct RequestBatch {
+    /// The queries to run, at most 1000. Invalid queries fail on their own, like failing searches.
+    pub queries: Vec<BatchQuery>,
+    /// Abort the batch at the first failing query. By default, the remaining queries are still
+    /// processed and failures are reported per item.
+    #[serde(default)]
+    pub fail_fast: bool,
+}
+
+#[cfg(feature = "geonames_routes")]
+impl Validate for RequestBatch {
+    fn check(&self, errors: &mut FieldErrors) {
+        if self.queries.len() > MAX_BATCH_QUERIES {
+            errors.add(
+                "queries",
+                format!("must contain at most {MAX_BATCH_QUERIES} queries"),
+            );
+        }
+    }
+}
+
+/// The outcome of a single batch query, with an HTTP-style status.
+#[derive(Serialize, JsonSchema)]
+pub(crate) struct BatchItem {
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub results: Option<BatchResults>,
+    /// Cursor of the next page, if the query was paged and has more results.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    /// Whether the results were truncated to the result cap of the service.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// Number of results before truncation, if `truncated`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ApiError>,
+    /// Time taken by the query in milliseconds, absent if it was skipped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<f64>,
+}
+
+impl BatchItem {
+    /// A query that failed with `error`.
+    pub(crate) fn failed(error: ApiError) -> Self {
+        Self {
+            status: error.status,
+            results: None,
+            next_cursor: None,
+            truncated: false,
+            total: None,
+            error: Some(error),
+            duration_ms: None,
+        }
+    }
+
+    #[cfg(feature = "geonames_routes")]
+    fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration_ms = Some(duration.as_secs_f64() * 1000.0);
+        self
+    }
+
+    /// Placeholder for queries that were not run because an earlier one failed in fail-fast mode.
+    #[cfg(feature = "geonames_routes")]
+    pub(crate) fn skipped() -> Self {
+        Self::failed(ApiError::new(
+            ErrorCode::Skipped,
+            "Skipped after an earlier query failed",
+        ))
+    }
+}
+
+#[cfg(feature = "geonames_routes")]
+#[derive(Serialize, JsonSchema)]
+pub(crate) struct BatchResponse {
+    /// One item per query, in the order of the request.
+    pub results: Vec<BatchItem>,
+    /// Number of queries that failed or were skipped.
+    pub failed: usize,
+}
+
+#[cfg(feature = "geonames_routes")]
+/// Summary sent as the final `done` event of a streamed batch.
+#[derive(Serialize, JsonSchema)]
+pub(crate) struct BatchSummary {
+    /// Number of queries that failed or were skipped.
+    pub failed: usize,
+}
+
+#[cfg(feature = "geonames_routes")]
+/// Run the queries concurrently on the blocking thread pool, sending each item in the order of
+/// the queries as soon as it and all earlier ones are done. Stops early if the receiver is
+/// dropped.
+fn run_batch(state: AppState, request: RequestBatch) -> mpsc::Receiver<BatchItem> {
+    let concurrency = state.batch_concurrency;
+    run_ordered(
+        request.queries,
+        concurrency,
+        request.fail_fast,
+        move |query| {
+            let state = state.clone();
+            async move {
+                let started = Instant::now();
+                let item = run_blocking(&state, move |searcher, deadline| {
+                    query.search(searcher, deadline)
+                })
+                .await
+                .unwrap_or_else(BatchItem::failed);
+                item.with_duration(started.elapsed())
+            }
+        },
+        |item| item.status != StatusCode::OK.as_u16(),
+        |_| BatchItem::skipped(),
+    )
+}
+
+#[cfg(feature = "geonames_routes")]
+pub(crate) async fn batch(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    headers: HeaderMap,
+    Valid(Json(request)): Valid<Json<RequestBatch>>,
+) -> impl IntoApiResponse {
+    let summary = RequestSummary::batch(request.queries.len());
+    let fail_fast = request.fail_fast;
+    let mut items = run_batch(state, request);
+
+    if accepts(&headers, EVENT_STREAM) {
+        return summary.attach(event_stream(|events| async move {
+            let (mut index, mut failed) = (0, 0);
+            while let Some(item) = items.recv().await {
+                failed += usize::from(item.status != StatusCode::OK.as_u16());
+                let item = event("item", &item).id(index.to_string());
+                if events.send(item).await.is_err() {
+                    return;
+                }
+                index += 1;
+            }
+            let _ = events.send(event("done", &BatchSummary { failed })).await;
+        }));
+    }
+
+    let mut results = Vec::new();
+    while let Some(item) = items.recv().await {
+        results.push(item);
+    }
+    let failed = results.iter().filter(|item| item.status != 200).count();
+    let status = match results.iter().find(|item| item.status != 200) {
+        Some(item) if fail_fast => {
+            StatusCode::from_u16(item.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+        _ => StatusCode::OK,
+    };
+
+    summary.attach(
+        (
+            status,
+            [(FAILED_ITEMS_HEADER, failed.to_string())],
+            format.encode(BatchResponse { results, failed }),
+        )
+            .into_response(),
+    )
+}
+
+#[cfg(feature = "geonames_routes")]
+pub(crate) fn batch_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Run a batch of find, regex, starts_with, contains, glob, fuzzy, levenshtein, ngram and tokens queries.<br>Failing queries do not invalidate the batch: each item carries its own HTTP-style <code>status</code> and the number of failed items is reported in the <code>x-failed-items</code> header. With <code>fail_fast</code>, processing stops at the first failure, the remaining items are reported as skipped (424), and the failure's status is returned for the whole request.<br>Queries are searched concurrently, up to <code>--batch-concurrency</code> at a time, but items are reported in the order of the queries with the time each query took in <code>duration_ms</code>. The search timeout applies to each query on its own.<br>With <code>Accept: text/event-stream</code>, each item is sent as an <code>item</code> event as soon as its query is done, with the index of the query as event id, followed by a final <code>done</code> event with the number of failed items.")
+        .response::<200, Json<BatchResponse>>()
+        .response_with::<422, ApiError, _>(|t| t.description("The batch could not be parsed or had too many queries."))
+        .default_response_with::<Json<BatchResponse>, _>(|t| {
+            t.description("A query failed and `fail_fast` was set. The status is the `status` of the failed item, e.g. 400 for an invalid query, 422 for invalid options or 504 if the query exceeded the search timeout.")
+        })
+}
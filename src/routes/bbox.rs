@@ -0,0 +1,123 @@
+use aide::axum::IntoApiResponse;
+use aide::transform::TransformOperation;
+use axum::extract::State;
+use axum::Json;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_aux::prelude::*;
+
+use super::error::{ApiError, ErrorCode};
+use super::format::ResponseFormat;
+use super::{
+    _schemars_default_filter, dedup_results, filter_results, limit_page, rank_results,
+    run_blocking, sort_results, spatial_response, ClusterOptions, FilterResults, Response,
+    ResultOptions, Search,
+};
+use crate::geonames::data::GeoNamesEntry;
+use crate::geonames::deadline::Deadline;
+use crate::geonames::geo::{is_valid_coordinate, BoundingBox};
+use crate::geonames::searcher::{GeoNamesSearcher, Page};
+use crate::AppState;
+
+fn _schemars_default_min_lat() -> f64 {
+    50.0
+}
+fn _schemars_default_min_lon() -> f64 {
+    8.0
+}
+fn _schemars_default_max_lat() -> f64 {
+    50.5
+}
+fn _schemars_default_max_lon() -> f64 {
+    9.0
+}
+#[derive(Clone, Deserialize, JsonSchema)]
+pub(crate) struct RequestBoundingBox {
+    /// Southern edge of the box in degrees.
+    #[schemars(default = "_schemars_default_min_lat")]
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_lat: f64,
+    /// Western edge of the box in degrees. If greater than `max_lon`, the box crosses the antimeridian.
+    #[schemars(default = "_schemars_default_min_lon")]
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_lon: f64,
+    /// Northern edge of the box in degrees.
+    #[schemars(default = "_schemars_default_max_lat")]
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_lat: f64,
+    /// Eastern edge of the box in degrees.
+    #[schemars(default = "_schemars_default_max_lon")]
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_lon: f64,
+    #[schemars(default = "_schemars_default_filter")]
+    pub filter: Option<FilterResults>,
+    #[serde(flatten)]
+    pub results: ResultOptions,
+    /// Return clusters of nearby results instead of the results themselves, e.g.
+    /// `{"grid_size_km": 50}`.
+    #[serde(default)]
+    pub cluster: Option<ClusterOptions>,
+    /// Also return the bounding box and centroid of the returned results as `extent`.
+    #[serde(default)]
+    pub extent: bool,
+}
+
+pub(crate) async fn bbox(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    Json(request): Json<RequestBoundingBox>,
+) -> impl IntoApiResponse {
+    let (cluster, extent) = (request.cluster, request.extent);
+    let result = run_blocking(&state, move |searcher, deadline| {
+        request.search(searcher, deadline)
+    })
+    .await;
+    spatial_response(format, result, &cluster, extent, None)
+}
+
+impl Search for RequestBoundingBox {
+    type Item = GeoNamesEntry;
+
+    fn search(
+        &self,
+        searcher: &GeoNamesSearcher,
+        _deadline: Deadline,
+    ) -> Result<Page<Self::Item>, ApiError> {
+        if !is_valid_coordinate(self.min_lat, self.min_lon)
+            || !is_valid_coordinate(self.max_lat, self.max_lon)
+        {
+            return Err(ApiError::new(
+                ErrorCode::InvalidRequest,
+                "Invalid coordinates: latitudes must be in [-90, 90] and longitudes in [-180, 180]",
+            ));
+        }
+        if self.min_lat > self.max_lat {
+            return Err(ApiError::new(
+                ErrorCode::InvalidRequest,
+                "Invalid bounding box: min_lat must not be greater than max_lat",
+            ));
+        }
+
+        let bbox = BoundingBox {
+            min_lat: self.min_lat,
+            min_lon: self.min_lon,
+            max_lat: self.max_lat,
+            max_lon: self.max_lon,
+        };
+        let results = filter_results(searcher.bbox(&bbox), &self.filter);
+        let results = dedup_results(results, self.results.dedup);
+        let results = rank_results(results, self.results.rank_by);
+        let results = sort_results(results, self.results.sort);
+        let page = limit_page(results.into(), self.results.limit, searcher.max_results);
+        Ok(page)
+    }
+}
+
+pub(crate) fn bbox_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Find all GeoNames entries inside a bounding box, e.g. a map viewport.<br>Boxes with <code>min_lon</code> greater than <code>max_lon</code> cross the antimeridian.<br>With <code>cluster</code>, nearby results are grouped into clusters with their centroid, count and most populous entry, which are returned instead.")
+        .response::<200, Json<Response<GeoNamesEntry>>>()
+        .response_with::<400, ApiError, _>(|t| {
+            t.description("The coordinates of the box were invalid.")
+        })
+        .response_with::<504, ApiError, _>(|t| t.description("The search exceeded the search timeout."))
+}
@@ -0,0 +1,57 @@
+use axum::extract::{Request, State};
+use axum::http::header::{CACHE_CONTROL, ETAG, IF_NONE_MATCH, VARY};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::AppState;
+
+/// Whether `If-None-Match` lists the entity tag, using the weak comparison of RFC 9110.
+fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+}
+
+/// Middleware making the responses of GET routes cacheable.
+///
+/// The index only changes while the server runs when entries are inserted into the overlay, so all
+/// successful GET responses share the ETag of the index generation and overlay revision, together
+/// with a `Cache-Control` max age. Requests whose
+/// `If-None-Match` lists the current ETag are answered with 304 Not Modified without searching.
+pub(crate) async fn conditional_get(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::GET {
+        return next.run(request).await;
+    }
+
+    // Entries inserted at runtime change the results, so they are part of the ETag
+    let index = state.index.load();
+    let revision = index.searcher.overlay.read().unwrap().revision();
+    let etag = format!("W/\"{}-{revision}\"", index.generation);
+    let headers = [
+        (ETAG, HeaderValue::from_str(&etag).unwrap()),
+        (
+            CACHE_CONTROL,
+            HeaderValue::from_str(&format!("public, max-age={}", state.cache_max_age_s)).unwrap(),
+        ),
+        // Binary formats are negotiated from the Accept header
+        (VARY, HeaderValue::from_static("accept")),
+    ];
+    if if_none_match(request.headers(), &etag) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+
+    let mut response = next.run(request).await;
+    if response.status().is_success() {
+        response.headers_mut().extend(headers);
+    }
+    response
+}
@@ -0,0 +1,121 @@
+use aide::axum::IntoApiResponse;
+use aide::openapi::OpenApi;
+use aide::transform::TransformOperation;
+use axum::extract::State;
+use axum::{http::StatusCode, Json};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::geonames::score::ScoreWeights;
+use crate::geonames::searcher::{Datasets, GeoNamesSearcher};
+use crate::AppState;
+
+/// Summary of what this particular build and configuration of the service supports.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub(crate) struct Capabilities {
+    pub version: &'static str,
+    pub datasets: Datasets,
+    /// Number of distinct search keys in the FST.
+    pub number_of_keys: usize,
+    pub number_of_geonames: usize,
+    /// Compile-time features enabled in this binary.
+    pub features: Vec<&'static str>,
+    pub spatial_index: bool,
+    /// Whether the trigram index for `/geonames/ngram` was built.
+    pub ngram_index: bool,
+    /// Whether the word index for `/geonames/tokens` was built.
+    pub token_index: bool,
+    /// Whether country shapes for `/geonames/country` were loaded.
+    pub country_shapes: bool,
+    /// Weights of the relevance `score` of search results.
+    pub score_weights: ScoreWeights,
+    /// Routes mounted in this service.
+    pub routes: Vec<String>,
+    /// Estimated heap footprint of the index in bytes.
+    pub memory_bytes: usize,
+    /// Whether a small startup index is served while the full index is built in the background.
+    pub preliminary: bool,
+}
+
+impl Capabilities {
+    pub fn new(searcher: &GeoNamesSearcher, routes: Vec<String>) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            datasets: searcher.datasets.clone(),
+            number_of_keys: searcher.map.len(),
+            number_of_geonames: searcher.geonames.len(),
+            features: enabled_features(),
+            spatial_index: true,
+            ngram_index: searcher.ngrams.is_some(),
+            token_index: searcher.tokens.is_some(),
+            country_shapes: searcher.shapes.is_some(),
+            score_weights: searcher.score_weights,
+            routes,
+            memory_bytes: searcher.memory_usage(),
+            preliminary: false,
+        }
+    }
+
+    /// The routes mounted in the API, sorted.
+    pub fn routes(api: &OpenApi) -> Vec<String> {
+        let mut routes: Vec<String> = api
+            .paths
+            .iter()
+            .flat_map(|paths| paths.paths.keys().cloned())
+            .collect();
+        routes.sort();
+        routes
+    }
+
+    pub fn log(&self) {
+        tracing::info!(
+            version = self.version,
+            geonames_files = self.datasets.geonames.len(),
+            alternate_files = self.datasets.alternate.len(),
+            languages = ?self.datasets.languages,
+            keys = self.number_of_keys,
+            geonames = self.number_of_geonames,
+            features = ?self.features,
+            spatial_index = self.spatial_index,
+            ngram_index = self.ngram_index,
+            token_index = self.token_index,
+            country_shapes = self.country_shapes,
+            score_weights = ?self.score_weights,
+            routes = ?self.routes,
+            memory_bytes = self.memory_bytes,
+            preliminary = self.preliminary,
+            "Capabilities"
+        );
+    }
+}
+
+fn enabled_features() -> Vec<&'static str> {
+    vec![
+        #[cfg(feature = "geonames_routes")]
+        "geonames_routes",
+        #[cfg(feature = "duui")]
+        "duui",
+        #[cfg(feature = "bzip2")]
+        "bzip2",
+        #[cfg(feature = "gzip")]
+        "gzip",
+        #[cfg(feature = "xz")]
+        "xz",
+        #[cfg(feature = "zstd")]
+        "zstd",
+        #[cfg(feature = "http")]
+        "http",
+    ]
+}
+
+pub(crate) async fn capabilities(State(state): State<AppState>) -> impl IntoApiResponse {
+    (
+        StatusCode::OK,
+        Json(state.index.load().capabilities.clone()),
+    )
+}
+
+pub(crate) fn capabilities_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Summary of the datasets, features and routes supported by this service.")
+        .response::<200, Json<Capabilities>>()
+}
@@ -0,0 +1,214 @@
+#[cfg(feature = "geonames_routes")]
+use aide::axum::IntoApiResponse;
+#[cfg(feature = "geonames_routes")]
+use aide::transform::TransformOperation;
+#[cfg(feature = "geonames_routes")]
+use axum::extract::State;
+#[cfg(feature = "geonames_routes")]
+use axum::Json;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_aux::prelude::*;
+
+use super::error::ApiError;
+use super::find::{RequestFind, RequestOptsFind};
+#[cfg(feature = "geonames_routes")]
+use super::format::ResponseFormat;
+use super::fuzzy::{RequestFuzzy, RequestOptsFuzzy};
+use super::levenshtein::{RequestLevenshtein, RequestOptsLevenshtein};
+use super::starts_with::{RequestOptsStartsWith, RequestStartsWith};
+#[cfg(feature = "geonames_routes")]
+use super::validate::Valid;
+use super::validate::{FieldErrors, Validate};
+use super::{FilterResults, Search, _schemars_default_filter};
+#[cfg(feature = "geonames_routes")]
+use super::{run_blocking, Response};
+#[cfg(feature = "geonames_routes")]
+use crate::diagnostics::RequestSummary;
+use crate::geonames::data::GeoNamesSearchResultWithDist;
+use crate::geonames::deadline::Deadline;
+use crate::geonames::searcher::{GeoNamesSearcher, Page};
+#[cfg(feature = "geonames_routes")]
+use crate::AppState;
+
+/// A stage of a cascade, with the options of the respective route.
+#[derive(Clone, Deserialize, JsonSchema)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub(crate) enum CascadeStage {
+    Find(RequestOptsFind),
+    StartsWith(RequestOptsStartsWith),
+    Fuzzy(RequestOptsFuzzy),
+    Levenshtein(RequestOptsLevenshtein),
+}
+
+impl CascadeStage {
+    pub(crate) fn filter_mut(&mut self) -> &mut Option<FilterResults> {
+        match self {
+            Self::Find(opts) => &mut opts.filter,
+            Self::StartsWith(opts) => &mut opts.filter,
+            Self::Fuzzy(opts) => &mut opts.filter,
+            Self::Levenshtein(opts) => &mut opts.filter,
+        }
+    }
+
+    fn cursor(&self) -> Option<&String> {
+        match self {
+            Self::Find(_) => None,
+            Self::StartsWith(opts) => opts.page.cursor.as_ref(),
+            Self::Fuzzy(opts) => opts.page.cursor.as_ref(),
+            Self::Levenshtein(opts) => opts.page.cursor.as_ref(),
+        }
+    }
+
+    /// Run the stage with the filter of the cascade if it has none of its own.
+    fn search(
+        &self,
+        searcher: &GeoNamesSearcher,
+        deadline: Deadline,
+        query: &str,
+        filter: &Option<FilterResults>,
+    ) -> Result<Page<GeoNamesSearchResultWithDist>, ApiError> {
+        let mut stage = self.clone();
+        if stage.filter_mut().is_none() {
+            *stage.filter_mut() = filter.clone();
+        }
+        let query = query.to_string();
+        Ok(match stage {
+            Self::Find(opts) => RequestFind { query, opts }
+                .search(searcher, deadline)?
+                .map(|results| results.into_iter().map(Into::into).collect()),
+            Self::StartsWith(opts) => {
+                RequestStartsWith { query, opts }.search(searcher, deadline)?
+            }
+            Self::Fuzzy(opts) => RequestFuzzy { query, opts }.search(searcher, deadline)?,
+            Self::Levenshtein(opts) => {
+                RequestLevenshtein { query, opts }.search(searcher, deadline)?
+            }
+        })
+    }
+}
+
+impl Validate for CascadeStage {
+    fn check(&self, errors: &mut FieldErrors) {
+        match self {
+            Self::Find(_) => {}
+            Self::StartsWith(opts) => opts.check(errors),
+            Self::Fuzzy(opts) => opts.check(errors),
+            Self::Levenshtein(opts) => opts.check(errors),
+        }
+        if self.cursor().is_some() {
+            errors.add("cursor", "is not supported in a cascade");
+        }
+    }
+}
+
+fn default_stages() -> Vec<CascadeStage> {
+    let stages = serde_json::json!([
+        { "mode": "find" },
+        { "mode": "starts_with" },
+        { "mode": "levenshtein", "max_dist": 1 },
+    ]);
+    serde_json::from_value(stages).expect("the default stages are valid")
+}
+fn _default_min_results() -> usize {
+    1
+}
+#[derive(Clone, Deserialize, JsonSchema)]
+pub(crate) struct RequestOptsCascade {
+    /// The searches to try in order, stopping at the first with at least `min_results` results.
+    /// Defaults to `find`, then `starts_with`, then `levenshtein` with a `max_dist` of 1.
+    #[serde(default = "default_stages")]
+    pub stages: Vec<CascadeStage>,
+    /// Number of results for a stage to end the cascade. Defaults to 1. If no stage has enough
+    /// results, those of the first stage with any results are returned.
+    #[serde(
+        default = "_default_min_results",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub min_results: usize,
+    /// Filter of the stages without a `filter` of their own.
+    #[schemars(default = "_schemars_default_filter")]
+    pub filter: Option<FilterResults>,
+}
+
+fn _schemars_default_cascade_query() -> String {
+    "Frankfurt".to_string()
+}
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct RequestCascade {
+    /// The search query (name of the GeoNames entity).
+    #[schemars(default = "_schemars_default_cascade_query")]
+    pub query: String,
+
+    #[serde(flatten)]
+    pub opts: RequestOptsCascade,
+}
+
+#[cfg(feature = "geonames_routes")]
+pub(crate) async fn cascade(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    Valid(Json(request)): Valid<Json<RequestCascade>>,
+) -> impl IntoApiResponse {
+    let summary = RequestSummary::query(&request.query);
+    let response = run_blocking(&state, move |searcher, deadline| {
+        request.search(searcher, deadline)
+    })
+    .await
+    .map(|page| format.encode(Response::from(page)));
+    summary.attach(response)
+}
+
+impl Validate for RequestCascade {
+    fn check(&self, errors: &mut FieldErrors) {
+        errors.query("query", &self.query);
+        self.opts.check(errors);
+    }
+}
+
+impl Validate for RequestOptsCascade {
+    fn check(&self, errors: &mut FieldErrors) {
+        if self.stages.is_empty() {
+            errors.add("stages", "must not be empty");
+        }
+        for (i, stage) in self.stages.iter().enumerate() {
+            let mut nested = FieldErrors::default();
+            stage.check(&mut nested);
+            errors.nested(&format!("stages[{i}]"), nested);
+        }
+        if self.min_results == 0 {
+            errors.add("min_results", "must be at least 1");
+        }
+    }
+}
+
+impl Search for RequestCascade {
+    type Item = GeoNamesSearchResultWithDist;
+
+    fn search(
+        &self,
+        searcher: &GeoNamesSearcher,
+        deadline: Deadline,
+    ) -> Result<Page<Self::Item>, ApiError> {
+        let mut fallback: Option<Page<Self::Item>> = None;
+        for stage in &self.opts.stages {
+            let page = stage.search(searcher, deadline, &self.query, &self.opts.filter)?;
+            if page.results.len() >= self.opts.min_results {
+                return Ok(page);
+            }
+            if fallback.as_ref().is_none_or(|page| page.results.is_empty()) {
+                fallback = Some(page);
+            }
+        }
+        Ok(fallback.unwrap_or_else(|| Vec::new().into()))
+    }
+}
+
+#[cfg(feature = "geonames_routes")]
+pub(crate) fn cascade_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Try several searches in order and return the results of the first one with at least <code>min_results</code> results, e.g. an exact <code>find</code>, then <code>starts_with</code>, then a <code>levenshtein</code> search with a small <code>max_dist</code>. Each stage takes the options of the respective route, except <code>cursor</code>.")
+        .response::<200, Json<Response<GeoNamesSearchResultWithDist>>>()
+        .response_with::<400, ApiError, _>(|t| t.description("A stage exceeded the maximum number of states (`query_too_complex`)."))
+        .response_with::<422, ApiError, _>(|t| t.description("The query was empty or too long, there were no stages, or the options of a stage were invalid."))
+        .response_with::<504, ApiError, _>(|t| t.description("The search exceeded the search timeout."))
+}
@@ -0,0 +1,101 @@
+use aide::axum::IntoApiResponse;
+use aide::transform::TransformOperation;
+use axum::extract::State;
+use axum::Json;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::error::{ApiError, ErrorCode};
+use super::run_blocking;
+use crate::geonames::data::GeoNamesEntry;
+use crate::geonames::distance::EditMetric;
+use crate::geonames::geo::{haversine_km, is_valid_coordinate};
+use crate::geonames::searcher::GeoNamesSearcher;
+use crate::AppState;
+
+fn _schemars_default_a() -> u64 {
+    2925533
+}
+fn _schemars_default_b() -> u64 {
+    2874225
+}
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct RequestCompare {
+    /// GeoNames id of the first entry.
+    #[schemars(default = "_schemars_default_a")]
+    pub a: u64,
+    /// GeoNames id of the second entry.
+    #[schemars(default = "_schemars_default_b")]
+    pub b: u64,
+    /// Edit distance between the names.
+    #[serde(default)]
+    pub metric: EditMetric,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct Comparison {
+    /// Edit distance between the canonical names of the entries.
+    pub name_distance: usize,
+    /// The country and administrative divisions containing both entries, from the country down.
+    pub shared_ancestors: Vec<GeoNamesEntry>,
+    /// Great-circle distance in kilometers, if both entries have valid coordinates.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance_km: Option<f64>,
+    pub a: GeoNamesEntry,
+    pub b: GeoNamesEntry,
+}
+
+impl RequestCompare {
+    fn compare(&self, searcher: &GeoNamesSearcher) -> Result<Comparison, ApiError> {
+        let entry = |id: u64| {
+            searcher.geonames.get(&id).ok_or(ApiError::new(
+                ErrorCode::NotFound,
+                format!("Unknown GeoNames id {id}"),
+            ))
+        };
+        let (a, b) = (entry(self.a)?, entry(self.b)?);
+
+        // Ancestors are shared down to the first level at which the entries differ, as divisions
+        // of different parents are never the same
+        let shared_ancestors = searcher
+            .admin
+            .parents(a)
+            .into_iter()
+            .zip(searcher.admin.parents(b))
+            .map_while(|(a, b)| a.zip(b).filter(|(a, b)| a == b))
+            .filter_map(|(id, _)| searcher.geonames.get(&id).cloned())
+            .collect();
+
+        let coordinates = |e: &GeoNamesEntry| {
+            let (lat, lon) = (e.latitude as f64, e.longitude as f64);
+            is_valid_coordinate(lat, lon).then_some((lat, lon))
+        };
+        let distance_km = coordinates(a)
+            .zip(coordinates(b))
+            .map(|((a_lat, a_lon), (b_lat, b_lon))| haversine_km(a_lat, a_lon, b_lat, b_lon));
+
+        Ok(Comparison {
+            name_distance: self.metric.distance(&a.name, &b.name),
+            shared_ancestors,
+            distance_km,
+            a: a.clone(),
+            b: b.clone(),
+        })
+    }
+}
+
+pub(crate) async fn compare(
+    State(state): State<AppState>,
+    Json(request): Json<RequestCompare>,
+) -> impl IntoApiResponse {
+    run_blocking(&state, move |searcher, _deadline| request.compare(searcher))
+        .await
+        .map(Json)
+}
+
+pub(crate) fn compare_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Compare two GeoNames entries by their ids: the edit distance between their names, the country and administrative divisions containing both, and the great-circle distance between them.")
+        .response::<200, Json<Comparison>>()
+        .response_with::<404, ApiError, _>(|t| t.description("A GeoNames id is unknown."))
+        .response_with::<504, ApiError, _>(|t| t.description("The search exceeded the search timeout."))
+}
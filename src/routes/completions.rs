@@ -0,0 +1,64 @@
+use aide::axum::IntoApiResponse;
+use aide::transform::TransformOperation;
+use axum::extract::State;
+use axum::Json;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_aux::prelude::*;
+
+use super::error::ApiError;
+use super::validate::{FieldErrors, Valid, Validate};
+use super::{cap, run_blocking, Response};
+use crate::diagnostics::RequestSummary;
+use crate::geonames::data::GeoNamesSearchResult;
+use crate::geonames::deadline::Deadline;
+use crate::geonames::searcher::GeoNamesSearcher;
+use crate::AppState;
+
+fn _schemars_default_prefix() -> String {
+    "Frank".to_string()
+}
+fn _default_limit() -> usize {
+    10
+}
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct RequestCompletions {
+    /// The prefix to complete.
+    #[validate(length(min = 1))]
+    #[schemars(default = "_schemars_default_prefix")]
+    pub query: String,
+    /// Maximum number of completions, at most the result cap of the service. Defaults to 10.
+    #[serde(
+        default = "_default_limit",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub limit: usize,
+}
+
+impl Validate for RequestCompletions {
+    fn check(&self, errors: &mut FieldErrors) {
+        errors.query("query", &self.query);
+    }
+}
+
+pub(crate) async fn completions(
+    State(state): State<AppState>,
+    Valid(Json(request)): Valid<Json<RequestCompletions>>,
+) -> impl IntoApiResponse {
+    let summary = RequestSummary::query(&request.query);
+    let search = move |searcher: &GeoNamesSearcher, _: Deadline| {
+        let limit = cap(request.limit, searcher.max_results);
+        Ok(searcher.completions(&request.query, limit))
+    };
+    let response = run_blocking(&state, search)
+        .await
+        .map(|results| Json(Response::results(results)));
+    summary.attach(response)
+}
+
+pub(crate) fn completions_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Find the most populous GeoNames entries that start with the specified prefix, one result per GeoNames id.<br>Prefixes up to <code>--completions-prefix-len</code> characters are answered from a precomputed table.")
+        .response::<200, Json<Response<GeoNamesSearchResult>>>()
+        .response_with::<422, ApiError, _>(|t| t.description("The query was empty or too long."))
+        .response_with::<504, ApiError, _>(|t| t.description("The search exceeded the search timeout."))
+}
@@ -0,0 +1,91 @@
+use aide::axum::IntoApiResponse;
+use aide::transform::TransformOperation;
+use axum::extract::State;
+use axum::Json;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_aux::prelude::*;
+
+use super::error::{ApiError, ErrorCode};
+use super::run_blocking;
+use crate::geonames::data::GeoNamesEntry;
+use crate::geonames::geo::is_valid_coordinate;
+use crate::geonames::searcher::GeoNamesSearcher;
+use crate::AppState;
+
+fn _schemars_default_lat() -> f64 {
+    50.1109
+}
+fn _schemars_default_lon() -> f64 {
+    8.6821
+}
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct RequestCountry {
+    /// Latitude of the point in degrees.
+    #[schemars(default = "_schemars_default_lat")]
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lat: f64,
+    /// Longitude of the point in degrees.
+    #[schemars(default = "_schemars_default_lon")]
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lon: f64,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct CountryMatch {
+    /// GeoNames id of the country.
+    pub id: u64,
+    /// The country's entry, if it is part of the index.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entry: Option<GeoNamesEntry>,
+}
+
+impl RequestCountry {
+    fn country(&self, searcher: &GeoNamesSearcher) -> Result<CountryMatch, ApiError> {
+        if searcher.shapes.is_none() {
+            return Err(ApiError::new(
+                ErrorCode::IndexUnavailable,
+                "No country shapes were loaded, start the service with --shapes",
+            ));
+        }
+        if !is_valid_coordinate(self.lat, self.lon) {
+            return Err(ApiError::new(
+                ErrorCode::InvalidRequest,
+                "Invalid coordinates: lat must be in [-90, 90] and lon in [-180, 180]",
+            ));
+        }
+
+        let id = searcher
+            .country_at(self.lat, self.lon)
+            .ok_or(ApiError::new(
+                ErrorCode::NotFound,
+                "No country contains the point",
+            ))?;
+        Ok(CountryMatch {
+            id,
+            entry: searcher.geonames.get(&id).cloned(),
+        })
+    }
+}
+
+pub(crate) async fn country(
+    State(state): State<AppState>,
+    Json(request): Json<RequestCountry>,
+) -> impl IntoApiResponse {
+    run_blocking(&state, move |searcher, _deadline| request.country(searcher))
+        .await
+        .map(Json)
+}
+
+pub(crate) fn country_docs(op: TransformOperation) -> TransformOperation {
+    op.description("The country containing a point, by the GeoNames country shapes loaded with <code>--shapes</code>.")
+        .response::<200, Json<CountryMatch>>()
+        .response_with::<400, ApiError, _>(|t| t.description("The coordinates were invalid."))
+        .response_with::<404, ApiError, _>(|t| {
+            t.description("No country contains the point, e.g. in international waters.")
+        })
+        .response_with::<501, ApiError, _>(|t| {
+            t.description("No country shapes were loaded.")
+        })
+        .response_with::<504, ApiError, _>(|t| t.description("The search exceeded the search timeout."))
+}
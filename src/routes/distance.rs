@@ -0,0 +1,124 @@
+use aide::axum::IntoApiResponse;
+use aide::transform::TransformOperation;
+use axum::extract::State;
+use axum::Json;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::error::{ApiError, ErrorCode};
+use super::run_blocking;
+use crate::geonames::data::GeoNamesEntry;
+use crate::geonames::geo::{haversine_km, initial_bearing_deg, is_valid_coordinate};
+use crate::geonames::searcher::GeoNamesSearcher;
+use crate::AppState;
+
+/// A GeoNames entry given by its id, or a point given by its coordinates.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub(crate) enum Location {
+    Id {
+        /// GeoNames id of the entry.
+        id: u64,
+    },
+    Point {
+        /// Latitude in degrees.
+        lat: f64,
+        /// Longitude in degrees.
+        lon: f64,
+    },
+}
+
+impl Location {
+    /// The coordinates of the location, and the entry if it was given by its id.
+    fn resolve<'a>(
+        &self,
+        searcher: &'a GeoNamesSearcher,
+    ) -> Result<(f64, f64, Option<&'a GeoNamesEntry>), ApiError> {
+        let (lat, lon, entry) = match self {
+            Location::Id { id } => {
+                let entry = searcher.geonames.get(id).ok_or(ApiError::new(
+                    ErrorCode::NotFound,
+                    format!("Unknown GeoNames id {id}"),
+                ))?;
+                (entry.latitude as f64, entry.longitude as f64, Some(entry))
+            }
+            Location::Point { lat, lon } => (*lat, *lon, None),
+        };
+        if !is_valid_coordinate(lat, lon) {
+            return Err(ApiError::new(
+                ErrorCode::InvalidRequest,
+                match entry {
+                    Some(entry) => format!("GeoNames id {} has no valid coordinates", entry.id),
+                    None => "Invalid coordinates: lat must be in [-90, 90] and lon in [-180, 180]"
+                        .to_string(),
+                },
+            ));
+        }
+        Ok((lat, lon, entry))
+    }
+}
+
+fn _schemars_default_from() -> Location {
+    Location::Id { id: 2925533 }
+}
+fn _schemars_default_to() -> Location {
+    Location::Point {
+        lat: 52.52437,
+        lon: 13.41053,
+    }
+}
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct RequestDistance {
+    #[schemars(default = "_schemars_default_from")]
+    pub from: Location,
+    #[schemars(default = "_schemars_default_to")]
+    pub to: Location,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct GeoDistance {
+    /// Great-circle distance in kilometers.
+    pub distance_km: f64,
+    /// Initial bearing from `from` towards `to`, in degrees clockwise from north.
+    pub bearing_deg: f64,
+    /// The entry of `from`, if it was given by its id.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<GeoNamesEntry>,
+    /// The entry of `to`, if it was given by its id.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<GeoNamesEntry>,
+}
+
+impl RequestDistance {
+    fn distance(&self, searcher: &GeoNamesSearcher) -> Result<GeoDistance, ApiError> {
+        let (from_lat, from_lon, from) = self.from.resolve(searcher)?;
+        let (to_lat, to_lon, to) = self.to.resolve(searcher)?;
+        Ok(GeoDistance {
+            distance_km: haversine_km(from_lat, from_lon, to_lat, to_lon),
+            bearing_deg: initial_bearing_deg(from_lat, from_lon, to_lat, to_lon),
+            from: from.cloned(),
+            to: to.cloned(),
+        })
+    }
+}
+
+pub(crate) async fn distance(
+    State(state): State<AppState>,
+    Json(request): Json<RequestDistance>,
+) -> impl IntoApiResponse {
+    run_blocking(&state, move |searcher, _deadline| {
+        request.distance(searcher)
+    })
+    .await
+    .map(Json)
+}
+
+pub(crate) fn distance_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Great-circle distance and initial bearing between two locations, each given either by a GeoNames id (<code>{\"id\": 2925533}</code>) or by coordinates (<code>{\"lat\": 52.5, \"lon\": 13.4}</code>).")
+        .response::<200, Json<GeoDistance>>()
+        .response_with::<400, ApiError, _>(|t| {
+            t.description("The coordinates were invalid, or the entry has no valid coordinates.")
+        })
+        .response_with::<404, ApiError, _>(|t| t.description("A GeoNames id is unknown."))
+        .response_with::<504, ApiError, _>(|t| t.description("The search exceeded the search timeout."))
+}
@@ -39,13 +39,3 @@ pub(crate) fn docs_routes(state: AppState) -> ApiRouter {
 async fn serve_docs(Extension(api): Extension<OpenApi>) -> impl IntoApiResponse {
     Json(api).into_response()
 }
-
-#[derive(serde::Serialize, schemars::JsonSchema)]
-pub(crate) struct DocResults<T> {
-    results: Vec<T>,
-}
-
-#[derive(serde::Serialize, schemars::JsonSchema)]
-pub(crate) struct DocError {
-    error: String,
-}
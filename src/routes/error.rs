@@ -0,0 +1,192 @@
+use aide::generate::GenContext;
+use aide::openapi::Operation;
+use aide::operation::OperationOutput;
+use axum::extract::Request;
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use fst::automaton::LevenshteinError;
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Media type of error responses.
+pub(crate) const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Largest plain-text error body that is wrapped into an `ApiError`.
+const MAX_REJECTION_BODY: usize = 64 * 1024;
+
+/// Machine-readable kind of an error, stable across releases unlike the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ErrorCode {
+    /// A parameter of the request is invalid, e.g. an empty query or invalid coordinates.
+    InvalidRequest,
+    /// The regex or glob pattern of the query could not be compiled.
+    InvalidPattern,
+    /// The query needs more automaton states than allowed, e.g. a large Levenshtein distance.
+    QueryTooComplex,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    Conflict,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    /// The request body could not be parsed.
+    UnprocessableRequest,
+    /// Fields of the request have invalid values, listed by field in the details.
+    ValidationFailed,
+    /// The query was not run because an earlier query of the batch failed.
+    #[cfg_attr(
+        not(any(feature = "geonames_routes", feature = "duui")),
+        allow(dead_code)
+    )]
+    Skipped,
+    Internal,
+    /// The index needed by the route was not built, e.g. the n-gram index.
+    IndexUnavailable,
+    /// The search exceeded the search timeout.
+    Timeout,
+}
+
+impl ErrorCode {
+    /// The HTTP status errors of this kind are reported with.
+    pub(crate) fn status(self) -> StatusCode {
+        match self {
+            Self::InvalidRequest | Self::InvalidPattern | Self::QueryTooComplex => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            Self::Conflict => StatusCode::CONFLICT,
+            Self::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::UnprocessableRequest | Self::ValidationFailed => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Skipped => StatusCode::FAILED_DEPENDENCY,
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::IndexUnavailable => StatusCode::NOT_IMPLEMENTED,
+            Self::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    /// The kind of error reported with `status` by a layer that does not know better, e.g. an
+    /// extractor rejection of axum.
+    fn of_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED => Self::Unauthorized,
+            StatusCode::FORBIDDEN => Self::Forbidden,
+            StatusCode::NOT_FOUND => Self::NotFound,
+            StatusCode::METHOD_NOT_ALLOWED => Self::MethodNotAllowed,
+            StatusCode::CONFLICT => Self::Conflict,
+            StatusCode::PAYLOAD_TOO_LARGE => Self::PayloadTooLarge,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => Self::UnsupportedMediaType,
+            StatusCode::UNPROCESSABLE_ENTITY => Self::UnprocessableRequest,
+            StatusCode::GATEWAY_TIMEOUT => Self::Timeout,
+            status if status.is_client_error() => Self::InvalidRequest,
+            _ => Self::Internal,
+        }
+    }
+}
+
+/// An error of any route, in the style of RFC 7807 problem details and served as
+/// `application/problem+json`.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub(crate) struct ApiError {
+    /// HTTP status of the error.
+    pub status: u16,
+    pub code: ErrorCode,
+    /// Human-readable description of the error.
+    pub message: String,
+    /// Additional machine-readable information, e.g. the limit that was exceeded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+impl ApiError {
+    pub(crate) fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            status: code.status().as_u16(),
+            code,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub(crate) fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub(crate) fn status(&self) -> StatusCode {
+        StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status(), [(CONTENT_TYPE, PROBLEM_JSON)], Json(self)).into_response()
+    }
+}
+
+impl OperationOutput for ApiError {
+    type Inner = Self;
+
+    fn operation_response(
+        ctx: &mut GenContext,
+        operation: &mut Operation,
+    ) -> Option<aide::openapi::Response> {
+        let mut response = Json::<Self>::operation_response(ctx, operation)?;
+        if let Some(media_type) = response.content.shift_remove("application/json") {
+            response
+                .content
+                .insert(PROBLEM_JSON.to_string(), media_type);
+        }
+        Some(response)
+    }
+}
+
+/// The error of a Levenshtein automaton that would need more states than allowed.
+pub(crate) fn too_complex(error: LevenshteinError) -> ApiError {
+    let message = format!("LevenshteinError: {error}");
+    let LevenshteinError::TooManyStates(limit) = error;
+    ApiError::new(ErrorCode::QueryTooComplex, message)
+        .with_details(serde_json::json!({ "state_limit": limit }))
+}
+
+/// Middleware wrapping the plain-text error responses of axum, e.g. rejected request bodies or
+/// unknown routes, into an `ApiError`, so that all errors share the same shape.
+pub(crate) async fn problem_details(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let status = response.status();
+    let is_text = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_none_or(|value| value.starts_with("text/plain"));
+    if !(status.is_client_error() || status.is_server_error()) || !is_text {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let message = match axum::body::to_bytes(body, MAX_REJECTION_BODY).await {
+        Ok(body) if !body.is_empty() => String::from_utf8_lossy(&body).into_owned(),
+        _ => status.canonical_reason().unwrap_or_default().to_string(),
+    };
+    let mut error = ApiError::new(ErrorCode::of_status(status), message);
+    error.status = status.as_u16();
+    let mut response = error.into_response();
+    // Keep headers such as `Allow` of 405 responses
+    for (name, value) in parts
+        .headers
+        .iter()
+        .filter(|(name, _)| ![CONTENT_TYPE, CONTENT_LENGTH].contains(name))
+    {
+        response.headers_mut().insert(name, value.clone());
+    }
+    response
+}
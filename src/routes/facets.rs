@@ -0,0 +1,70 @@
+use std::collections::BTreeMap;
+
+#[cfg(feature = "geonames_routes")]
+use aide::axum::IntoApiResponse;
+#[cfg(feature = "geonames_routes")]
+use aide::transform::TransformOperation;
+#[cfg(feature = "geonames_routes")]
+use axum::extract::State;
+#[cfg(feature = "geonames_routes")]
+use axum::Json;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::geonames::data::GeoNamesEntry;
+#[cfg(feature = "geonames_routes")]
+use crate::AppState;
+
+/// Distinct values of the filterable fields of the index, with the number of entries carrying
+/// each value.
+#[derive(Debug, Clone, Default, Serialize, JsonSchema)]
+pub(crate) struct Facets {
+    /// Number of entries per GeoNames feature class, e.g. `P` for populated places.
+    pub feature_classes: BTreeMap<String, usize>,
+    /// Number of entries per GeoNames feature code, e.g. `PPLC` for capitals.
+    pub feature_codes: BTreeMap<String, usize>,
+    /// Number of entries per ISO-3166 country code.
+    pub countries: BTreeMap<String, usize>,
+}
+
+impl Facets {
+    pub fn new<'a>(entries: impl IntoIterator<Item = &'a GeoNamesEntry>) -> Self {
+        let mut facets = Self::default();
+        entries.into_iter().for_each(|entry| facets.add(entry));
+        facets
+    }
+
+    fn add(&mut self, entry: &GeoNamesEntry) {
+        *self
+            .feature_classes
+            .entry(entry.feature_class.to_string())
+            .or_default() += 1;
+        *self
+            .feature_codes
+            .entry(entry.feature_code.to_string())
+            .or_default() += 1;
+        *self
+            .countries
+            .entry(entry.country_code.to_string())
+            .or_default() += 1;
+    }
+}
+
+/// The facets of the index computed when it was loaded, plus the entries inserted since.
+#[cfg(feature = "geonames_routes")]
+pub(crate) async fn facets(State(state): State<AppState>) -> impl IntoApiResponse {
+    let index = state.index.load();
+    let mut facets = index.facets.clone();
+    let overlay = index.searcher.overlay.read().unwrap();
+    overlay.entries().for_each(|entry| facets.add(entry));
+    Json(facets)
+}
+
+#[cfg(feature = "geonames_routes")]
+pub(crate) fn facets_docs(op: TransformOperation) -> TransformOperation {
+    op.description("The distinct feature classes, feature codes and country codes of the served index with their number of entries, e.g. to offer only the values of the <code>filter</code> that can match.<br>Responses carry an <code>ETag</code> and <code>Cache-Control</code>, so that clients and HTTP caches can reuse them.")
+        .response::<200, Json<Facets>>()
+        .response_with::<304, (), _>(|t| {
+            t.description("The facets did not change since the ETag given in If-None-Match.")
+        })
+}
@@ -1,13 +1,33 @@
+#[cfg(feature = "geonames_routes")]
 use aide::axum::IntoApiResponse;
+#[cfg(feature = "geonames_routes")]
 use aide::transform::TransformOperation;
+#[cfg(feature = "geonames_routes")]
 use axum::extract::State;
-use axum::{http::StatusCode, Json};
+#[cfg(feature = "geonames_routes")]
+use axum::Json;
 use schemars::JsonSchema;
 use serde::Deserialize;
 
-use super::docs::{DocError, DocResults};
-use super::{filter_results, FilterResults, Response};
+use super::error::{ApiError, ErrorCode};
+#[cfg(feature = "geonames_routes")]
+use super::format::ResponseFormat;
+use super::regex_automaton::{LiteralPosition, RegexSearchAutomaton};
+#[cfg(feature = "geonames_routes")]
+use super::validate::Valid;
+use super::validate::{FieldErrors, Validate};
+use super::{
+    dedup_results, extend_results, filter_predicate, filter_results, limit_page, rank_results,
+    score_results, sort_results, ExtrasOptions, FilterResults, ResultOptions, Search,
+};
+#[cfg(feature = "geonames_routes")]
+use super::{run_blocking, Response};
+#[cfg(feature = "geonames_routes")]
+use crate::diagnostics::RequestSummary;
 use crate::geonames::data::GeoNamesSearchResult;
+use crate::geonames::deadline::Deadline;
+use crate::geonames::searcher::{Collect, GeoNamesSearcher, Page};
+#[cfg(feature = "geonames_routes")]
 use crate::AppState;
 
 fn _schemars_default_filter_class_t() -> Option<FilterResults> {
@@ -15,12 +35,29 @@ fn _schemars_default_filter_class_t() -> Option<FilterResults> {
         feature_class: Some("T".to_string()),
         feature_code: None,
         country_code: Some("DE".to_string()),
+        admin1_code: None,
+        admin2_code: None,
+        admin3_code: None,
+        admin4_code: None,
+        min_population: None,
+        max_population: None,
+        match_types: None,
+        languages: None,
+        bbox: None,
+        polygon: None,
     })
 }
-#[derive(Deserialize, JsonSchema)]
+#[derive(Clone, Deserialize, JsonSchema)]
 pub(crate) struct RequestOptsFind {
+    /// Match the query ignoring case, e.g. "frankfurt am main" finds "Frankfurt am Main".
+    #[serde(default)]
+    pub case_insensitive: bool,
     #[schemars(default = "_schemars_default_filter_class_t")]
     pub filter: Option<FilterResults>,
+    #[serde(flatten)]
+    pub results: ResultOptions,
+    #[serde(flatten)]
+    pub extras: ExtrasOptions,
 }
 
 fn _schemars_default_query() -> String {
@@ -37,25 +74,76 @@ pub(crate) struct RequestFind {
     pub opts: RequestOptsFind,
 }
 
+#[cfg(feature = "geonames_routes")]
 pub(crate) async fn find(
     State(state): State<AppState>,
-    Json(request): Json<RequestFind>,
+    format: ResponseFormat,
+    Valid(Json(request)): Valid<Json<RequestFind>>,
 ) -> impl IntoApiResponse {
-    if request.query.is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(Response::Error("Empty query".to_string())),
-        );
+    let summary = RequestSummary::query(&request.query);
+    let extent = request.opts.extras.extent;
+    let fields = request.opts.extras.fields.clone();
+    let response = run_blocking(&state, move |searcher, deadline| {
+        request.search(searcher, deadline)
+    })
+    .await
+    .map(|page| {
+        format
+            .encode(Response::from(page).with_extent(extent))
+            .with_fields(fields)
+    });
+    summary.attach(response)
+}
+
+impl Validate for RequestFind {
+    fn check(&self, errors: &mut FieldErrors) {
+        errors.query("query", &self.query);
     }
+}
 
-    let results: Vec<GeoNamesSearchResult> =
-        filter_results(state.searcher.find(&request.query), &request.opts.filter);
+impl Search for RequestFind {
+    type Item = GeoNamesSearchResult;
 
-    (StatusCode::OK, Json(Response::Results(results)))
+    fn search(
+        &self,
+        searcher: &GeoNamesSearcher,
+        deadline: Deadline,
+    ) -> Result<Page<Self::Item>, ApiError> {
+        let results = if self.opts.case_insensitive {
+            let query = RegexSearchAutomaton::literal(&self.query, LiteralPosition::Exact, true)
+                .map_err(|e| {
+                    ApiError::new(ErrorCode::InvalidPattern, format!("RegexError: {e:#}"))
+                })?;
+            searcher
+                .search(
+                    query,
+                    Collect::All,
+                    deadline,
+                    filter_predicate(&self.opts.filter),
+                )?
+                .results
+        } else {
+            filter_results(searcher.find(&self.query), &self.opts.filter)
+        };
+        let results = dedup_results(results, self.opts.results.dedup);
+        let results = score_results(results, &searcher.score_weights);
+        let results = rank_results(results, self.opts.results.rank_by);
+        let results = sort_results(results, self.opts.results.sort);
+        Ok(limit_page(
+            results.into(),
+            self.opts.results.limit,
+            searcher.max_results,
+        )
+        .map(|results| extend_results(results, &self.opts.extras, searcher)))
+    }
 }
 
+#[cfg(feature = "geonames_routes")]
 pub(crate) fn find_docs(op: TransformOperation) -> TransformOperation {
     op.description("Find all GeoNames entries with the specified name.")
-        .response::<200, Json<DocResults<GeoNamesSearchResult>>>()
-        .response_with::<400, Json<DocError>, _>(|t| t.description("The query was empty."))
+        .response::<200, Json<Response<GeoNamesSearchResult>>>()
+        .response_with::<422, ApiError, _>(|t| t.description("The query was empty or too long."))
+        .response_with::<504, ApiError, _>(|t| {
+            t.description("The search exceeded the search timeout.")
+        })
 }
@@ -0,0 +1,179 @@
+use std::convert::Infallible;
+
+use aide::operation::{OperationInput, OperationOutput};
+use axum::extract::FromRequestParts;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::request::Parts;
+use axum::response::IntoResponse;
+use axum::Json;
+use serde::{Serialize, Serializer};
+
+use super::batch::BatchResponse;
+use super::error::{ApiError, ErrorCode};
+use super::select::{SelectFields, Selected};
+use super::stream::accepts;
+use super::Response;
+use crate::diagnostics::ResultCount;
+use crate::geonames::cluster::GeoCluster;
+use crate::geonames::data::EntryField;
+use crate::geonames::geo::Extent;
+
+/// Media type of MessagePack, with the alias used by older clients.
+const MSGPACK: [&str; 2] = ["application/msgpack", "application/x-msgpack"];
+/// Media type of CBOR.
+const CBOR: &str = "application/cbor";
+
+/// Encoding of the response body, negotiated from the `Accept` header.
+///
+/// The binary formats carry the same fields as JSON, but are smaller and faster to parse for
+/// clients that call the service at high rates. JSON is used unless a binary format is accepted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum ResponseFormat {
+    #[default]
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl ResponseFormat {
+    /// Encode `body` in this format.
+    pub(crate) fn encode<T: Serialize>(self, body: T) -> Encoded<T> {
+        Encoded {
+            format: self,
+            body,
+            fields: None,
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ResponseFormat {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let headers = &parts.headers;
+        Ok(if MSGPACK.iter().any(|msgpack| accepts(headers, msgpack)) {
+            Self::MessagePack
+        } else if accepts(headers, CBOR) {
+            Self::Cbor
+        } else {
+            Self::Json
+        })
+    }
+}
+
+impl OperationInput for ResponseFormat {}
+
+/// A response body encoded in the negotiated format.
+pub(crate) struct Encoded<T> {
+    format: ResponseFormat,
+    body: T,
+    fields: Option<Vec<EntryField>>,
+}
+
+impl<T> Encoded<T> {
+    /// Only return these fields of the entries in the body, if given.
+    pub(crate) fn with_fields(mut self, fields: Option<Vec<EntryField>>) -> Self {
+        self.fields = fields;
+        self
+    }
+}
+
+impl<T: SelectFields> SelectFields for Response<T> {
+    fn serialize_selected<S: Serializer>(
+        &self,
+        fields: &[EntryField],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        #[serde(bound = "T: SelectFields")]
+        struct Page<'a, T> {
+            results: Selected<'a, [T]>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            next_cursor: Option<&'a String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            extent: Option<&'a Extent>,
+            #[serde(skip_serializing_if = "std::ops::Not::not")]
+            truncated: bool,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            total: Option<usize>,
+        }
+        Page {
+            results: Selected(&self.results, fields),
+            next_cursor: self.next_cursor.as_ref(),
+            extent: self.extent.as_ref(),
+            truncated: self.truncated,
+            total: self.total,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Keys of the index, streamed without entries.
+impl SelectFields for str {}
+
+impl SelectFields for GeoCluster {}
+
+/// The queries of a batch select the fields of their own results.
+impl SelectFields for BatchResponse {}
+
+/// Response bodies that report how many results they hold, for the access log.
+pub(crate) trait CountResults {
+    fn result_count(&self) -> Option<usize>;
+}
+
+impl<T> CountResults for Response<T> {
+    fn result_count(&self) -> Option<usize> {
+        Some(self.results.len())
+    }
+}
+
+impl CountResults for BatchResponse {
+    fn result_count(&self) -> Option<usize> {
+        Some(self.results.len())
+    }
+}
+
+impl<T: SelectFields + CountResults> IntoResponse for Encoded<T> {
+    fn into_response(self) -> axum::response::Response {
+        let count = self.body.result_count();
+        let mut response = match self.fields {
+            None => encode(self.format, &self.body),
+            Some(fields) => encode(self.format, &Selected(&self.body, &fields)),
+        };
+        if let Some(count) = count {
+            response.extensions_mut().insert(ResultCount(count));
+        }
+        response
+    }
+}
+
+impl<T> OperationOutput for Encoded<T> {
+    type Inner = T;
+}
+
+fn encode<T: Serialize>(format: ResponseFormat, body: &T) -> axum::response::Response {
+    let body = match format {
+        ResponseFormat::Json => return Json(body).into_response(),
+        ResponseFormat::MessagePack => rmp_serde::to_vec_named(body)
+            .map(|body| (MSGPACK[0], body))
+            .map_err(|e| e.to_string()),
+        ResponseFormat::Cbor => {
+            let mut buffer = Vec::new();
+            ciborium::into_writer(body, &mut buffer)
+                .map(|_| (CBOR, buffer))
+                .map_err(|e| e.to_string())
+        }
+    };
+    match body {
+        Ok((content_type, body)) => ([(CONTENT_TYPE, content_type)], body).into_response(),
+        Err(e) => encoding_failed(e),
+    }
+}
+
+fn encoding_failed(e: impl std::fmt::Display) -> axum::response::Response {
+    ApiError::new(
+        ErrorCode::Internal,
+        format!("Failed to encode response: {e}"),
+    )
+    .into_response()
+}
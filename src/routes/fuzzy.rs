@@ -1,27 +1,57 @@
+#[cfg(feature = "geonames_routes")]
 use aide::axum::IntoApiResponse;
+#[cfg(feature = "geonames_routes")]
 use aide::transform::TransformOperation;
+#[cfg(feature = "geonames_routes")]
 use axum::extract::State;
-use axum::{http::StatusCode, Json};
+#[cfg(feature = "geonames_routes")]
+use axum::Json;
 use fst::automaton::Subsequence;
 use schemars::JsonSchema;
 use serde::Deserialize;
 use serde_aux::prelude::*;
 
-use super::docs::{DocError, DocResults};
-use super::{filter_results, FilterResults, Response, _schemars_default_filter};
+use super::error::ApiError;
+#[cfg(feature = "geonames_routes")]
+use super::format::ResponseFormat;
+#[cfg(feature = "geonames_routes")]
+use super::validate::Valid;
+use super::validate::{deserialize_edit_distance, FieldErrors, Validate};
+use super::{
+    _schemars_default_filter, collect, dedup_results, extend_results, filter_predicate, limit_page,
+    page_and_limit, rank_results, score_results, sort_results, ExtrasOptions, FilterResults,
+    PageOptions, ResultOptions, Search,
+};
+#[cfg(feature = "geonames_routes")]
+use super::{run_blocking, Response};
+#[cfg(feature = "geonames_routes")]
+use crate::diagnostics::RequestSummary;
 use crate::geonames::data::GeoNamesSearchResultWithDist;
+use crate::geonames::deadline::Deadline;
+use crate::geonames::distance::EditMetric;
+use crate::geonames::searcher::{GeoNamesSearcher, Page};
+#[cfg(feature = "geonames_routes")]
 use crate::AppState;
 
-#[derive(Deserialize, JsonSchema)]
+#[derive(Clone, Deserialize, JsonSchema)]
 pub(crate) struct RequestOptsFuzzy {
-    /// Filter results by Levenshtein distance. Omit or set to `0` to disable filtering.
+    /// Filter results by edit distance. Omit or set to `0` to disable filtering.
     #[serde(
         default = "default_u32::<0>",
-        deserialize_with = "deserialize_number_from_string"
+        deserialize_with = "deserialize_edit_distance"
     )]
     pub max_dist: u32,
+    /// Edit distance used for `max_dist` and the reported distances. Defaults to `levenshtein`.
+    #[serde(default)]
+    pub metric: EditMetric,
     #[schemars(default = "_schemars_default_filter")]
     pub filter: Option<FilterResults>,
+    #[serde(flatten)]
+    pub results: ResultOptions,
+    #[serde(flatten)]
+    pub extras: ExtrasOptions,
+    #[serde(flatten)]
+    pub page: PageOptions,
 }
 
 fn _schemars_default_fuzzy_query() -> String {
@@ -38,32 +68,84 @@ pub(crate) struct RequestFuzzy {
     pub opts: RequestOptsFuzzy,
 }
 
+#[cfg(feature = "geonames_routes")]
 pub(crate) async fn fuzzy(
     State(state): State<AppState>,
-    Json(request): Json<RequestFuzzy>,
+    format: ResponseFormat,
+    Valid(Json(request)): Valid<Json<RequestFuzzy>>,
 ) -> impl IntoApiResponse {
-    if request.query.is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(Response::Error("Empty query".to_string())),
-        );
+    let summary = RequestSummary::query(&request.query);
+    let extent = request.opts.extras.extent;
+    let fields = request.opts.extras.fields.clone();
+    let response = run_blocking(&state, move |searcher, deadline| {
+        request.search(searcher, deadline)
+    })
+    .await
+    .map(|page| {
+        format
+            .encode(Response::from(page).with_extent(extent))
+            .with_fields(fields)
+    });
+    summary.attach(response)
+}
+
+impl Validate for RequestFuzzy {
+    fn check(&self, errors: &mut FieldErrors) {
+        errors.query("query", &self.query);
+        self.opts.check(errors);
     }
+}
+
+impl Validate for RequestOptsFuzzy {
+    fn check(&self, errors: &mut FieldErrors) {
+        errors.edit_distance("max_dist", self.max_dist);
+    }
+}
 
-    let query = Subsequence::new(&request.query);
+impl Search for RequestFuzzy {
+    type Item = GeoNamesSearchResultWithDist;
 
-    let results =
-        state
-            .searcher
-            .search_with_dist(query, &request.query, Some(request.opts.max_dist));
-    let results = filter_results(results, &request.opts.filter);
+    fn search(
+        &self,
+        searcher: &GeoNamesSearcher,
+        deadline: Deadline,
+    ) -> Result<Page<Self::Item>, ApiError> {
+        let query = Subsequence::new(&self.query);
 
-    (StatusCode::OK, Json(Response::Results(results)))
+        let (cursor, limit) = page_and_limit(
+            &self.opts.page.cursor,
+            self.opts.results.limit,
+            searcher.max_results,
+        );
+        let collect = collect(cursor, limit, &self.opts.results);
+        let page = searcher.search_with_dist(
+            query,
+            &self.query,
+            self.opts.metric,
+            Some(self.opts.max_dist),
+            collect,
+            deadline,
+            filter_predicate(&self.opts.filter),
+        )?;
+        let page = page.map(|results| {
+            let results = dedup_results(results, self.opts.results.dedup);
+            let results = score_results(results, &searcher.score_weights);
+            let results = rank_results(results, self.opts.results.rank_by);
+            sort_results(results, self.opts.results.sort)
+        });
+        Ok(limit_page(page, limit, searcher.max_results)
+            .map(|results| extend_results(results, &self.opts.extras, searcher)))
+    }
 }
 
+#[cfg(feature = "geonames_routes")]
 pub(crate) fn fuzzy_docs(op: TransformOperation) -> TransformOperation {
     op.description(
         "Find all GeoNames entries that match the fuzzy search query with a maximum edit distance.",
     )
-    .response::<200, Json<DocResults<GeoNamesSearchResultWithDist>>>()
-    .response_with::<400, Json<DocError>, _>(|t| t.description("The query was empty."))
+    .response::<200, Json<Response<GeoNamesSearchResultWithDist>>>()
+    .response_with::<422, ApiError, _>(|t| {
+        t.description("The query was empty or too long, or `max_dist` was too high.")
+    })
+    .response_with::<504, ApiError, _>(|t| t.description("The search exceeded the search timeout."))
 }
@@ -0,0 +1,184 @@
+#[cfg(feature = "geonames_routes")]
+use aide::axum::IntoApiResponse;
+#[cfg(feature = "geonames_routes")]
+use aide::transform::TransformOperation;
+#[cfg(feature = "geonames_routes")]
+use axum::extract::State;
+#[cfg(feature = "geonames_routes")]
+use axum::http::HeaderMap;
+#[cfg(feature = "geonames_routes")]
+use axum::response::IntoResponse;
+#[cfg(feature = "geonames_routes")]
+use axum::Json;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use super::error::{ApiError, ErrorCode};
+#[cfg(feature = "geonames_routes")]
+use super::format::ResponseFormat;
+#[cfg(feature = "geonames_routes")]
+use super::ndjson::{check_streamable, stream_ndjson, Emitter, NDJSON};
+use super::regex_automaton::RegexSearchAutomaton;
+#[cfg(feature = "geonames_routes")]
+use super::stream::accepts;
+#[cfg(feature = "geonames_routes")]
+use super::validate::Valid;
+use super::validate::{FieldErrors, Validate};
+use super::{
+    _schemars_default_filter, collect, dedup_results, extend_results, filter_predicate, limit_page,
+    page_and_limit, rank_results, score_results, sort_results, ExtrasOptions, FilterResults,
+    PageOptions, ResultOptions, Search,
+};
+#[cfg(feature = "geonames_routes")]
+use super::{run_blocking, Response};
+#[cfg(feature = "geonames_routes")]
+use crate::diagnostics::RequestSummary;
+use crate::geonames::data::GeoNamesSearchResult;
+use crate::geonames::deadline::Deadline;
+use crate::geonames::searcher::{GeoNamesSearcher, Page};
+#[cfg(feature = "geonames_routes")]
+use crate::AppState;
+
+#[derive(Clone, Deserialize, JsonSchema)]
+pub(crate) struct RequestOptsGlob {
+    /// Match the pattern ignoring case, e.g. "neu*berg" finds "Neuberg" and "Neuenburg".
+    #[serde(default)]
+    pub case_insensitive: bool,
+    #[schemars(default = "_schemars_default_filter")]
+    pub filter: Option<FilterResults>,
+    #[serde(flatten)]
+    pub results: ResultOptions,
+    #[serde(flatten)]
+    pub extras: ExtrasOptions,
+    #[serde(flatten)]
+    pub page: PageOptions,
+}
+
+fn _schemars_default_pattern() -> String {
+    "Frankfurt*".to_string()
+}
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct RequestGlob {
+    /// The glob pattern to match against the GeoNames entities: `*` matches any sequence of
+    /// characters, `?` any single character, and `\` escapes the following character.
+    #[validate(length(min = 1))]
+    #[schemars(default = "_schemars_default_pattern")]
+    pub pattern: String,
+
+    #[serde(flatten)]
+    pub opts: RequestOptsGlob,
+}
+
+#[cfg(feature = "geonames_routes")]
+pub(crate) async fn glob(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    headers: HeaderMap,
+    Valid(Json(request)): Valid<Json<RequestGlob>>,
+) -> impl IntoApiResponse {
+    let summary = RequestSummary::query(&request.pattern);
+    if accepts(&headers, NDJSON) {
+        let (limit, fields) = (
+            request.opts.results.limit,
+            request.opts.extras.fields.clone(),
+        );
+        return summary.attach(
+            stream_ndjson(&state, limit, fields, move |searcher, emitter| {
+                request.stream(searcher, emitter)
+            })
+            .await,
+        );
+    }
+
+    let extent = request.opts.extras.extent;
+    let fields = request.opts.extras.fields.clone();
+    let response = run_blocking(&state, move |searcher, deadline| {
+        request.search(searcher, deadline)
+    })
+    .await
+    .map(|page| {
+        format
+            .encode(Response::from(page).with_extent(extent))
+            .with_fields(fields)
+    })
+    .into_response();
+    summary.attach(response)
+}
+
+impl RequestGlob {
+    fn query(&self) -> Result<RegexSearchAutomaton, ApiError> {
+        RegexSearchAutomaton::glob(&self.pattern, self.opts.case_insensitive)
+            .map_err(|e| ApiError::new(ErrorCode::InvalidPattern, format!("GlobError: {e:#}")))
+    }
+
+    /// Stream the results in key order as they are found.
+    #[cfg(feature = "geonames_routes")]
+    fn stream(&self, searcher: &GeoNamesSearcher, emitter: &mut Emitter) -> Result<(), ApiError> {
+        let opts = &self.opts;
+        check_streamable(&opts.results, &opts.page, opts.extras.extent)?;
+        let deadline = emitter.deadline();
+        let query = self.query()?;
+        searcher.search_each(
+            query,
+            deadline,
+            filter_predicate(&opts.filter),
+            |key, typ, gn| {
+                let results = vec![GeoNamesSearchResult::new(key, typ, gn)];
+                let results = score_results(results, &searcher.score_weights);
+                extend_results(results, &opts.extras, searcher)
+                    .iter()
+                    .all(|result| emitter.emit(result))
+            },
+        )?;
+        Ok(())
+    }
+}
+
+impl Validate for RequestGlob {
+    fn check(&self, errors: &mut FieldErrors) {
+        errors.query("pattern", &self.pattern);
+    }
+}
+
+impl Search for RequestGlob {
+    type Item = GeoNamesSearchResult;
+
+    fn search(
+        &self,
+        searcher: &GeoNamesSearcher,
+        deadline: Deadline,
+    ) -> Result<Page<Self::Item>, ApiError> {
+        let query = self.query()?;
+        let (cursor, limit) = page_and_limit(
+            &self.opts.page.cursor,
+            self.opts.results.limit,
+            searcher.max_results,
+        );
+        let collect = collect(cursor, limit, &self.opts.results);
+        let page = searcher.search(
+            query,
+            collect,
+            deadline,
+            filter_predicate(&self.opts.filter),
+        )?;
+        let page = page.map(|results| {
+            let results = dedup_results(results, self.opts.results.dedup);
+            let results = score_results(results, &searcher.score_weights);
+            let results = rank_results(results, self.opts.results.rank_by);
+            sort_results(results, self.opts.results.sort)
+        });
+        Ok(limit_page(page, limit, searcher.max_results)
+            .map(|results| extend_results(results, &self.opts.extras, searcher)))
+    }
+}
+
+#[cfg(feature = "geonames_routes")]
+pub(crate) fn glob_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Find all GeoNames entries matching the specified glob pattern, e.g. <code>Neu*berg</code> or <code>Fran?furt</code>.<br>With <code>Accept: application/x-ndjson</code>, the results are streamed in key order as newline-delimited JSON while the search runs, without <code>dedup</code>, <code>rank_by</code>, <code>sort</code>, <code>cursor</code> or <code>extent</code>. An error after the first result ends the stream with an <code>{\"error\": ...}</code> line.")
+        .response::<200, Json<Response<GeoNamesSearchResult>>>()
+        .response_with::<400, ApiError, _>(|t| {
+            t.description("The pattern ends with an unescaped backslash, or the options cannot be streamed.")
+        })
+        .response_with::<422, ApiError, _>(|t| t.description("The pattern was empty or too long."))
+        .response_with::<504, ApiError, _>(|t| t.description("The search exceeded the search timeout."))
+}
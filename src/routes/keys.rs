@@ -0,0 +1,50 @@
+use aide::axum::IntoApiResponse;
+use aide::transform::TransformOperation;
+use axum::extract::{Query, State};
+use fst::automaton::Str;
+use fst::Automaton;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use super::error::ApiError;
+use super::ndjson::stream_ndjson;
+use super::validate::{FieldErrors, Valid, Validate};
+use crate::AppState;
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct KeysQuery {
+    /// Only return keys starting with this prefix. Omit to return all keys.
+    #[serde(default)]
+    pub prefix: String,
+    /// Return at most this many keys. Omit to return all matching keys.
+    pub limit: Option<usize>,
+}
+
+impl Validate for KeysQuery {
+    fn check(&self, errors: &mut FieldErrors) {
+        errors.max_length("prefix", &self.prefix);
+    }
+}
+
+pub(crate) async fn keys(
+    State(state): State<AppState>,
+    Valid(Query(query)): Valid<Query<KeysQuery>>,
+) -> impl IntoApiResponse {
+    stream_ndjson(&state, query.limit, None, move |searcher, emitter| {
+        let prefix = Str::new(&query.prefix).starts_with();
+        searcher.keys_each(prefix, |key| emitter.emit(key));
+        Ok(())
+    })
+    .await
+}
+
+pub(crate) fn keys_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Stream the search keys of the index that start with <code>prefix</code> in key order, without their entries, e.g. to mirror a part of the lexicon into a client-side autocompleter.<br>The keys are sent as newline-delimited JSON (<code>application/x-ndjson</code>), one JSON string per line, while the index is scanned. An error after the first key ends the stream with an <code>{\"error\": ...}</code> line.<br>Responses carry an <code>ETag</code> and <code>Cache-Control</code>, so that clients and HTTP caches can reuse them.")
+        .response_with::<304, (), _>(|t| {
+            t.description("The keys did not change since the ETag given in If-None-Match.")
+        })
+        .response_with::<422, ApiError, _>(|t| t.description("The prefix was too long."))
+        .response_with::<504, ApiError, _>(|t| {
+            t.description("The scan exceeded the search timeout before the first key.")
+        })
+}
@@ -0,0 +1,106 @@
+use aide::axum::IntoApiResponse;
+use aide::transform::TransformOperation;
+use axum::extract::State;
+use axum::Json;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_aux::prelude::*;
+
+use super::error::{ApiError, ErrorCode};
+use super::format::ResponseFormat;
+use super::{
+    _schemars_default_filter, dedup_results, extend_results, limit_page, rank_results,
+    run_blocking, score_results, sort_results, spatial_response, ClusterOptions, ExtrasOptions,
+    FilterResults, Response, ResultOptions, Search,
+};
+use crate::geonames::data::GeoNamesNearbyResult;
+use crate::geonames::deadline::Deadline;
+use crate::geonames::geo::is_valid_coordinate;
+use crate::geonames::searcher::{GeoNamesSearcher, Page};
+use crate::AppState;
+
+fn _schemars_default_lat() -> f64 {
+    50.1109
+}
+fn _schemars_default_lon() -> f64 {
+    8.6821
+}
+#[derive(Clone, Deserialize, JsonSchema)]
+pub(crate) struct RequestKnn {
+    /// Latitude of the query point in degrees.
+    #[schemars(default = "_schemars_default_lat")]
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lat: f64,
+    /// Longitude of the query point in degrees.
+    #[schemars(default = "_schemars_default_lon")]
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lon: f64,
+    /// Number of entries to find, nearest first. Defaults to 5.
+    #[serde(
+        default = "default_u32::<5>",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub k: u32,
+    /// Only count entries passing the filter towards `k`, e.g. `{"feature_code": "AIRP"}` for
+    /// the nearest airports.
+    #[schemars(default = "_schemars_default_filter")]
+    pub filter: Option<FilterResults>,
+    #[serde(flatten)]
+    pub results: ResultOptions,
+    /// Return clusters of nearby results instead of the results themselves, e.g.
+    /// `{"grid_size_km": 50}`.
+    #[serde(default)]
+    pub cluster: Option<ClusterOptions>,
+    #[serde(flatten)]
+    pub extras: ExtrasOptions,
+}
+
+pub(crate) async fn knn(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    Json(request): Json<RequestKnn>,
+) -> impl IntoApiResponse {
+    let (cluster, extent) = (request.cluster, request.extras.extent);
+    let fields = request.extras.fields.clone();
+    let result = run_blocking(&state, move |searcher, deadline| {
+        request.search(searcher, deadline)
+    })
+    .await;
+    spatial_response(format, result, &cluster, extent, fields)
+}
+
+impl Search for RequestKnn {
+    type Item = GeoNamesNearbyResult;
+
+    fn search(
+        &self,
+        searcher: &GeoNamesSearcher,
+        _deadline: Deadline,
+    ) -> Result<Page<Self::Item>, ApiError> {
+        if !is_valid_coordinate(self.lat, self.lon) {
+            return Err(ApiError::new(
+                ErrorCode::InvalidRequest,
+                "Invalid coordinates: lat must be in [-90, 90] and lon in [-180, 180]",
+            ));
+        }
+
+        let results = searcher.knn(self.lat, self.lon, self.k as usize, |entry| {
+            self.filter
+                .as_ref()
+                .is_none_or(|filter| filter.matches(entry))
+        });
+        let results = dedup_results(results, self.results.dedup);
+        let results = score_results(results, &searcher.score_weights);
+        let results = rank_results(results, self.results.rank_by);
+        let results = sort_results(results, self.results.sort);
+        let page = limit_page(results.into(), self.results.limit, searcher.max_results);
+        Ok(page.map(|results| extend_results(results, &self.extras, searcher)))
+    }
+}
+
+pub(crate) fn knn_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Find the <code>k</code> GeoNames entries nearest to a point that pass the filter, sorted by great-circle distance. Backed by the spatial index, so only the entries closest to the point are visited.<br>With <code>cluster</code>, nearby results are grouped into clusters with their centroid, count and most populous entry, which are returned instead.")
+        .response::<200, Json<Response<GeoNamesNearbyResult>>>()
+        .response_with::<400, ApiError, _>(|t| t.description("The coordinates were invalid."))
+        .response_with::<504, ApiError, _>(|t| t.description("The search exceeded the search timeout."))
+}
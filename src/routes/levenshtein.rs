@@ -1,16 +1,37 @@
+#[cfg(feature = "geonames_routes")]
 use aide::axum::IntoApiResponse;
+#[cfg(feature = "geonames_routes")]
 use aide::transform::TransformOperation;
+#[cfg(feature = "geonames_routes")]
 use axum::extract::State;
-use axum::{http::StatusCode, Json};
-use fst::automaton::{Levenshtein, LevenshteinError};
+#[cfg(feature = "geonames_routes")]
+use axum::Json;
+use fst::automaton::{Levenshtein, Str};
+use fst::Automaton;
 use schemars::JsonSchema;
 use serde::Deserialize;
 use serde_aux::prelude::*;
 
-use super::docs::{DocError, DocResults};
-use super::{filter_results, FilterResults, Response, _schemars_default_filter};
+use super::error::{too_complex, ApiError};
+#[cfg(feature = "geonames_routes")]
+use super::format::ResponseFormat;
+#[cfg(feature = "geonames_routes")]
+use super::validate::Valid;
+use super::validate::{deserialize_edit_distance, FieldErrors, Validate};
+use super::{
+    _schemars_default_filter, collect, dedup_results, extend_results, filter_predicate, limit_page,
+    page_and_limit, rank_results, score_results, sort_results, ExtrasOptions, FilterResults,
+    PageOptions, ResultOptions, Search,
+};
+#[cfg(feature = "geonames_routes")]
+use super::{run_blocking, Response};
+#[cfg(feature = "geonames_routes")]
+use crate::diagnostics::RequestSummary;
 use crate::geonames::data::GeoNamesSearchResultWithDist;
-use crate::geonames::searcher::GeoNamesSearcher;
+use crate::geonames::deadline::Deadline;
+use crate::geonames::distance::EditMetric;
+use crate::geonames::searcher::{Collect, GeoNamesSearcher, Page};
+#[cfg(feature = "geonames_routes")]
 use crate::AppState;
 
 fn _schemars_default_max_dist() -> u32 {
@@ -19,23 +40,38 @@ fn _schemars_default_max_dist() -> u32 {
 fn _default_state_limit() -> usize {
     10000
 }
-#[derive(Deserialize, JsonSchema)]
+#[derive(Clone, Deserialize, JsonSchema)]
 pub(crate) struct RequestOptsLevenshtein {
-    /// Maximum Levenshtein distance. Defaults to 1.
+    /// Maximum edit distance. Defaults to 1.
     #[serde(
         default = "default_u32::<1>",
-        deserialize_with = "deserialize_number_from_string"
+        deserialize_with = "deserialize_edit_distance"
     )]
     #[schemars(default = "_schemars_default_max_dist")]
     pub max_dist: u32,
+    /// Edit distance used for `max_dist` and the reported distances. Defaults to `levenshtein`.
+    /// With `damerau`, the underlying Levenshtein automaton has to search up to twice the
+    /// `max_dist`, which may require a higher `state_limit`.
+    #[serde(default)]
+    pub metric: EditMetric,
     /// Limit the number of states to search. Defaults to 10000. Long queries or high `max_dist` values may require increasing this limit.
     #[serde(
         default = "_default_state_limit",
         deserialize_with = "deserialize_number_from_string"
     )]
     pub state_limit: usize,
+    /// Only match keys starting with this exact prefix, e.g. `"Frank"`, searching the remaining
+    /// keys with the full edit distance budget. Useful for typo-tolerant autocompletion.
+    #[serde(default)]
+    pub prefix: Option<String>,
     #[schemars(default = "_schemars_default_filter")]
     pub filter: Option<FilterResults>,
+    #[serde(flatten)]
+    pub results: ResultOptions,
+    #[serde(flatten)]
+    pub extras: ExtrasOptions,
+    #[serde(flatten)]
+    pub page: PageOptions,
 }
 
 fn _schemars_default_levenshtein_query() -> String {
@@ -52,54 +88,110 @@ pub(crate) struct RequestLevenshtein {
     pub opts: RequestOptsLevenshtein,
 }
 
+#[cfg(feature = "geonames_routes")]
 pub(crate) async fn levenshtein(
     State(state): State<AppState>,
-    Json(request): Json<RequestLevenshtein>,
+    format: ResponseFormat,
+    Valid(Json(request)): Valid<Json<RequestLevenshtein>>,
 ) -> impl IntoApiResponse {
-    if request.query.is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(Response::Error("Empty query".to_string())),
-        );
+    let summary = RequestSummary::query(&request.query);
+    let extent = request.opts.extras.extent;
+    let fields = request.opts.extras.fields.clone();
+    let response = run_blocking(&state, move |searcher, deadline| {
+        request.search(searcher, deadline)
+    })
+    .await
+    .map(|page| {
+        format
+            .encode(Response::from(page).with_extent(extent))
+            .with_fields(fields)
+    });
+    summary.attach(response)
+}
+
+impl Validate for RequestLevenshtein {
+    fn check(&self, errors: &mut FieldErrors) {
+        errors.query("query", &self.query);
+        self.opts.check(errors);
     }
+}
 
-    match levenshtein_inner(
-        &state.searcher,
-        &request.query,
-        request.opts.state_limit,
-        request.opts.max_dist,
-        &request.opts.filter,
-    ) {
-        Ok(results) => (StatusCode::OK, Json(Response::Results(results))),
-        Err(error) => (
-            StatusCode::NOT_ACCEPTABLE,
-            Json(Response::Error(
-                format!("LevenshteinError: {error:?}").to_string(),
-            )),
-        ),
+impl Validate for RequestOptsLevenshtein {
+    fn check(&self, errors: &mut FieldErrors) {
+        errors.edit_distance("max_dist", self.max_dist);
+    }
+}
+
+impl Search for RequestLevenshtein {
+    type Item = GeoNamesSearchResultWithDist;
+
+    fn search(
+        &self,
+        searcher: &GeoNamesSearcher,
+        deadline: Deadline,
+    ) -> Result<Page<Self::Item>, ApiError> {
+        let (cursor, limit) = page_and_limit(
+            &self.opts.page.cursor,
+            self.opts.results.limit,
+            searcher.max_results,
+        );
+        let collect = collect(cursor, limit, &self.opts.results);
+        let page = levenshtein_inner(searcher, &self.query, &self.opts, collect, deadline)?.map(
+            |results| {
+                let results = dedup_results(results, self.opts.results.dedup);
+                let results = score_results(results, &searcher.score_weights);
+                let results = rank_results(results, self.opts.results.rank_by);
+                sort_results(results, self.opts.results.sort)
+            },
+        );
+        Ok(limit_page(page, limit, searcher.max_results)
+            .map(|results| extend_results(results, &self.opts.extras, searcher)))
     }
 }
 
 pub(crate) fn levenshtein_inner(
     searcher: &GeoNamesSearcher,
     query: &str,
-    state_limit: usize,
-    max_dist: u32,
-    filter: &Option<FilterResults>,
-) -> Result<Vec<GeoNamesSearchResultWithDist>, LevenshteinError> {
-    let levenshtein_query = Levenshtein::new_with_limit(query, max_dist, state_limit);
-    match levenshtein_query {
-        Ok(levenshtein_query) => Ok(filter_results(
-            searcher.search_with_dist(levenshtein_query, query, None),
+    opts: &RequestOptsLevenshtein,
+    collect: Collect,
+    deadline: Deadline,
+) -> Result<Page<GeoNamesSearchResultWithDist>, ApiError> {
+    // Every transposition costs 2 under Levenshtein, so search a wider radius and filter by the
+    // Damerau distance afterwards
+    let (automaton_dist, filter_dist) = match opts.metric {
+        EditMetric::Levenshtein => (opts.max_dist, None),
+        EditMetric::Damerau => (opts.max_dist * 2, Some(opts.max_dist)),
+    };
+    let levenshtein_query = Levenshtein::new_with_limit(query, automaton_dist, opts.state_limit)
+        .map_err(too_complex)?;
+    let filter = filter_predicate(&opts.filter);
+    Ok(match &opts.prefix {
+        Some(prefix) => searcher.search_with_dist(
+            levenshtein_query.intersection(Str::new(prefix).starts_with()),
+            query,
+            opts.metric,
+            filter_dist,
+            collect,
+            deadline,
             filter,
-        )),
-        Err(error) => Err(error),
-    }
+        )?,
+        None => searcher.search_with_dist(
+            levenshtein_query,
+            query,
+            opts.metric,
+            filter_dist,
+            collect,
+            deadline,
+            filter,
+        )?,
+    })
 }
 
+#[cfg(feature = "geonames_routes")]
 pub(crate) fn levenshtein_docs(op: TransformOperation) -> TransformOperation {
-    op.description("Find all GeoNames entries that match the Levenshtein search query with a maximum edit distance.<br><strong>NOTE:</strong> The Levenshtein search may consume a lot of memory and is thus capped to a maximum number of states of 10000 by default. If your search query exceeds this limit, you will recieve an error (406 Not Acceptable). The number of required states depends on the <code>max_dist</code>.<br><br><em>Use with caution!</em>")
-        .response::<200, Json<DocResults<GeoNamesSearchResultWithDist>>>()
-        .response_with::<400, Json<DocError>, _>(|t|t.description("The query was empty."))
-        .response_with::<406, Json<DocError>, _>(|t| t.description("The search query exceeded the maximum number of states"))
+    op.description("Find all GeoNames entries that match the Levenshtein search query with a maximum edit distance.<br><strong>NOTE:</strong> The Levenshtein search may consume a lot of memory and is thus capped to a maximum number of states of 10000 by default. If your search query exceeds this limit, you will recieve an error (400 Bad Request with the code <code>query_too_complex</code>). The number of required states depends on the <code>max_dist</code>.<br><br><em>Use with caution!</em>")
+        .response::<200, Json<Response<GeoNamesSearchResultWithDist>>>()
+        .response_with::<400, ApiError, _>(|t| t.description("The query exceeded the maximum number of states (`query_too_complex`)."))
+        .response_with::<422, ApiError, _>(|t| t.description("The query was empty or too long, or `max_dist` was too high."))
+        .response_with::<504, ApiError, _>(|t| t.description("The search exceeded the search timeout."))
 }
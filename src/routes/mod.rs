@@ -1,46 +1,391 @@
+pub mod admin;
+#[cfg(feature = "geonames_routes")]
+pub mod autocomplete;
+pub mod batch;
+#[cfg(feature = "geonames_routes")]
+pub mod bbox;
+#[cfg(feature = "geonames_routes")]
+pub mod cache;
+pub mod capabilities;
+pub mod cascade;
+#[cfg(feature = "geonames_routes")]
+pub mod compare;
+#[cfg(feature = "geonames_routes")]
+pub mod completions;
+pub mod contains;
+#[cfg(feature = "geonames_routes")]
+pub mod country;
+#[cfg(feature = "geonames_routes")]
+pub mod distance;
 pub mod docs;
+pub mod error;
+pub mod facets;
 pub mod find;
+#[cfg(feature = "geonames_routes")]
+pub mod format;
 pub mod fuzzy;
+pub mod glob;
+#[cfg(feature = "geonames_routes")]
+pub mod keys;
+#[cfg(feature = "geonames_routes")]
+pub mod knn;
 pub mod levenshtein;
+#[cfg(feature = "geonames_routes")]
+pub mod names;
+#[cfg(feature = "geonames_routes")]
+pub mod ndjson;
+#[cfg(feature = "geonames_routes")]
+pub mod nearby;
+pub mod ngram;
 pub mod regex;
 pub mod regex_automaton;
+pub mod select;
 pub mod starts_with;
+pub mod stats;
+#[cfg(any(feature = "geonames_routes", feature = "duui"))]
+pub mod stream;
+#[cfg(feature = "geonames_routes")]
+pub mod suggest;
+#[cfg(feature = "geonames_routes")]
+pub mod tile;
+pub mod tokens;
+pub mod validate;
 
-use find::{find, find_docs};
-use fuzzy::{fuzzy, fuzzy_docs};
-use levenshtein::{levenshtein, levenshtein_docs};
-use regex::{regex, regex_docs};
-use starts_with::{starts_with, starts_with_docs};
+use error::{ApiError, ErrorCode};
 
-use crate::geonames::data;
+use crate::geonames::data::{self, EntryField, GeoNamesEntry, MatchKey, MatchType, MatchTypeKind};
+use crate::geonames::deadline::{Deadline, TimedOut};
+#[cfg(feature = "geonames_routes")]
+use crate::geonames::geo::Extent;
+use crate::geonames::geo::{BoundingBox, Polygon};
+use crate::geonames::score::ScoreWeights;
+use crate::geonames::searcher::{Collect, Cursor, GeoNamesSearcher, Page};
 
-use aide::axum::{routing::post_with, ApiRouter};
+use std::collections::HashMap;
+#[cfg(any(feature = "geonames_routes", feature = "duui"))]
+use std::collections::VecDeque;
+#[cfg(any(feature = "geonames_routes", feature = "duui"))]
+use std::future::Future;
 
+#[cfg(feature = "geonames_routes")]
+use aide::axum::ApiRouter;
+use serde_aux::prelude::*;
+#[cfg(any(feature = "geonames_routes", feature = "duui"))]
+use tokio::sync::mpsc;
+
+#[cfg(any(feature = "geonames_routes", feature = "duui"))]
 use crate::AppState;
 
+#[cfg(feature = "geonames_routes")]
 pub(crate) fn geonames_routes(state: AppState) -> ApiRouter {
+    use autocomplete::{autocomplete, autocomplete_docs, autocomplete_get};
+    use batch::{batch, batch_docs};
+    use bbox::{bbox, bbox_docs};
+    use cache::conditional_get;
+    use cascade::{cascade, cascade_docs};
+    use compare::{compare, compare_docs};
+    use completions::{completions, completions_docs};
+    use contains::{contains, contains_docs};
+    use country::{country, country_docs};
+    use distance::{distance, distance_docs};
+    use facets::{facets, facets_docs};
+    use find::{find, find_docs};
+    use fuzzy::{fuzzy, fuzzy_docs};
+    use glob::{glob, glob_docs};
+    use keys::{keys, keys_docs};
+    use knn::{knn, knn_docs};
+    use levenshtein::{levenshtein, levenshtein_docs};
+    use names::{names, names_docs};
+    use nearby::{nearby, nearby_docs};
+    use ngram::{ngram, ngram_docs};
+    use regex::{regex, regex_docs};
+    use starts_with::{starts_with, starts_with_docs};
+    use suggest::{suggest, suggest_docs};
+    use tile::{tile, tile_docs};
+    use tokens::{tokens, tokens_docs};
+
+    use aide::axum::routing::{get_with, post_with};
+
     ApiRouter::new()
         .api_route("/find", post_with(find, find_docs))
         .api_route("/regex", post_with(regex, regex_docs))
         .api_route("/starts_with", post_with(starts_with, starts_with_docs))
+        .api_route("/contains", post_with(contains, contains_docs))
+        .api_route("/glob", post_with(glob, glob_docs))
         .api_route("/fuzzy", post_with(fuzzy, fuzzy_docs))
         .api_route("/levenshtein", post_with(levenshtein, levenshtein_docs))
+        .api_route("/cascade", post_with(cascade, cascade_docs))
+        .api_route("/ngram", post_with(ngram, ngram_docs))
+        .api_route("/tokens", post_with(tokens, tokens_docs))
+        .api_route("/completions", post_with(completions, completions_docs))
+        .api_route(
+            "/autocomplete",
+            post_with(autocomplete, autocomplete_docs)
+                .get_with(autocomplete_get, autocomplete_docs),
+        )
+        .api_route("/suggest", post_with(suggest, suggest_docs))
+        .api_route("/nearby", post_with(nearby, nearby_docs))
+        .api_route("/knn", post_with(knn, knn_docs))
+        .api_route("/tile/{z}/{x}/{y}", get_with(tile, tile_docs))
+        .api_route("/bbox", post_with(bbox, bbox_docs))
+        .api_route("/distance", post_with(distance, distance_docs))
+        .api_route("/compare", post_with(compare, compare_docs))
+        .api_route("/country", post_with(country, country_docs))
+        .api_route("/batch", post_with(batch, batch_docs))
+        .api_route("/facets", get_with(facets, facets_docs))
+        .api_route("/id/{id}/names", get_with(names, names_docs))
+        .api_route("/keys", get_with(keys, keys_docs))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            conditional_get,
+        ))
         .with_state(state)
 }
 
+/// Response header with the number of failed items of a batch request.
+#[cfg(any(feature = "geonames_routes", feature = "duui"))]
+pub(crate) const FAILED_ITEMS_HEADER: &str = "x-failed-items";
+
+/// A search request that can be run against the searcher, e.g. by its route or as part of a batch.
+pub(crate) trait Search {
+    type Item;
+
+    fn search(
+        &self,
+        searcher: &GeoNamesSearcher,
+        deadline: Deadline,
+    ) -> Result<Page<Self::Item>, ApiError>;
+}
+
+/// Run a search on the blocking thread pool, so that slow searches do not stall the runtime.
+///
+/// Fails with 504 Gateway Timeout if the search exceeds the configured search timeout. The search
+/// is given the deadline to stop streaming keys by itself once it has passed; searches that do
+/// not check it still run to completion in the background, but their result is discarded.
+#[cfg(any(feature = "geonames_routes", feature = "duui"))]
+pub(crate) async fn run_blocking<T, F>(state: &AppState, search: F) -> Result<T, ApiError>
+where
+    F: FnOnce(&GeoNamesSearcher, Deadline) -> Result<T, ApiError> + Send + 'static,
+    T: Send + 'static,
+{
+    let searcher = state.searcher();
+    let deadline = Deadline::after(state.search_timeout);
+    let task = tokio::task::spawn_blocking(move || search(&searcher, deadline));
+    let result = match state.search_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, task)
+            .await
+            .map_err(|_| timed_out(timeout))?,
+        None => task.await,
+    };
+    result.map_err(|e| ApiError::new(ErrorCode::Internal, format!("Search failed: {e}")))?
+}
+
+/// The error of a search that exceeded the search timeout.
+pub(crate) fn timed_out(timeout: std::time::Duration) -> ApiError {
+    let timeout_ms = timeout.as_millis();
+    ApiError::new(
+        ErrorCode::Timeout,
+        format!("Search timed out after {timeout_ms} ms"),
+    )
+    .with_details(serde_json::json!({ "timeout_ms": timeout_ms }))
+}
+
+impl From<TimedOut> for ApiError {
+    fn from(TimedOut(timeout): TimedOut) -> Self {
+        timed_out(timeout)
+    }
+}
+
+/// Runs `run` on each job of a batch, at most `concurrency` jobs at a time, and sends the outputs
+/// in the order of the jobs as they become available.
+///
+/// With `fail_fast`, the first output in job order for which `failed` holds is the last one to
+/// run: the outputs of all later jobs are replaced by `skipped`, even if they already ran. This
+/// reports the same items as running the jobs one after another. Stops early if the receiver is
+/// dropped.
+#[cfg(any(feature = "geonames_routes", feature = "duui"))]
+pub(crate) fn run_ordered<J, O, F, Fut>(
+    jobs: Vec<J>,
+    concurrency: usize,
+    fail_fast: bool,
+    run: F,
+    failed: fn(&O) -> bool,
+    skipped: fn(&J) -> O,
+) -> mpsc::Receiver<O>
+where
+    J: Send + 'static,
+    O: Send + 'static,
+    F: Fn(J) -> Fut + Send + 'static,
+    Fut: Future<Output = O> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(concurrency.max(1));
+    tokio::spawn(async move {
+        let mut jobs = jobs.into_iter();
+        let mut running = VecDeque::new();
+        let mut stopped = false;
+        loop {
+            while !stopped && running.len() < concurrency.max(1) {
+                let Some(job) = jobs.next() else { break };
+                running.push_back((skipped(&job), tokio::spawn(run(job))));
+            }
+            let output = match running.pop_front() {
+                Some((skip, task)) if stopped => {
+                    task.abort();
+                    skip
+                }
+                Some((_, task)) => match task.await {
+                    Ok(output) => output,
+                    // Only a panicking job fails to join, which also ends the batch
+                    Err(_) => return,
+                },
+                None => match jobs.next() {
+                    Some(job) => skipped(&job),
+                    None => return,
+                },
+            };
+            stopped |= fail_fast && failed(&output);
+            if sender.send(output).await.is_err() {
+                running.iter().for_each(|(_, task)| task.abort());
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+#[cfg(feature = "geonames_routes")]
+/// The results of a successful search. Failed searches respond with an `ApiError` instead.
 #[derive(serde::Serialize, schemars::JsonSchema)]
-pub(crate) enum Response<T> {
-    #[serde(rename = "results")]
-    Results(Vec<T>),
-    #[serde(rename = "error")]
-    Error(String),
+pub(crate) struct Response<T> {
+    pub results: Vec<T>,
+    /// Pass as `cursor` to request the next page, absent on the last page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    /// Bounding box and centroid of the results, if requested with `extent`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extent: Option<Extent>,
+    /// Whether the results were truncated to the result cap of the service (`--max-results`).
+    /// Narrow the query or page through the results with `cursor` to get the others.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// Number of results before truncation, if `truncated`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<usize>,
+}
+
+#[cfg(feature = "geonames_routes")]
+impl<T> Response<T> {
+    pub(crate) fn results(results: Vec<T>) -> Self {
+        Self {
+            results,
+            next_cursor: None,
+            extent: None,
+            truncated: false,
+            total: None,
+        }
+    }
+}
+
+#[cfg(feature = "geonames_routes")]
+impl<T: data::Entry> Response<T> {
+    /// Add the extent of the results if `enabled`.
+    pub(crate) fn with_extent(mut self, enabled: bool) -> Self {
+        self.extent = enabled.then(|| entries_extent(&self.results)).flatten();
+        self
+    }
+}
+
+#[cfg(feature = "geonames_routes")]
+/// The extent of the entries of the results.
+fn entries_extent<T: data::Entry>(results: &[T]) -> Option<Extent> {
+    Extent::of(results.iter().map(|result| {
+        let entry = result.entry();
+        (entry.latitude as f64, entry.longitude as f64)
+    }))
+}
+
+#[cfg(feature = "geonames_routes")]
+impl<T> From<Page<T>> for Response<T> {
+    fn from(page: Page<T>) -> Self {
+        Self {
+            results: page.results,
+            next_cursor: page.next_cursor,
+            extent: None,
+            truncated: page.total.is_some(),
+            total: page.total,
+        }
+    }
+}
+
+/// Page size used if a `cursor` is given without a `limit`.
+pub(crate) const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Resolve the `cursor` and `limit` options of a request.
+///
+/// With a cursor, results are paged in key order and `limit` is the page size, at most
+/// `max_results`. Without one, `limit` truncates the sorted results.
+pub(crate) fn page_and_limit(
+    cursor: &Option<String>,
+    limit: Option<usize>,
+    max_results: Option<usize>,
+) -> (Option<Cursor<'_>>, Option<usize>) {
+    match cursor {
+        Some(from) => (
+            Some(Cursor {
+                from,
+                limit: cap(limit.unwrap_or(DEFAULT_PAGE_SIZE), max_results),
+            }),
+            None,
+        ),
+        None => (None, limit),
+    }
+}
+
+/// What the searcher should collect: the page at the cursor, or only the first `limit` results
+/// if nothing drops or reorders the results after the search, instead of sorting all of them.
+pub(crate) fn collect<'a>(
+    cursor: Option<Cursor<'a>>,
+    limit: Option<usize>,
+    options: &ResultOptions,
+) -> Collect<'a> {
+    match (cursor, limit) {
+        (Some(cursor), _) => Collect::Page(cursor),
+        (None, Some(limit))
+            if !options.dedup && options.rank_by == RankBy::Match && options.sort.is_none() =>
+        {
+            Collect::Top(limit)
+        }
+        (None, _) => Collect::All,
+    }
+}
+
+/// `limit`, but at most `max_results`.
+pub(crate) fn cap(limit: usize, max_results: Option<usize>) -> usize {
+    max_results.map_or(limit, |max_results| limit.min(max_results))
+}
+
+/// Truncate the results of `page` to `limit`, then to `max_results`. Only truncation to
+/// `max_results` is reported in the `total` of the page, as the client asked for `limit`.
+pub(crate) fn limit_page<T>(
+    mut page: Page<T>,
+    limit: Option<usize>,
+    max_results: Option<usize>,
+) -> Page<T> {
+    if let Some(limit) = limit {
+        page.results.truncate(limit);
+    }
+    if let Some(max_results) = max_results.filter(|max| page.results.len() > *max) {
+        page.total = Some(page.results.len());
+        page.results.truncate(max_results);
+    }
+    page
 }
 
 fn _default_string_none() -> Option<String> {
     None
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 pub(crate) struct FilterResults {
     #[schemars(default = "_default_string_none")]
     pub feature_class: Option<String>,
@@ -48,6 +393,220 @@ pub(crate) struct FilterResults {
     pub feature_code: Option<String>,
     #[schemars(default = "_default_string_none")]
     pub country_code: Option<String>,
+    /// Code of the first-order administrative division, e.g. `05` for Hessen in Germany.
+    #[schemars(default = "_default_string_none")]
+    pub admin1_code: Option<String>,
+    #[schemars(default = "_default_string_none")]
+    pub admin2_code: Option<String>,
+    #[schemars(default = "_default_string_none")]
+    pub admin3_code: Option<String>,
+    #[schemars(default = "_default_string_none")]
+    pub admin4_code: Option<String>,
+    /// Only include entries with at least this population.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub min_population: Option<u64>,
+    /// Only include entries with at most this population.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub max_population: Option<u64>,
+    /// Only include results matched by one of these kinds of names, e.g. `["Name", "AsciiName"]`.
+    /// Ignored by spatial searches, whose results are not matched by name.
+    #[serde(default)]
+    pub match_types: Option<Vec<MatchTypeKind>>,
+    /// Only include alternate names in one of these languages, e.g. `["de", ""]`, where `""` are
+    /// alternate names without a language. Main names, ASCII names and synonyms are always
+    /// included.
+    #[serde(default)]
+    pub languages: Option<Vec<String>>,
+    /// Restrict results to entries inside this bounding box.
+    #[schemars(default = "_default_bbox_none")]
+    pub bbox: Option<BoundingBox>,
+    /// Restrict results to entries inside this polygon, given as a GeoJSON `Polygon` or
+    /// `MultiPolygon` geometry or as a WKT string.
+    #[schemars(default = "_default_polygon_none")]
+    pub polygon: Option<Polygon>,
+}
+
+fn _default_bbox_none() -> Option<BoundingBox> {
+    None
+}
+
+fn _default_polygon_none() -> Option<Polygon> {
+    None
+}
+
+impl FilterResults {
+    /// Returns true if the match passes the `match_types` and `languages` filters.
+    pub(crate) fn matches_type(&self, typ: &MatchType) -> bool {
+        self.match_types
+            .as_ref()
+            .is_none_or(|kinds| kinds.contains(&typ.kind()))
+            && self.languages.as_ref().is_none_or(|languages| {
+                typ.lang()
+                    .is_none_or(|lang| languages.iter().any(|l| l == lang))
+            })
+    }
+
+    /// Returns true if the entry passes all filters.
+    pub(crate) fn matches(&self, entry: &GeoNamesEntry) -> bool {
+        let eq = |filter: &Option<String>, value: &str| filter.as_ref().is_none_or(|f| f == value);
+        eq(&self.feature_class, &entry.feature_class)
+            && eq(&self.feature_code, &entry.feature_code)
+            && eq(&self.country_code, &entry.country_code)
+            && eq(&self.admin1_code, &entry.adm1)
+            && eq(&self.admin2_code, &entry.adm2)
+            && eq(&self.admin3_code, &entry.adm3)
+            && eq(&self.admin4_code, &entry.adm4)
+            && self
+                .min_population
+                .is_none_or(|min| entry.population >= min)
+            && self
+                .max_population
+                .is_none_or(|max| entry.population <= max)
+            && self
+                .bbox
+                .as_ref()
+                .is_none_or(|bbox| bbox.contains(entry.latitude as f64, entry.longitude as f64))
+            && self.polygon.as_ref().is_none_or(|polygon| {
+                polygon.contains(entry.latitude as f64, entry.longitude as f64)
+            })
+    }
+}
+
+/// The filter as a predicate for the searcher's collection loop.
+pub(crate) fn filter_predicate(
+    filter: &Option<FilterResults>,
+) -> impl Fn(&MatchType, &GeoNamesEntry) -> bool + '_ {
+    move |typ, entry| {
+        filter
+            .as_ref()
+            .is_none_or(|filter| filter.matches_type(typ) && filter.matches(entry))
+    }
+}
+
+/// Order of the returned results.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RankBy {
+    /// Order by the route's own criterion: match type, edit distance or spatial distance.
+    #[default]
+    Match,
+    /// Most populous entries first, then by feature class (administrative areas and populated
+    /// places before other features), keeping the route's order on ties.
+    Population,
+}
+
+/// Related entities to attach to each result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Expand {
+    /// The administrative divisions (admin1 to admin4) containing the entry.
+    Admin,
+    /// The country of the entry.
+    Country,
+}
+
+/// Additional information to attach to each result.
+#[derive(Debug, Clone, Default, serde::Deserialize, schemars::JsonSchema)]
+pub(crate) struct ExtrasOptions {
+    /// Attach related entities to each result, e.g. `["admin", "country"]` for the administrative
+    /// divisions and the country containing the entry.
+    #[serde(default)]
+    pub expand: Vec<Expand>,
+    /// Also return the name of each entry in this language, e.g. `de`, as `display_name`. Falls
+    /// back to the canonical name if the entry has no name in this language, or the language was
+    /// not included from the alternate names.
+    #[serde(default)]
+    pub display_language: Option<String>,
+    /// Also return all indexed names of each entry, with their match types, as
+    /// `alternate_names`.
+    #[serde(default)]
+    pub include_alternate_names: bool,
+    /// Also return the bounding box and centroid of the returned results as `extent`, e.g. to
+    /// zoom a map to them.
+    #[serde(default)]
+    #[cfg_attr(not(feature = "geonames_routes"), allow(dead_code))]
+    pub extent: bool,
+    /// Only return these fields of each entry, e.g. `["id", "latitude", "longitude"]`. Returns
+    /// all fields by default.
+    #[serde(default)]
+    pub fields: Option<Vec<EntryField>>,
+}
+
+/// Attach the requested extras to the (already limited) results.
+pub(crate) fn extend_results<T>(
+    mut results: Vec<T>,
+    options: &ExtrasOptions,
+    searcher: &GeoNamesSearcher,
+) -> Vec<T>
+where
+    T: data::Entry,
+{
+    let country = options.expand.contains(&Expand::Country);
+    let admin = options.expand.contains(&Expand::Admin);
+    for result in results.iter_mut() {
+        let hierarchy =
+            (country || admin).then(|| searcher.hierarchy(result.entry(), country, admin));
+        let display_name = options
+            .display_language
+            .as_ref()
+            .map(|lang| searcher.display_name(result.entry(), lang));
+        let alternate_names = options
+            .include_alternate_names
+            .then(|| searcher.names_for(result.entry().id));
+        if let Some(extras) = result.extras_mut() {
+            extras.hierarchy = hierarchy;
+            extras.display_name = display_name;
+            extras.alternate_names = alternate_names;
+        }
+    }
+    results
+}
+
+/// Explicit sort order of the results, applied after ranking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SortBy {
+    /// Closest first, by edit distance or spatial distance. Results without a distance keep their order.
+    Distance,
+    /// By the canonical name of the entry.
+    Name,
+    /// Most populous first.
+    Population,
+    /// By feature class, then feature code.
+    FeatureClass,
+    /// Main names before alternate names, see `MatchType`.
+    MatchType,
+    /// Highest relevance `score` first.
+    Score,
+}
+
+/// How the results of a search are collapsed, ordered and limited.
+#[derive(Debug, Clone, Default, serde::Deserialize, schemars::JsonSchema)]
+pub(crate) struct ResultOptions {
+    /// Collapse the results to one per GeoNames id, keeping the best match and listing the other
+    /// matching keys in `other_keys`.
+    #[serde(default)]
+    pub dedup: bool,
+    /// Order of the results. Defaults to `match`.
+    #[serde(default)]
+    pub rank_by: RankBy,
+    /// Sort the results by this field instead of the route's default order. Takes precedence over
+    /// `rank_by`, which then only breaks ties.
+    #[serde(default)]
+    pub sort: Option<SortBy>,
+    /// Return at most this many results, after filtering and ranking. Omit to return all results.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub limit: Option<usize>,
+}
+
+/// Paging through the results of a search in key order.
+#[derive(Debug, Clone, Default, serde::Deserialize, schemars::JsonSchema)]
+pub(crate) struct PageOptions {
+    /// Page through the results in key order, starting at this key. Use `""` for the first page
+    /// and the `next_cursor` of the response for the following pages. `limit` is the page size,
+    /// and `dedup` and ranking apply within each page.
+    #[serde(default)]
+    pub cursor: Option<String>,
 }
 
 pub(crate) fn _schemars_default_filter() -> Option<FilterResults> {
@@ -59,15 +618,175 @@ where
     T: data::Entry,
 {
     if let Some(filter) = filter {
-        if let Some(feature_class) = &filter.feature_class {
-            results.retain(|r| r.entry().feature_class.eq(feature_class));
+        results.retain(|r| {
+            filter.matches(r.entry()) && r.key().is_none_or(|key| filter.matches_type(key.typ()))
+        });
+    }
+    results
+}
+
+#[cfg(feature = "geonames_routes")]
+/// Group the results of a spatial search into clusters for map display.
+#[derive(Debug, Clone, Copy, serde::Deserialize, schemars::JsonSchema)]
+pub(crate) struct ClusterOptions {
+    /// Edge length of the grid cells the results are grouped by, in kilometers.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub grid_size_km: f64,
+}
+
+#[cfg(feature = "geonames_routes")]
+/// Response of a spatial search: the results, or their clusters if requested.
+pub(crate) fn spatial_response<T>(
+    format: format::ResponseFormat,
+    result: Result<Page<T>, ApiError>,
+    cluster: &Option<ClusterOptions>,
+    extent: bool,
+    fields: Option<Vec<EntryField>>,
+) -> axum::response::Response
+where
+    T: data::Entry + select::SelectFields,
+{
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+
+    use crate::geonames;
+
+    if cluster.is_some_and(|cluster| cluster.grid_size_km.is_nan() || cluster.grid_size_km <= 0.0) {
+        return ApiError::new(
+            ErrorCode::InvalidRequest,
+            "Invalid cluster: grid_size_km must be positive",
+        )
+        .into_response();
+    }
+    match (result, cluster) {
+        (Ok(page), None) => {
+            let response = Response::from(page).with_extent(extent);
+            (StatusCode::OK, format.encode(response).with_fields(fields)).into_response()
+        }
+        (Ok(page), Some(cluster)) => {
+            let clusters = geonames::cluster::cluster(
+                page.results.iter().map(data::Entry::entry),
+                cluster.grid_size_km,
+            );
+            let response = Response {
+                results: clusters,
+                next_cursor: None,
+                extent: extent.then(|| entries_extent(&page.results)).flatten(),
+                truncated: page.total.is_some(),
+                total: page.total,
+            };
+            (StatusCode::OK, format.encode(response)).into_response()
+        }
+        (Err(error), _) => error.into_response(),
+    }
+}
+
+/// Compute the relevance score of each result.
+pub(crate) fn score_results<T>(mut results: Vec<T>, weights: &ScoreWeights) -> Vec<T>
+where
+    T: data::Entry,
+{
+    for result in results.iter_mut() {
+        let key = result.key();
+        let score = weights.score(
+            key.map(|key| key.typ().ord()),
+            key.and(result.distance()),
+            result.entry().population,
+        );
+        if let Some(extras) = result.extras_mut() {
+            extras.score = Some(score);
         }
-        if let Some(feature_code) = &filter.feature_code {
-            results.retain(|r| r.entry().feature_code.eq(feature_code));
+    }
+    results
+}
+
+pub(crate) fn rank_results<T>(mut results: Vec<T>, rank_by: RankBy) -> Vec<T>
+where
+    T: data::Entry,
+{
+    if rank_by == RankBy::Population {
+        results.sort_by_key(|r| {
+            let entry = r.entry();
+            (
+                std::cmp::Reverse(entry.population),
+                entry.feature_class_priority(),
+            )
+        });
+    }
+    results
+}
+
+/// Collapse the results to one per GeoNames id, keeping the best match: the smallest distance,
+/// then the most preferred match type. The keys of the other matches are listed in `other_keys`.
+/// The kept result takes the position of the first match of its entry.
+pub(crate) fn dedup_results<T>(results: Vec<T>, dedup: bool) -> Vec<T>
+where
+    T: data::Entry,
+{
+    if !dedup {
+        return results;
+    }
+    fn is_better<T: data::Entry>(a: &T, b: &T) -> bool {
+        let ord = |r: &T| r.key().map_or(u8::MAX, |key| key.typ().ord());
+        let distance = |r: &T| r.distance().unwrap_or(0.0);
+        distance(a)
+            .total_cmp(&distance(b))
+            .then(ord(a).cmp(&ord(b)))
+            .is_lt()
+    }
+
+    let mut kept: Vec<(T, Vec<MatchKey>)> = Vec::new();
+    let mut positions: HashMap<u64, usize> = HashMap::new();
+    for result in results {
+        match positions.get(&result.entry().id) {
+            Some(&pos) => {
+                let (best, others) = &mut kept[pos];
+                let other = if is_better(&result, best) {
+                    std::mem::replace(best, result)
+                } else {
+                    result
+                };
+                others.extend(other.key().cloned());
+            }
+            None => {
+                positions.insert(result.entry().id, kept.len());
+                kept.push((result, Vec::new()));
+            }
         }
-        if let Some(country_code) = &filter.country_code {
-            results.retain(|r| r.entry().country_code.eq(country_code));
+    }
+    kept.into_iter()
+        .map(|(mut result, others)| {
+            if let Some(extras) = result.extras_mut() {
+                extras.other_keys = (!others.is_empty()).then_some(others);
+            }
+            result
+        })
+        .collect()
+}
+
+pub(crate) fn sort_results<T>(mut results: Vec<T>, sort: Option<SortBy>) -> Vec<T>
+where
+    T: data::Entry,
+{
+    match sort {
+        None => {}
+        Some(SortBy::Distance) => results.sort_by(|a, b| match (a.distance(), b.distance()) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            _ => std::cmp::Ordering::Equal,
+        }),
+        Some(SortBy::Name) => results.sort_by(|a, b| a.entry().name.cmp(&b.entry().name)),
+        Some(SortBy::Population) => {
+            results.sort_by_key(|r| std::cmp::Reverse(r.entry().population))
         }
+        Some(SortBy::FeatureClass) => results.sort_by(|a, b| {
+            let (a, b) = (a.entry(), b.entry());
+            (&a.feature_class, &a.feature_code).cmp(&(&b.feature_class, &b.feature_code))
+        }),
+        Some(SortBy::MatchType) => results.sort_by_key(|r| r.key().map(|key| key.typ().ord())),
+        Some(SortBy::Score) => results.sort_by(|a, b| match (a.score(), b.score()) {
+            (Some(a), Some(b)) => b.total_cmp(&a),
+            _ => std::cmp::Ordering::Equal,
+        }),
     }
     results
 }
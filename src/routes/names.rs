@@ -0,0 +1,41 @@
+use aide::axum::IntoApiResponse;
+use aide::transform::TransformOperation;
+use axum::extract::{Path, State};
+use axum::Json;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use super::error::{ApiError, ErrorCode};
+use super::Response;
+use crate::geonames::data::MatchKey;
+use crate::AppState;
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct IdPath {
+    /// GeoNames id of the entry.
+    pub id: u64,
+}
+
+pub(crate) async fn names(
+    State(state): State<AppState>,
+    Path(path): Path<IdPath>,
+) -> impl IntoApiResponse {
+    let names = state.searcher().names_for(path.id);
+    // Every entry is indexed under at least its main name
+    if names.is_empty() {
+        return Err(ApiError::new(
+            ErrorCode::NotFound,
+            format!("Unknown GeoNames id: {}", path.id),
+        ));
+    }
+    Ok(Json(Response::results(names)))
+}
+
+pub(crate) fn names_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Every indexed name of a GeoNames entry with how it matches the entry, e.g. as its main name, ASCII name or an alternate name in a language. Main names come first.<br>Responses carry an <code>ETag</code> and <code>Cache-Control</code>, so that clients and HTTP caches can reuse them.")
+        .response::<200, Json<Response<MatchKey>>>()
+        .response_with::<304, (), _>(|t| {
+            t.description("The names did not change since the ETag given in If-None-Match.")
+        })
+        .response_with::<404, ApiError, _>(|t| t.description("The GeoNames id is unknown."))
+}
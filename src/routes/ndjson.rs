@@ -0,0 +1,201 @@
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum::body::Body;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use futures_core::Stream;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use super::error::{ApiError, ErrorCode};
+use super::select::{SelectFields, Selected};
+use super::{cap, timed_out, PageOptions, RankBy, ResultOptions};
+use crate::geonames::data::EntryField;
+use crate::geonames::deadline::Deadline;
+use crate::geonames::searcher::GeoNamesSearcher;
+use crate::AppState;
+
+/// Media type of newline-delimited JSON, one result per line.
+pub(crate) const NDJSON: &str = "application/x-ndjson";
+
+/// Number of lines buffered between the search and the connection.
+const BUFFER_LINES: usize = 256;
+
+/// The last line of a stream that was truncated to the result cap.
+const TRUNCATED_LINE: &[u8] = b"{\"truncated\":true}\n";
+
+/// A serialized result line, or the error that ended the search.
+type Line = Result<Vec<u8>, ApiError>;
+
+/// Rejects options that need the complete result set, which a stream never holds.
+pub(crate) fn check_streamable(
+    results: &ResultOptions,
+    page: &PageOptions,
+    extent: bool,
+) -> Result<(), ApiError> {
+    let unsupported = [
+        ("dedup", results.dedup),
+        ("rank_by", results.rank_by != RankBy::Match),
+        ("sort", results.sort.is_some()),
+        ("cursor", page.cursor.is_some()),
+        ("extent", extent),
+    ];
+    match unsupported.iter().find(|(_, set)| *set) {
+        Some((option, _)) => Err(ApiError::new(
+            ErrorCode::InvalidRequest,
+            format!("`{option}` is not supported with Accept: {NDJSON}"),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Writes the results of a streamed search to the response, one line per result.
+pub(crate) struct Emitter {
+    lines: mpsc::Sender<Line>,
+    remaining: Option<usize>,
+    /// Whether `remaining` is bounded by the result cap rather than the requested limit, so that
+    /// reaching it truncates the results.
+    capped: bool,
+    fields: Option<Vec<EntryField>>,
+    deadline: Deadline,
+}
+
+impl Emitter {
+    /// The deadline of the streamed search, to stop streaming keys that produce no results.
+    pub(crate) fn deadline(&self) -> Deadline {
+        self.deadline
+    }
+
+    /// Sends a result to the client. Returns `false` once the search should stop: the limit is
+    /// reached, the search timed out, or the client went away.
+    pub(crate) fn emit<T: SelectFields + ?Sized>(&mut self, result: &T) -> bool {
+        if self.remaining == Some(0) {
+            if std::mem::take(&mut self.capped) {
+                let _ = self.lines.blocking_send(Ok(TRUNCATED_LINE.to_vec()));
+            }
+            return false;
+        }
+        if let Err(error) = self.deadline.check() {
+            let _ = self.lines.blocking_send(Err(error.into()));
+            return false;
+        }
+        let line = match &self.fields {
+            None => serde_json::to_vec(result),
+            Some(fields) => serde_json::to_vec(&Selected(result, fields)),
+        };
+        let mut line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                let error = format!("Failed to serialize result: {e}");
+                let _ = self
+                    .lines
+                    .blocking_send(Err(ApiError::new(ErrorCode::Internal, error)));
+                return false;
+            }
+        };
+        line.push(b'\n');
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= 1;
+        }
+        // At the cap, keep the search going for one more result to learn whether it truncates
+        self.lines.blocking_send(Ok(line)).is_ok() && (self.remaining != Some(0) || self.capped)
+    }
+}
+
+/// The lines of a running search, starting with the one that was awaited to pick the status.
+struct Lines {
+    first: Option<Vec<u8>>,
+    rest: mpsc::Receiver<Line>,
+}
+
+impl Stream for Lines {
+    type Item = Result<Vec<u8>, Infallible>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(first) = self.first.take() {
+            return Poll::Ready(Some(Ok(first)));
+        }
+        self.rest.poll_recv(cx).map(|line| {
+            line.map(|line| {
+                // The status has already been sent, so errors become the last line instead
+                Ok(line.unwrap_or_else(error_line))
+            })
+        })
+    }
+}
+
+/// The last line of a stream that failed after it started.
+#[derive(Serialize)]
+struct ErrorLine {
+    error: ApiError,
+}
+
+fn error_line(error: ApiError) -> Vec<u8> {
+    let mut line = serde_json::to_vec(&ErrorLine { error }).unwrap_or_default();
+    line.push(b'\n');
+    line
+}
+
+/// Runs a search on the blocking thread pool and streams its results as newline-delimited JSON
+/// while the search is still running, stopping after `limit` results. Only the given `fields` of
+/// the entries are written, if any.
+///
+/// Streams are truncated to the result cap like other responses, ending with a
+/// `{"truncated":true}` line if there were more results.
+///
+/// Errors raised before the first result are returned as a regular `ApiError` with their status.
+/// Later errors, e.g. exceeding the search timeout, end the stream with an `{"error": ...}` line
+/// holding the `ApiError`.
+/// The search stops early if the client disconnects.
+pub(crate) async fn stream_ndjson<F>(
+    state: &AppState,
+    limit: Option<usize>,
+    fields: Option<Vec<EntryField>>,
+    search: F,
+) -> axum::response::Response
+where
+    F: FnOnce(&GeoNamesSearcher, &mut Emitter) -> Result<(), ApiError> + Send + 'static,
+{
+    let (sender, mut receiver) = mpsc::channel(BUFFER_LINES);
+    let searcher = state.searcher();
+    let max_results = searcher.max_results;
+    let mut emitter = Emitter {
+        lines: sender.clone(),
+        remaining: limit.map_or(max_results, |limit| Some(cap(limit, max_results))),
+        capped: max_results
+            .is_some_and(|max_results| limit.is_none_or(|limit| limit > max_results)),
+        fields,
+        deadline: Deadline::after(state.search_timeout),
+    };
+    tokio::task::spawn_blocking(move || {
+        if let Err(error) = search(&searcher, &mut emitter) {
+            let _ = sender.blocking_send(Err(error));
+        }
+    });
+
+    let first = match state.search_timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, receiver.recv()).await {
+            Ok(first) => first,
+            Err(_) => Some(Err(timed_out(timeout))),
+        },
+        None => receiver.recv().await,
+    };
+    let first = match first.transpose() {
+        Ok(first) => first,
+        Err(error) => return error.into_response(),
+    };
+
+    let lines = Lines {
+        first,
+        rest: receiver,
+    };
+    (
+        StatusCode::OK,
+        [(CONTENT_TYPE, NDJSON)],
+        Body::from_stream(lines),
+    )
+        .into_response()
+}
@@ -0,0 +1,112 @@
+use aide::axum::IntoApiResponse;
+use aide::transform::TransformOperation;
+use axum::extract::State;
+use axum::Json;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_aux::prelude::*;
+
+use super::error::{ApiError, ErrorCode};
+use super::format::ResponseFormat;
+use super::{
+    _schemars_default_filter, dedup_results, extend_results, filter_results, limit_page,
+    rank_results, run_blocking, score_results, sort_results, spatial_response, ClusterOptions,
+    ExtrasOptions, FilterResults, Response, ResultOptions, Search,
+};
+use crate::geonames::data::GeoNamesNearbyResult;
+use crate::geonames::deadline::Deadline;
+use crate::geonames::geo::is_valid_coordinate;
+use crate::geonames::searcher::{GeoNamesSearcher, Page};
+use crate::AppState;
+
+fn _schemars_default_lat() -> f64 {
+    50.1109
+}
+fn _schemars_default_lon() -> f64 {
+    8.6821
+}
+fn _schemars_default_radius_km() -> f64 {
+    10.0
+}
+#[derive(Clone, Deserialize, JsonSchema)]
+pub(crate) struct RequestNearby {
+    /// Latitude of the center point in degrees.
+    #[schemars(default = "_schemars_default_lat")]
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lat: f64,
+    /// Longitude of the center point in degrees.
+    #[schemars(default = "_schemars_default_lon")]
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lon: f64,
+    /// Search radius in kilometers.
+    #[schemars(default = "_schemars_default_radius_km")]
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub radius_km: f64,
+    #[schemars(default = "_schemars_default_filter")]
+    pub filter: Option<FilterResults>,
+    #[serde(flatten)]
+    pub results: ResultOptions,
+    /// Return clusters of nearby results instead of the results themselves, e.g.
+    /// `{"grid_size_km": 50}`.
+    #[serde(default)]
+    pub cluster: Option<ClusterOptions>,
+    #[serde(flatten)]
+    pub extras: ExtrasOptions,
+}
+
+pub(crate) async fn nearby(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    Json(request): Json<RequestNearby>,
+) -> impl IntoApiResponse {
+    let (cluster, extent) = (request.cluster, request.extras.extent);
+    let fields = request.extras.fields.clone();
+    let result = run_blocking(&state, move |searcher, deadline| {
+        request.search(searcher, deadline)
+    })
+    .await;
+    spatial_response(format, result, &cluster, extent, fields)
+}
+
+impl Search for RequestNearby {
+    type Item = GeoNamesNearbyResult;
+
+    fn search(
+        &self,
+        searcher: &GeoNamesSearcher,
+        _deadline: Deadline,
+    ) -> Result<Page<Self::Item>, ApiError> {
+        if !is_valid_coordinate(self.lat, self.lon) {
+            return Err(ApiError::new(
+                ErrorCode::InvalidRequest,
+                "Invalid coordinates: lat must be in [-90, 90] and lon in [-180, 180]",
+            ));
+        }
+        if self.radius_km.is_nan() || self.radius_km < 0.0 {
+            return Err(ApiError::new(
+                ErrorCode::InvalidRequest,
+                "Invalid radius: radius_km must not be negative",
+            ));
+        }
+
+        let results = filter_results(
+            searcher.nearby(self.lat, self.lon, self.radius_km),
+            &self.filter,
+        );
+        let results = dedup_results(results, self.results.dedup);
+        let results = score_results(results, &searcher.score_weights);
+        let results = rank_results(results, self.results.rank_by);
+        let results = sort_results(results, self.results.sort);
+        let page = limit_page(results.into(), self.results.limit, searcher.max_results);
+        Ok(page.map(|results| extend_results(results, &self.extras, searcher)))
+    }
+}
+
+pub(crate) fn nearby_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Find all GeoNames entries within <code>radius_km</code> kilometers of a point, sorted by great-circle distance.<br>With <code>cluster</code>, nearby results are grouped into clusters with their centroid, count and most populous entry, which are returned instead.")
+        .response::<200, Json<Response<GeoNamesNearbyResult>>>()
+        .response_with::<400, ApiError, _>(|t| {
+            t.description("The coordinates or the radius were invalid.")
+        })
+        .response_with::<504, ApiError, _>(|t| t.description("The search exceeded the search timeout."))
+}
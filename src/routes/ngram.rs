@@ -0,0 +1,155 @@
+#[cfg(feature = "geonames_routes")]
+use aide::axum::IntoApiResponse;
+#[cfg(feature = "geonames_routes")]
+use aide::transform::TransformOperation;
+#[cfg(feature = "geonames_routes")]
+use axum::extract::State;
+#[cfg(feature = "geonames_routes")]
+use axum::Json;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_aux::prelude::*;
+
+use super::error::{ApiError, ErrorCode};
+#[cfg(feature = "geonames_routes")]
+use super::format::ResponseFormat;
+#[cfg(feature = "geonames_routes")]
+use super::validate::Valid;
+use super::validate::{deserialize_edit_distance, FieldErrors, Validate};
+use super::{
+    _schemars_default_filter, dedup_results, extend_results, filter_predicate, limit_page,
+    rank_results, score_results, sort_results, ExtrasOptions, FilterResults, ResultOptions, Search,
+};
+#[cfg(feature = "geonames_routes")]
+use super::{run_blocking, Response};
+#[cfg(feature = "geonames_routes")]
+use crate::diagnostics::RequestSummary;
+use crate::geonames::data::GeoNamesSearchResultWithDist;
+use crate::geonames::deadline::Deadline;
+use crate::geonames::distance::EditMetric;
+use crate::geonames::searcher::{GeoNamesSearcher, Page};
+#[cfg(feature = "geonames_routes")]
+use crate::AppState;
+
+fn _default_min_similarity() -> f32 {
+    0.2
+}
+#[derive(Clone, Deserialize, JsonSchema)]
+pub(crate) struct RequestOptsNgram {
+    /// Filter results by edit distance. Omit or set to `0` to disable filtering.
+    #[serde(
+        default = "default_u32::<0>",
+        deserialize_with = "deserialize_edit_distance"
+    )]
+    pub max_dist: u32,
+    /// Edit distance used for `max_dist` and the reported distances. Defaults to `levenshtein`.
+    #[serde(default)]
+    pub metric: EditMetric,
+    /// Minimum share of trigrams a name has to have in common with the query (Jaccard index,
+    /// between 0 and 1). Defaults to 0.2.
+    #[serde(
+        default = "_default_min_similarity",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub min_similarity: f32,
+    /// Number of names with the most trigrams in common with the query to re-rank by edit
+    /// distance. Defaults to 100.
+    #[serde(
+        default = "default_u32::<100>",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub candidates: u32,
+    #[schemars(default = "_schemars_default_filter")]
+    pub filter: Option<FilterResults>,
+    #[serde(flatten)]
+    pub results: ResultOptions,
+    #[serde(flatten)]
+    pub extras: ExtrasOptions,
+}
+
+fn _schemars_default_ngram_query() -> String {
+    "Fran1dfnrt am Mian".to_string()
+}
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct RequestNgram {
+    /// The search query (name of the GeoNames entity).
+    #[validate(length(min = 1))]
+    #[schemars(default = "_schemars_default_ngram_query")]
+    pub query: String,
+
+    #[serde(flatten)]
+    pub opts: RequestOptsNgram,
+}
+
+#[cfg(feature = "geonames_routes")]
+pub(crate) async fn ngram(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    Valid(Json(request)): Valid<Json<RequestNgram>>,
+) -> impl IntoApiResponse {
+    let summary = RequestSummary::query(&request.query);
+    let extent = request.opts.extras.extent;
+    let fields = request.opts.extras.fields.clone();
+    let response = run_blocking(&state, move |searcher, deadline| {
+        request.search(searcher, deadline)
+    })
+    .await
+    .map(|page| {
+        format
+            .encode(Response::from(page).with_extent(extent))
+            .with_fields(fields)
+    });
+    summary.attach(response)
+}
+
+impl Validate for RequestNgram {
+    fn check(&self, errors: &mut FieldErrors) {
+        errors.query("query", &self.query);
+        errors.edit_distance("max_dist", self.opts.max_dist);
+    }
+}
+
+impl Search for RequestNgram {
+    type Item = GeoNamesSearchResultWithDist;
+
+    fn search(
+        &self,
+        searcher: &GeoNamesSearcher,
+        _deadline: Deadline,
+    ) -> Result<Page<Self::Item>, ApiError> {
+        let results = searcher
+            .ngram_search(
+                &self.query,
+                self.opts.metric,
+                Some(self.opts.max_dist),
+                self.opts.min_similarity,
+                self.opts.candidates as usize,
+                filter_predicate(&self.opts.filter),
+            )
+            .ok_or(ApiError::new(
+                ErrorCode::IndexUnavailable,
+                "The n-gram index was not built, start the service with --ngram-index",
+            ))?;
+        let results = dedup_results(results, self.opts.results.dedup);
+        let results = score_results(results, &searcher.score_weights);
+        let results = rank_results(results, self.opts.results.rank_by);
+        let results = sort_results(results, self.opts.results.sort);
+        Ok(limit_page(
+            results.into(),
+            self.opts.results.limit,
+            searcher.max_results,
+        )
+        .map(|results| extend_results(results, &self.opts.extras, searcher)))
+    }
+}
+
+#[cfg(feature = "geonames_routes")]
+pub(crate) fn ngram_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Find GeoNames entries for heavily garbled names, e.g. from OCR, that are too far off for the Levenshtein search. Retrieves the names sharing the most character trigrams with the query and re-ranks them by edit distance.<br>Requires the index to be built with <code>--ngram-index</code>.")
+        .response::<200, Json<Response<GeoNamesSearchResultWithDist>>>()
+        .response_with::<422, ApiError, _>(|t| t.description("The query was empty or too long, or `max_dist` was too high."))
+        .response_with::<501, ApiError, _>(|t| {
+            t.description("The index was built without the trigram index.")
+        })
+        .response_with::<504, ApiError, _>(|t| t.description("The search exceeded the search timeout."))
+}
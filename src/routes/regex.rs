@@ -1,25 +1,59 @@
-use std::str::FromStr;
-
+#[cfg(feature = "geonames_routes")]
 use aide::axum::IntoApiResponse;
+#[cfg(feature = "geonames_routes")]
 use aide::transform::TransformOperation;
+#[cfg(feature = "geonames_routes")]
 use axum::extract::State;
-use axum::{http::StatusCode, Json};
+#[cfg(feature = "geonames_routes")]
+use axum::http::HeaderMap;
+#[cfg(feature = "geonames_routes")]
+use axum::response::IntoResponse;
+#[cfg(feature = "geonames_routes")]
+use axum::Json;
 use schemars::JsonSchema;
 use serde::Deserialize;
 
-use super::docs::{DocError, DocResults};
-use super::regex_automaton::RegexSearchAutomaton;
-use super::{filter_results, FilterResults, Response, _schemars_default_filter};
+use super::error::{ApiError, ErrorCode};
+#[cfg(feature = "geonames_routes")]
+use super::format::ResponseFormat;
+#[cfg(feature = "geonames_routes")]
+use super::ndjson::{check_streamable, stream_ndjson, Emitter, NDJSON};
+use super::regex_automaton::{RegexFlags, RegexSearchAutomaton};
+#[cfg(feature = "geonames_routes")]
+use super::stream::accepts;
+#[cfg(feature = "geonames_routes")]
+use super::validate::Valid;
+use super::validate::{FieldErrors, Validate};
+use super::{
+    _schemars_default_filter, collect, dedup_results, extend_results, filter_predicate, limit_page,
+    page_and_limit, rank_results, score_results, sort_results, ExtrasOptions, FilterResults,
+    PageOptions, ResultOptions, Search,
+};
+#[cfg(feature = "geonames_routes")]
+use super::{run_blocking, Response};
+#[cfg(feature = "geonames_routes")]
+use crate::diagnostics::RequestSummary;
 use crate::geonames::data::GeoNamesSearchResult;
+use crate::geonames::deadline::Deadline;
+use crate::geonames::searcher::{GeoNamesSearcher, Page};
+#[cfg(feature = "geonames_routes")]
 use crate::AppState;
 
-#[derive(Deserialize, JsonSchema)]
+#[derive(Clone, Deserialize, JsonSchema)]
 pub(crate) struct RequestOptsRegex {
     #[schemars(
         default = "_schemars_default_filter",
         skip_serializing_if = "Option::is_none"
     )]
     pub filter: Option<FilterResults>,
+    #[serde(flatten)]
+    pub flags: RegexFlags,
+    #[serde(flatten)]
+    pub results: ResultOptions,
+    #[serde(flatten)]
+    pub extras: ExtrasOptions,
+    #[serde(flatten)]
+    pub page: PageOptions,
 }
 
 fn _schemars_default_regex() -> String {
@@ -36,34 +70,118 @@ pub(crate) struct RequestRegex {
     pub opts: RequestOptsRegex,
 }
 
+#[cfg(feature = "geonames_routes")]
 pub(crate) async fn regex(
     State(state): State<AppState>,
-    Json(request): Json<RequestRegex>,
+    format: ResponseFormat,
+    headers: HeaderMap,
+    Valid(Json(request)): Valid<Json<RequestRegex>>,
 ) -> impl IntoApiResponse {
-    if request.regex.is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(Response::Error("Empty query".to_string())),
+    let summary = RequestSummary::query(&request.regex);
+    if accepts(&headers, NDJSON) {
+        let (limit, fields) = (
+            request.opts.results.limit,
+            request.opts.extras.fields.clone(),
+        );
+        return summary.attach(
+            stream_ndjson(&state, limit, fields, move |searcher, emitter| {
+                request.stream(searcher, emitter)
+            })
+            .await,
         );
     }
 
-    let dfa = RegexSearchAutomaton::from_str(&request.regex);
-    if let Ok(query) = dfa {
-        let results = filter_results(state.searcher.search(query), &request.opts.filter);
+    let extent = request.opts.extras.extent;
+    let fields = request.opts.extras.fields.clone();
+    let response = run_blocking(&state, move |searcher, deadline| {
+        request.search(searcher, deadline)
+    })
+    .await
+    .map(|page| {
+        format
+            .encode(Response::from(page).with_extent(extent))
+            .with_fields(fields)
+    })
+    .into_response();
+    summary.attach(response)
+}
 
-        (StatusCode::OK, Json(Response::Results(results)))
-    } else {
-        let e = dfa.unwrap_err();
+impl RequestRegex {
+    fn query(&self) -> Result<RegexSearchAutomaton, ApiError> {
+        RegexSearchAutomaton::new(&self.regex, &self.opts.flags)
+            .map_err(|e| ApiError::new(ErrorCode::InvalidPattern, format!("RegexError: {e:#}")))
+    }
 
-        (
-            StatusCode::BAD_REQUEST,
-            Json(Response::Error(format!("RegexError: {e:?}").to_string())),
-        )
+    /// Stream the results in key order as they are found.
+    #[cfg(feature = "geonames_routes")]
+    fn stream(&self, searcher: &GeoNamesSearcher, emitter: &mut Emitter) -> Result<(), ApiError> {
+        let opts = &self.opts;
+        check_streamable(&opts.results, &opts.page, opts.extras.extent)?;
+        let deadline = emitter.deadline();
+        let query = self.query()?;
+        searcher.search_each(
+            query,
+            deadline,
+            filter_predicate(&opts.filter),
+            |key, typ, gn| {
+                let results = vec![GeoNamesSearchResult::new(key, typ, gn)];
+                let results = score_results(results, &searcher.score_weights);
+                extend_results(results, &opts.extras, searcher)
+                    .iter()
+                    .all(|result| emitter.emit(result))
+            },
+        )?;
+        Ok(())
+    }
+}
+
+impl Validate for RequestRegex {
+    fn check(&self, errors: &mut FieldErrors) {
+        errors.query("regex", &self.regex);
+    }
+}
+
+impl Search for RequestRegex {
+    type Item = GeoNamesSearchResult;
+
+    fn search(
+        &self,
+        searcher: &GeoNamesSearcher,
+        deadline: Deadline,
+    ) -> Result<Page<Self::Item>, ApiError> {
+        let query = self.query()?;
+        let (cursor, limit) = page_and_limit(
+            &self.opts.page.cursor,
+            self.opts.results.limit,
+            searcher.max_results,
+        );
+        let collect = collect(cursor, limit, &self.opts.results);
+        let page = searcher.search(
+            query,
+            collect,
+            deadline,
+            filter_predicate(&self.opts.filter),
+        )?;
+        let page = page.map(|results| {
+            let results = dedup_results(results, self.opts.results.dedup);
+            let results = score_results(results, &searcher.score_weights);
+            let results = rank_results(results, self.opts.results.rank_by);
+            sort_results(results, self.opts.results.sort)
+        });
+        Ok(limit_page(page, limit, searcher.max_results)
+            .map(|results| extend_results(results, &self.opts.extras, searcher)))
     }
 }
 
+#[cfg(feature = "geonames_routes")]
 pub(crate) fn regex_docs(op: TransformOperation) -> TransformOperation {
-    op.description("Find all GeoNames entries with the specified regex.")
-        .response::<200, Json<DocResults<GeoNamesSearchResult>>>()
-        .response_with::<400, Json<DocError>, _>(|t| t.description("The query was empty."))
+    op.description("Find all GeoNames entries with the specified regex.<br>With <code>Accept: application/x-ndjson</code>, the results are streamed in key order as newline-delimited JSON while the search runs, without <code>dedup</code>, <code>rank_by</code>, <code>sort</code>, <code>cursor</code> or <code>extent</code>. An error after the first result ends the stream with an <code>{\"error\": ...}</code> line.")
+        .response::<200, Json<Response<GeoNamesSearchResult>>>()
+        .response_with::<400, ApiError, _>(|t| {
+            t.description("The query is not a valid regular expression, or the options cannot be streamed.")
+        })
+        .response_with::<422, ApiError, _>(|t| t.description("The query was empty or too long."))
+        .response_with::<504, ApiError, _>(|t| {
+            t.description("The search exceeded the search timeout.")
+        })
 }
@@ -3,7 +3,47 @@ use std::str::FromStr;
 use regex_automata::dfa::dense::DFA;
 use regex_automata::dfa::{dense, Automaton as RegexAutomaton};
 use regex_automata::util::primitives::StateID;
+use regex_automata::util::syntax;
 use regex_automata::Input;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+fn _default_true() -> bool {
+    true
+}
+
+/// Flags of the regular expression, equivalent to the inline flags `(?i)`, `(?u)` and `(?s)`.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub(crate) struct RegexFlags {
+    /// Match letters regardless of case.
+    #[serde(default)]
+    pub case_insensitive: bool,
+    /// Enable Unicode support, e.g. for case folding and `\w`. Disabling it makes the automaton
+    /// smaller, but classes like `\w` then only match ASCII. Defaults to `true`.
+    #[serde(default = "_default_true")]
+    pub unicode: bool,
+    /// Allow `.` to match `\n`.
+    #[serde(default)]
+    pub dot_matches_newline: bool,
+}
+
+/// Where a literal query has to occur in a key.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum LiteralPosition {
+    Exact,
+    Prefix,
+    Substring,
+}
+
+impl Default for RegexFlags {
+    fn default() -> Self {
+        Self {
+            case_insensitive: false,
+            unicode: true,
+            dot_matches_newline: false,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub(crate) struct RegexSearchAutomaton {
@@ -15,10 +55,68 @@ impl FromStr for RegexSearchAutomaton {
     type Err = anyhow::Error;
 
     fn from_str(query: &str) -> Result<Self, Self::Err> {
-        let dfa = dense::DFA::new(query)?;
+        Self::new(query, &RegexFlags::default())
+    }
+}
+
+impl RegexSearchAutomaton {
+    pub(crate) fn new(query: &str, flags: &RegexFlags) -> Result<Self, anyhow::Error> {
+        let dfa = dense::Builder::new()
+            .syntax(
+                syntax::Config::new()
+                    .case_insensitive(flags.case_insensitive)
+                    .unicode(flags.unicode)
+                    .dot_matches_new_line(flags.dot_matches_newline),
+            )
+            .build(query)?;
         let start_state = dfa.start_state_forward(&Input::new(query))?;
         Ok(RegexSearchAutomaton { dfa, start_state })
     }
+
+    /// Match keys that equal, start with or contain the literal.
+    pub(crate) fn literal(
+        literal: &str,
+        position: LiteralPosition,
+        case_insensitive: bool,
+    ) -> Result<Self, anyhow::Error> {
+        let literal = regex_syntax::escape(literal);
+        let pattern = match position {
+            LiteralPosition::Exact => format!("^{literal}$"),
+            LiteralPosition::Prefix => format!("^{literal}.*"),
+            LiteralPosition::Substring => format!("^.*{literal}.*"),
+        };
+        let flags = RegexFlags {
+            case_insensitive,
+            dot_matches_newline: true,
+            ..Default::default()
+        };
+        Self::new(&pattern, &flags)
+    }
+
+    /// Match keys against a glob pattern, where `*` matches any sequence of characters and `?`
+    /// any single character. A backslash escapes the following character.
+    pub(crate) fn glob(pattern: &str, case_insensitive: bool) -> Result<Self, anyhow::Error> {
+        let mut regex = String::from("^");
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' => regex.push_str(".*"),
+                '?' => regex.push('.'),
+                '\\' => match chars.next() {
+                    Some(escaped) => regex.push_str(&regex_syntax::escape(&escaped.to_string())),
+                    None => Err(anyhow::anyhow!("Trailing backslash in glob pattern"))?,
+                },
+                c => regex.push_str(&regex_syntax::escape(&c.to_string())),
+            }
+        }
+        regex.push('$');
+        let flags = RegexFlags {
+            case_insensitive,
+            dot_matches_newline: true,
+            ..Default::default()
+        };
+        Self::new(&regex, &flags)
+    }
 }
 
 impl fst::Automaton for RegexSearchAutomaton {
@@ -29,6 +127,10 @@ impl fst::Automaton for RegexSearchAutomaton {
         Some(self.start_state)
     }
 
+    fn can_match(&self, state: &Self::State) -> bool {
+        state.is_some_and(|state| !self.dfa.is_dead_state(state))
+    }
+
     fn is_match(&self, state: &Self::State) -> bool {
         state
             .map(|state| self.dfa.is_match_state(self.dfa.next_eoi_state(state)))
@@ -0,0 +1,128 @@
+use serde::{Serialize, Serializer};
+
+use crate::geonames::data::{
+    EntryField, GeoNamesEntry, GeoNamesNearbyResult, GeoNamesSearchResult,
+    GeoNamesSearchResultWithDist, MatchKey, ResultExtras, SelectedEntry,
+};
+
+/// Values that can be serialized with only some fields of the entries they contain.
+///
+/// Values without entries keep the default, which serializes them as a whole.
+pub(crate) trait SelectFields: Serialize {
+    fn serialize_selected<S: Serializer>(
+        &self,
+        fields: &[EntryField],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let _ = fields;
+        self.serialize(serializer)
+    }
+}
+
+/// Serializes `T` with only the selected fields of its entries, however deeply nested, e.g. in
+/// the results of a batch.
+pub(crate) struct Selected<'a, T: ?Sized>(pub &'a T, pub &'a [EntryField]);
+
+impl<T: SelectFields + ?Sized> Serialize for Selected<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize_selected(self.1, serializer)
+    }
+}
+
+impl<T: SelectFields> SelectFields for [T] {
+    fn serialize_selected<S: Serializer>(
+        &self,
+        fields: &[EntryField],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter().map(|value| Selected(value, fields)))
+    }
+}
+
+impl<T: SelectFields> SelectFields for Vec<T> {
+    fn serialize_selected<S: Serializer>(
+        &self,
+        fields: &[EntryField],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        self.as_slice().serialize_selected(fields, serializer)
+    }
+}
+
+impl SelectFields for GeoNamesEntry {
+    fn serialize_selected<S: Serializer>(
+        &self,
+        fields: &[EntryField],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        SelectedEntry(self, fields).serialize(serializer)
+    }
+}
+
+impl SelectFields for GeoNamesSearchResult {
+    fn serialize_selected<S: Serializer>(
+        &self,
+        fields: &[EntryField],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Result<'a> {
+            key: &'a MatchKey,
+            entry: SelectedEntry<'a>,
+            #[serde(flatten)]
+            extras: &'a ResultExtras,
+        }
+        Result {
+            key: &self.key,
+            entry: SelectedEntry(&self.entry, fields),
+            extras: &self.extras,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl SelectFields for GeoNamesSearchResultWithDist {
+    fn serialize_selected<S: Serializer>(
+        &self,
+        fields: &[EntryField],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Result<'a> {
+            key: &'a MatchKey,
+            entry: SelectedEntry<'a>,
+            distance: usize,
+            #[serde(flatten)]
+            extras: &'a ResultExtras,
+        }
+        Result {
+            key: &self.key,
+            entry: SelectedEntry(&self.entry, fields),
+            distance: self.distance,
+            extras: &self.extras,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl SelectFields for GeoNamesNearbyResult {
+    fn serialize_selected<S: Serializer>(
+        &self,
+        fields: &[EntryField],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Result<'a> {
+            entry: SelectedEntry<'a>,
+            distance_km: f64,
+            #[serde(flatten)]
+            extras: &'a ResultExtras,
+        }
+        Result {
+            entry: SelectedEntry(&self.entry, fields),
+            distance_km: self.distance_km,
+            extras: &self.extras,
+        }
+        .serialize(serializer)
+    }
+}
@@ -1,28 +1,69 @@
+#[cfg(feature = "geonames_routes")]
 use aide::axum::IntoApiResponse;
+#[cfg(feature = "geonames_routes")]
 use aide::transform::TransformOperation;
+#[cfg(feature = "geonames_routes")]
 use axum::extract::State;
-use axum::{http::StatusCode, Json};
+#[cfg(feature = "geonames_routes")]
+use axum::http::HeaderMap;
+#[cfg(feature = "geonames_routes")]
+use axum::response::IntoResponse;
+#[cfg(feature = "geonames_routes")]
+use axum::Json;
 use fst::automaton::Str;
 use fst::Automaton;
 use schemars::JsonSchema;
 use serde::Deserialize;
 use serde_aux::prelude::*;
 
-use super::docs::{DocError, DocResults};
-use super::{filter_results, FilterResults, Response, _schemars_default_filter};
+use super::error::{ApiError, ErrorCode};
+#[cfg(feature = "geonames_routes")]
+use super::format::ResponseFormat;
+#[cfg(feature = "geonames_routes")]
+use super::ndjson::{check_streamable, stream_ndjson, Emitter, NDJSON};
+use super::regex_automaton::{LiteralPosition, RegexSearchAutomaton};
+#[cfg(feature = "geonames_routes")]
+use super::stream::accepts;
+#[cfg(feature = "geonames_routes")]
+use super::validate::Valid;
+use super::validate::{deserialize_edit_distance, FieldErrors, Validate};
+use super::{
+    _schemars_default_filter, collect, dedup_results, extend_results, filter_predicate, limit_page,
+    page_and_limit, rank_results, score_results, sort_results, ExtrasOptions, FilterResults,
+    PageOptions, ResultOptions, Search,
+};
+#[cfg(feature = "geonames_routes")]
+use super::{run_blocking, Response};
+#[cfg(feature = "geonames_routes")]
+use crate::diagnostics::RequestSummary;
 use crate::geonames::data::GeoNamesSearchResultWithDist;
+#[cfg(feature = "geonames_routes")]
+use crate::geonames::data::{GeoNamesEntry, MatchType};
+use crate::geonames::deadline::Deadline;
+use crate::geonames::distance::EditMetric;
+use crate::geonames::searcher::{GeoNamesSearcher, Page};
+#[cfg(feature = "geonames_routes")]
 use crate::AppState;
 
-#[derive(Deserialize, JsonSchema)]
+#[derive(Clone, Deserialize, JsonSchema)]
 pub(crate) struct RequestOptsStartsWith {
     /// Filter results by Levenshtein distance. Omit or set to `0` to disable filtering.
     #[serde(
         default = "default_u32::<0>",
-        deserialize_with = "deserialize_number_from_string"
+        deserialize_with = "deserialize_edit_distance"
     )]
     pub max_dist: u32,
+    /// Match the query ignoring case, e.g. "frankfurt am main" finds "Frankfurt am Main".
+    #[serde(default)]
+    pub case_insensitive: bool,
     #[schemars(default = "_schemars_default_filter")]
     pub filter: Option<FilterResults>,
+    #[serde(flatten)]
+    pub results: ResultOptions,
+    #[serde(flatten)]
+    pub extras: ExtrasOptions,
+    #[serde(flatten)]
+    pub page: PageOptions,
 }
 
 fn _schemars_default_query() -> String {
@@ -39,30 +80,152 @@ pub(crate) struct RequestStartsWith {
     pub opts: RequestOptsStartsWith,
 }
 
+#[cfg(feature = "geonames_routes")]
 pub(crate) async fn starts_with(
     State(state): State<AppState>,
-    Json(request): Json<RequestStartsWith>,
+    format: ResponseFormat,
+    headers: HeaderMap,
+    Valid(Json(request)): Valid<Json<RequestStartsWith>>,
 ) -> impl IntoApiResponse {
-    if request.query.is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(Response::Error("Empty query".to_string())),
+    let summary = RequestSummary::query(&request.query);
+    if accepts(&headers, NDJSON) {
+        let (limit, fields) = (
+            request.opts.results.limit,
+            request.opts.extras.fields.clone(),
+        );
+        return summary.attach(
+            stream_ndjson(&state, limit, fields, move |searcher, emitter| {
+                request.stream(searcher, emitter)
+            })
+            .await,
         );
     }
 
-    let query = Str::new(&request.query).starts_with();
+    let extent = request.opts.extras.extent;
+    let fields = request.opts.extras.fields.clone();
+    let response = run_blocking(&state, move |searcher, deadline| {
+        request.search(searcher, deadline)
+    })
+    .await
+    .map(|page| {
+        format
+            .encode(Response::from(page).with_extent(extent))
+            .with_fields(fields)
+    })
+    .into_response();
+    summary.attach(response)
+}
 
-    let results =
-        state
-            .searcher
-            .search_with_dist(query, &request.query, Some(request.opts.max_dist));
-    let results = filter_results(results, &request.opts.filter);
+impl RequestStartsWith {
+    /// Stream the results in key order as they are found.
+    #[cfg(feature = "geonames_routes")]
+    fn stream(&self, searcher: &GeoNamesSearcher, emitter: &mut Emitter) -> Result<(), ApiError> {
+        let opts = &self.opts;
+        check_streamable(&opts.results, &opts.page, opts.extras.extent)?;
+        let deadline = emitter.deadline();
+        let metric = EditMetric::default();
+        let emit = |key: &str, typ: &MatchType, gn: &GeoNamesEntry| {
+            let dist = match opts.max_dist {
+                0 => metric.distance(&self.query, key),
+                max => match metric.distance_within(&self.query, key, max as usize) {
+                    Some(dist) => dist,
+                    None => return true,
+                },
+            };
+            let results = vec![GeoNamesSearchResultWithDist::new(key, typ, gn, dist)];
+            let results = score_results(results, &searcher.score_weights);
+            extend_results(results, &opts.extras, searcher)
+                .iter()
+                .all(|result| emitter.emit(result))
+        };
+        let filter = filter_predicate(&opts.filter);
+        if opts.case_insensitive {
+            let query = RegexSearchAutomaton::literal(&self.query, LiteralPosition::Prefix, true)
+                .map_err(|e| {
+                ApiError::new(ErrorCode::InvalidPattern, format!("RegexError: {e:#}"))
+            })?;
+            searcher.search_each(query, deadline, filter, emit)?;
+        } else {
+            searcher.search_each(Str::new(&self.query).starts_with(), deadline, filter, emit)?;
+        }
+        Ok(())
+    }
+}
 
-    (StatusCode::OK, Json(Response::Results(results)))
+impl Validate for RequestStartsWith {
+    fn check(&self, errors: &mut FieldErrors) {
+        errors.query("query", &self.query);
+        self.opts.check(errors);
+    }
+}
+
+impl Validate for RequestOptsStartsWith {
+    fn check(&self, errors: &mut FieldErrors) {
+        errors.edit_distance("max_dist", self.max_dist);
+    }
+}
+
+impl Search for RequestStartsWith {
+    type Item = GeoNamesSearchResultWithDist;
+
+    fn search(
+        &self,
+        searcher: &GeoNamesSearcher,
+        deadline: Deadline,
+    ) -> Result<Page<Self::Item>, ApiError> {
+        let (cursor, limit) = page_and_limit(
+            &self.opts.page.cursor,
+            self.opts.results.limit,
+            searcher.max_results,
+        );
+        let collect = collect(cursor, limit, &self.opts.results);
+        let filter = filter_predicate(&self.opts.filter);
+        let metric = EditMetric::default();
+        let max_dist = Some(self.opts.max_dist);
+        let page = if self.opts.case_insensitive {
+            let query = RegexSearchAutomaton::literal(&self.query, LiteralPosition::Prefix, true)
+                .map_err(|e| {
+                ApiError::new(ErrorCode::InvalidPattern, format!("RegexError: {e:#}"))
+            })?;
+            searcher.search_with_dist(
+                query,
+                &self.query,
+                metric,
+                max_dist,
+                collect,
+                deadline,
+                filter,
+            )?
+        } else {
+            let query = Str::new(&self.query).starts_with();
+            searcher.search_with_dist(
+                query,
+                &self.query,
+                metric,
+                max_dist,
+                collect,
+                deadline,
+                filter,
+            )?
+        };
+        let page = page.map(|results| {
+            let results = dedup_results(results, self.opts.results.dedup);
+            let results = score_results(results, &searcher.score_weights);
+            let results = rank_results(results, self.opts.results.rank_by);
+            sort_results(results, self.opts.results.sort)
+        });
+        Ok(limit_page(page, limit, searcher.max_results)
+            .map(|results| extend_results(results, &self.opts.extras, searcher)))
+    }
 }
 
+#[cfg(feature = "geonames_routes")]
 pub(crate) fn starts_with_docs(op: TransformOperation) -> TransformOperation {
-    op.description("Find all GeoNames entries that start with the specified string.")
-        .response::<200, Json<DocResults<GeoNamesSearchResultWithDist>>>()
-        .response_with::<400, Json<DocError>, _>(|t| t.description("The query was empty."))
+    op.description("Find all GeoNames entries that start with the specified string.<br>With <code>Accept: application/x-ndjson</code>, the results are streamed in key order as newline-delimited JSON while the search runs, without <code>dedup</code>, <code>rank_by</code>, <code>sort</code>, <code>cursor</code> or <code>extent</code>. An error after the first result ends the stream with an <code>{\"error\": ...}</code> line.")
+        .response::<200, Json<Response<GeoNamesSearchResultWithDist>>>()
+        .response_with::<400, ApiError, _>(|t| t.description("The options cannot be streamed."))
+        .response_with::<422, ApiError, _>(|t| t.description("The query was empty or too long, or `max_dist` was too high."))
+        .response_with::<504, ApiError, _>(|t| {
+            t.description("The search exceeded the search timeout.")
+        })
 }
@@ -0,0 +1,123 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aide::axum::IntoApiResponse;
+use aide::transform::TransformOperation;
+use axum::extract::State;
+use axum::{http::StatusCode, Json};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use super::facets::Facets;
+use crate::geonames::searcher::GeoNamesSearcher;
+use crate::AppState;
+
+/// Statistics of the served index, computed once when the index is loaded.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub(crate) struct IndexStats {
+    /// Number of distinct search keys in the FST.
+    pub number_of_keys: usize,
+    /// Number of keys matching more than one entry, e.g. the names of several towns.
+    pub shared_keys: usize,
+    /// Share of the keys matching more than one entry.
+    pub shared_key_ratio: f64,
+    pub number_of_geonames: usize,
+    /// Number of entries per GeoNames feature class.
+    pub feature_classes: BTreeMap<String, usize>,
+    /// Number of entries per ISO-3166 country code.
+    pub countries: BTreeMap<String, usize>,
+    /// Size of the FST in bytes.
+    pub fst_bytes: usize,
+    /// Number of alternate names per language. Names without a language are counted under `""`.
+    pub alternate_name_languages: BTreeMap<String, usize>,
+    /// Build time of the index in seconds since the Unix epoch.
+    pub built: u64,
+    /// Peak resident memory of the process in bytes when the statistics were computed, right
+    /// after building or loading the index. Only known on Linux.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peak_rss_bytes: Option<u64>,
+}
+
+impl IndexStats {
+    pub fn new(searcher: &GeoNamesSearcher, facets: &Facets) -> Self {
+        let mut alternate_name_languages = BTreeMap::new();
+        for lang in searcher
+            .search_matches
+            .iter()
+            .flatten()
+            .filter_map(|typ| typ.lang())
+        {
+            *alternate_name_languages
+                .entry(lang.to_string())
+                .or_default() += 1;
+        }
+
+        let shared_keys = searcher
+            .search_matches
+            .iter()
+            .filter(|matches| matches.iter().any(|typ| typ.id() != matches[0].id()))
+            .count();
+
+        Self {
+            number_of_keys: searcher.map.len(),
+            shared_keys,
+            shared_key_ratio: shared_keys as f64 / searcher.map.len().max(1) as f64,
+            number_of_geonames: searcher.geonames.len(),
+            feature_classes: facets.feature_classes.clone(),
+            countries: facets.countries.clone(),
+            fst_bytes: searcher.map.as_fst().as_bytes().len(),
+            alternate_name_languages,
+            // Indices that were not loaded from an artifact are built right before serving
+            built: match &searcher.manifest {
+                Some(manifest) => manifest.created,
+                None => SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|now| now.as_secs())
+                    .unwrap_or_default(),
+            },
+            peak_rss_bytes: peak_rss_bytes(),
+        }
+    }
+
+    pub fn log(&self) {
+        tracing::info!(
+            keys = self.number_of_keys,
+            shared_keys = self.shared_keys,
+            shared_key_ratio = self.shared_key_ratio,
+            geonames = self.number_of_geonames,
+            feature_classes = ?self.feature_classes,
+            countries = ?self.countries,
+            alternate_name_languages = ?self.alternate_name_languages,
+            fst_bytes = self.fst_bytes,
+            peak_rss_bytes = self.peak_rss_bytes,
+            "Index statistics"
+        );
+    }
+}
+
+/// Peak resident set size of the process, from `VmHWM` in `/proc/self/status`.
+fn peak_rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kilobytes = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+pub(crate) async fn stats(State(state): State<AppState>) -> impl IntoApiResponse {
+    (StatusCode::OK, Json(state.index.load().stats.clone()))
+}
+
+pub(crate) fn stats_docs(op: TransformOperation) -> TransformOperation {
+    op.description(
+        "Statistics of the served index: key and entry counts, the share of keys matching several \
+         entries, entries per feature class and country, FST size, alternate names per language, \
+         build time and the peak memory of the process after the build.",
+    )
+    .response::<200, Json<IndexStats>>()
+}
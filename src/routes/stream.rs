@@ -0,0 +1,66 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum::http::header::ACCEPT;
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::IntoResponse;
+use futures_core::Stream;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+/// Media type of server-sent events.
+pub(crate) const EVENT_STREAM: &str = "text/event-stream";
+
+/// Number of events buffered between a job and the connection.
+const BUFFER_EVENTS: usize = 64;
+
+/// Whether the client listed `media_type` in its `Accept` header, e.g. to ask for a stream.
+pub(crate) fn accepts(headers: &HeaderMap, media_type: &str) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|accepted| accepted.split(';').next())
+        .any(|accepted| accepted.trim().eq_ignore_ascii_case(media_type))
+}
+
+/// A server-sent event named `name` with the JSON of `data`.
+pub(crate) fn event<T: Serialize>(name: &str, data: &T) -> Event {
+    Event::default()
+        .event(name)
+        .json_data(data)
+        .unwrap_or_else(|e| {
+            Event::default()
+                .event("error")
+                .data(format!("Failed to serialize event: {e}"))
+        })
+}
+
+/// Server-sent events of a running job, ending when the job drops its sender.
+struct Events(mpsc::Receiver<Event>);
+
+impl Stream for Events {
+    type Item = Result<Event, Infallible>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.poll_recv(cx).map(|event| event.map(Ok))
+    }
+}
+
+/// Runs `job` on a background task and streams the events it sends as server-sent events. The
+/// job should stop once sending fails, which means that the client went away.
+pub(crate) fn event_stream<F, Fut>(job: F) -> axum::response::Response
+where
+    F: FnOnce(mpsc::Sender<Event>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(BUFFER_EVENTS);
+    tokio::spawn(job(sender));
+    Sse::new(Events(receiver))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
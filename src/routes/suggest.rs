@@ -0,0 +1,84 @@
+use aide::axum::IntoApiResponse;
+use aide::transform::TransformOperation;
+use axum::extract::State;
+use axum::Json;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_aux::prelude::*;
+
+use super::error::{too_complex, ApiError};
+use super::validate::{deserialize_edit_distance, FieldErrors, Valid, Validate};
+use super::{cap, run_blocking, Response};
+use crate::diagnostics::RequestSummary;
+use crate::geonames::data::SpellingSuggestion;
+use crate::geonames::deadline::Deadline;
+use crate::geonames::searcher::GeoNamesSearcher;
+use crate::AppState;
+
+fn _default_state_limit() -> usize {
+    10000
+}
+fn _schemars_default_query() -> String {
+    "Frankfrut".to_string()
+}
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct RequestSuggest {
+    /// The possibly misspelled query.
+    #[validate(length(min = 1))]
+    #[schemars(default = "_schemars_default_query")]
+    pub query: String,
+    /// Maximum Levenshtein distance of the suggestions. Defaults to 2.
+    #[serde(
+        default = "default_u32::<2>",
+        deserialize_with = "deserialize_edit_distance"
+    )]
+    pub max_dist: u32,
+    /// Limit the number of states to search. Defaults to 10000. Long queries or high `max_dist` values may require increasing this limit.
+    #[serde(
+        default = "_default_state_limit",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub state_limit: usize,
+    /// Maximum number of suggestions, at most the result cap of the service. Defaults to 5.
+    #[serde(
+        default = "default_u32::<5>",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub limit: u32,
+}
+
+impl Validate for RequestSuggest {
+    fn check(&self, errors: &mut FieldErrors) {
+        errors.query("query", &self.query);
+        errors.edit_distance("max_dist", self.max_dist);
+    }
+}
+
+pub(crate) async fn suggest(
+    State(state): State<AppState>,
+    Valid(Json(request)): Valid<Json<RequestSuggest>>,
+) -> impl IntoApiResponse {
+    let summary = RequestSummary::query(&request.query);
+    let search = move |searcher: &GeoNamesSearcher, _: Deadline| {
+        searcher
+            .suggestions(
+                &request.query,
+                request.max_dist,
+                request.state_limit,
+                cap(request.limit as usize, searcher.max_results),
+            )
+            .map_err(too_complex)
+    };
+    let response = run_blocking(&state, search)
+        .await
+        .map(|suggestions| Json(Response::results(suggestions)));
+    summary.attach(response)
+}
+
+pub(crate) fn suggest_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Spelling suggestions (\"did you mean\") for a possibly misspelled query: distinct names within <code>max_dist</code> of the query, excluding the query itself.<br>Closer names come first, ties are broken by the highest population and the number of GeoNames entries carrying the name. Returns only the names, not the entries.")
+        .response::<200, Json<Response<SpellingSuggestion>>>()
+        .response_with::<400, ApiError, _>(|t| t.description("The query exceeded the maximum number of states (`query_too_complex`)."))
+        .response_with::<422, ApiError, _>(|t| t.description("The query was empty or too long, or `max_dist` was too high."))
+        .response_with::<504, ApiError, _>(|t| t.description("The search exceeded the search timeout."))
+}
@@ -0,0 +1,85 @@
+use aide::axum::IntoApiResponse;
+use aide::transform::TransformOperation;
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use super::error::{ApiError, ErrorCode};
+use super::{limit_page, run_blocking, Response};
+use crate::geonames::data::GeoNamesEntry;
+use crate::geonames::geo::BoundingBox;
+use crate::AppState;
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct TilePath {
+    /// Zoom level, from `0` to `30`.
+    pub z: u8,
+    /// Column of the tile, from `0` (west) to `2^z - 1`.
+    pub x: u32,
+    /// Row of the tile, from `0` (north) to `2^z - 1`.
+    pub y: u32,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct TileQuery {
+    /// Only include entries of this feature class, e.g. `P` for populated places.
+    pub feature_class: Option<String>,
+    /// Only include entries of this feature code, e.g. `PPLC` for capitals.
+    pub feature_code: Option<String>,
+    /// Only include entries with at least this population.
+    pub min_population: Option<u64>,
+    /// Return at most this many entries, the most populous first. Omit to return all entries, up to
+    /// the result cap of the service.
+    pub limit: Option<usize>,
+}
+
+pub(crate) async fn tile(
+    State(state): State<AppState>,
+    Path(tile): Path<TilePath>,
+    Query(query): Query<TileQuery>,
+) -> impl IntoApiResponse {
+    let Some(bbox) = BoundingBox::from_tile(tile.z, tile.x, tile.y) else {
+        return Err(ApiError::new(
+            ErrorCode::InvalidRequest,
+            format!(
+                "Invalid tile: {}/{}/{} does not exist",
+                tile.z, tile.x, tile.y
+            ),
+        ));
+    };
+
+    let page = run_blocking(&state, move |searcher, _deadline| {
+        let eq = |filter: &Option<String>, value: &str| filter.as_ref().is_none_or(|f| f == value);
+        let mut results: Vec<GeoNamesEntry> = searcher
+            .bbox(&bbox)
+            .into_iter()
+            .filter(|gn| {
+                eq(&query.feature_class, &gn.feature_class)
+                    && eq(&query.feature_code, &gn.feature_code)
+                    && query.min_population.is_none_or(|min| gn.population >= min)
+            })
+            .collect();
+        results.sort_by(|a, b| b.population.cmp(&a.population).then(a.id.cmp(&b.id)));
+        Ok(limit_page(
+            results.into(),
+            query.limit,
+            searcher.max_results,
+        ))
+    })
+    .await?;
+
+    Ok(Json(Response::from(page)))
+}
+
+pub(crate) fn tile_docs(op: TransformOperation) -> TransformOperation {
+    op.description("All GeoNames entries inside a web mercator (\"slippy map\") tile, the most populous first, e.g. to draw an overlay of GeoNames data on a map.<br>Responses carry an <code>ETag</code> and <code>Cache-Control</code>, so that clients and HTTP caches can reuse them.")
+        .response::<200, Json<Response<GeoNamesEntry>>>()
+        .response_with::<304, (), _>(|t| {
+            t.description("The tile did not change since the ETag given in If-None-Match.")
+        })
+        .response_with::<400, ApiError, _>(|t| {
+            t.description("The tile does not exist at the zoom level.")
+        })
+        .response_with::<504, ApiError, _>(|t| t.description("The search exceeded the search timeout."))
+}
@@ -0,0 +1,119 @@
+#[cfg(feature = "geonames_routes")]
+use aide::axum::IntoApiResponse;
+#[cfg(feature = "geonames_routes")]
+use aide::transform::TransformOperation;
+#[cfg(feature = "geonames_routes")]
+use axum::extract::State;
+#[cfg(feature = "geonames_routes")]
+use axum::Json;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use super::error::{ApiError, ErrorCode};
+#[cfg(feature = "geonames_routes")]
+use super::format::ResponseFormat;
+#[cfg(feature = "geonames_routes")]
+use super::validate::Valid;
+use super::validate::{FieldErrors, Validate};
+use super::{
+    _schemars_default_filter, dedup_results, extend_results, filter_predicate, limit_page,
+    rank_results, score_results, sort_results, ExtrasOptions, FilterResults, ResultOptions, Search,
+};
+#[cfg(feature = "geonames_routes")]
+use super::{run_blocking, Response};
+#[cfg(feature = "geonames_routes")]
+use crate::diagnostics::RequestSummary;
+use crate::geonames::data::GeoNamesSearchResult;
+use crate::geonames::deadline::Deadline;
+use crate::geonames::searcher::{GeoNamesSearcher, Page};
+#[cfg(feature = "geonames_routes")]
+use crate::AppState;
+
+#[derive(Clone, Deserialize, JsonSchema)]
+pub(crate) struct RequestOptsTokens {
+    #[schemars(default = "_schemars_default_filter")]
+    pub filter: Option<FilterResults>,
+    #[serde(flatten)]
+    pub results: ResultOptions,
+    #[serde(flatten)]
+    pub extras: ExtrasOptions,
+}
+
+fn _schemars_default_tokens_query() -> String {
+    "Main Frankfurt".to_string()
+}
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct RequestTokens {
+    /// The words to search for, in any order.
+    #[validate(length(min = 1))]
+    #[schemars(default = "_schemars_default_tokens_query")]
+    pub query: String,
+
+    #[serde(flatten)]
+    pub opts: RequestOptsTokens,
+}
+
+#[cfg(feature = "geonames_routes")]
+pub(crate) async fn tokens(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    Valid(Json(request)): Valid<Json<RequestTokens>>,
+) -> impl IntoApiResponse {
+    let summary = RequestSummary::query(&request.query);
+    let extent = request.opts.extras.extent;
+    let fields = request.opts.extras.fields.clone();
+    let response = run_blocking(&state, move |searcher, deadline| {
+        request.search(searcher, deadline)
+    })
+    .await
+    .map(|page| {
+        format
+            .encode(Response::from(page).with_extent(extent))
+            .with_fields(fields)
+    });
+    summary.attach(response)
+}
+
+impl Validate for RequestTokens {
+    fn check(&self, errors: &mut FieldErrors) {
+        errors.query("query", &self.query);
+    }
+}
+
+impl Search for RequestTokens {
+    type Item = GeoNamesSearchResult;
+
+    fn search(
+        &self,
+        searcher: &GeoNamesSearcher,
+        _deadline: Deadline,
+    ) -> Result<Page<Self::Item>, ApiError> {
+        let results = searcher
+            .token_search(&self.query, filter_predicate(&self.opts.filter))
+            .ok_or(ApiError::new(
+                ErrorCode::IndexUnavailable,
+                "The token index was not built, start the service with --token-index",
+            ))?;
+        let results = dedup_results(results, self.opts.results.dedup);
+        let results = score_results(results, &searcher.score_weights);
+        let results = rank_results(results, self.opts.results.rank_by);
+        let results = sort_results(results, self.opts.results.sort);
+        Ok(limit_page(
+            results.into(),
+            self.opts.results.limit,
+            searcher.max_results,
+        )
+        .map(|results| extend_results(results, &self.opts.extras, searcher)))
+    }
+}
+
+#[cfg(feature = "geonames_routes")]
+pub(crate) fn tokens_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Find all GeoNames entries whose name contains every word of the query, in any order and ignoring case, e.g. \"Main Frankfurt\" finds \"Frankfurt am Main\".<br>Requires the index to be built with <code>--token-index</code>.")
+        .response::<200, Json<Response<GeoNamesSearchResult>>>()
+        .response_with::<422, ApiError, _>(|t| t.description("The query was empty or too long."))
+        .response_with::<501, ApiError, _>(|t| {
+            t.description("The index was built without the token index.")
+        })
+        .response_with::<504, ApiError, _>(|t| t.description("The search exceeded the search timeout."))
+}
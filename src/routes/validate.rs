@@ -0,0 +1,302 @@
+#[cfg(feature = "duui")]
+use std::any::TypeId;
+use std::collections::BTreeMap;
+#[cfg(feature = "duui")]
+use std::collections::{BTreeSet, HashMap};
+#[cfg(any(feature = "geonames_routes", feature = "duui"))]
+use std::ops::Deref;
+#[cfg(feature = "duui")]
+use std::sync::{Mutex, OnceLock};
+
+#[cfg(any(feature = "geonames_routes", feature = "duui"))]
+use aide::generate::GenContext;
+#[cfg(any(feature = "geonames_routes", feature = "duui"))]
+use aide::openapi::{Operation, Response};
+#[cfg(any(feature = "geonames_routes", feature = "duui"))]
+use aide::operation::OperationInput;
+#[cfg(any(feature = "geonames_routes", feature = "duui"))]
+use axum::extract::{FromRequest, FromRequestParts, Request};
+#[cfg(any(feature = "geonames_routes", feature = "duui"))]
+use axum::http::request::Parts;
+#[cfg(any(feature = "geonames_routes", feature = "duui"))]
+use axum::response::IntoResponse;
+#[cfg(feature = "duui")]
+use axum::Json;
+#[cfg(feature = "duui")]
+use schemars::schema::{Schema, SchemaObject};
+#[cfg(feature = "duui")]
+use schemars::JsonSchema;
+#[cfg(feature = "duui")]
+use serde::de::DeserializeOwned;
+use serde::de::Error as _;
+use serde::Deserializer;
+use serde_aux::prelude::deserialize_number_from_string;
+#[cfg(feature = "duui")]
+use serde_json::{Map, Value};
+
+use super::error::{ApiError, ErrorCode};
+
+/// Longest accepted query or pattern, in characters.
+pub(crate) const MAX_QUERY_CHARS: usize = 1024;
+/// Largest accepted edit distance. Higher distances match nearly every key and are never useful.
+pub(crate) const MAX_EDIT_DISTANCE: u32 = 8;
+/// Largest accepted number of queries of a batch.
+#[cfg(feature = "geonames_routes")]
+pub(crate) const MAX_BATCH_QUERIES: usize = 1000;
+
+/// Deserialize an edit distance from a number or a numeric string, rejecting negative distances
+/// with a message naming the field, which is otherwise lost in flattened options.
+pub(crate) fn deserialize_edit_distance<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<u32, D::Error> {
+    let dist: i64 = deserialize_number_from_string(deserializer)?;
+    u32::try_from(dist).map_err(|_| {
+        D::Error::custom(format!(
+            "max_dist must be a non-negative integer, got {dist}"
+        ))
+    })
+}
+
+/// Error messages of the invalid fields of a request, by field name.
+#[derive(Debug, Default)]
+pub(crate) struct FieldErrors(BTreeMap<String, Vec<String>>);
+
+impl FieldErrors {
+    pub(crate) fn add(&mut self, field: &str, message: impl Into<String>) {
+        self.0
+            .entry(field.to_string())
+            .or_default()
+            .push(message.into());
+    }
+
+    /// Add the errors of a nested object, prefixing their fields with `field`, e.g.
+    /// `queries[0].max_dist`.
+    pub(crate) fn nested(&mut self, field: &str, errors: FieldErrors) {
+        for (name, messages) in errors.0 {
+            self.0
+                .entry(format!("{field}.{name}"))
+                .or_default()
+                .extend(messages);
+        }
+    }
+
+    /// Check that a query or pattern is neither empty nor longer than [`MAX_QUERY_CHARS`].
+    pub(crate) fn query(&mut self, field: &str, query: &str) {
+        if query.is_empty() {
+            self.add(field, "must not be empty");
+        } else {
+            self.max_length(field, query);
+        }
+    }
+
+    /// Check that a possibly empty query is not longer than [`MAX_QUERY_CHARS`].
+    pub(crate) fn max_length(&mut self, field: &str, query: &str) {
+        if query.chars().count() > MAX_QUERY_CHARS {
+            self.add(
+                field,
+                format!("must be at most {MAX_QUERY_CHARS} characters long"),
+            );
+        }
+    }
+
+    /// Check that an edit distance is at most [`MAX_EDIT_DISTANCE`].
+    pub(crate) fn edit_distance(&mut self, field: &str, dist: u32) {
+        if dist > MAX_EDIT_DISTANCE {
+            self.add(field, format!("must be at most {MAX_EDIT_DISTANCE}"));
+        }
+    }
+
+    pub(crate) fn into_result(self) -> Result<(), ApiError> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        let message = self
+            .0
+            .iter()
+            .map(|(field, messages)| format!("{field} {}", messages.join(", ")))
+            .collect::<Vec<_>>()
+            .join("; ");
+        Err(ApiError::new(
+            ErrorCode::ValidationFailed,
+            format!("Invalid request: {message}"),
+        )
+        .with_details(serde_json::json!({ "fields": self.0 })))
+    }
+}
+
+/// Checks of a request that its types cannot express, e.g. non-empty queries.
+pub(crate) trait Validate {
+    fn check(&self, errors: &mut FieldErrors);
+
+    /// Fails with 422 Unprocessable Entity and the messages of all invalid fields, if any.
+    fn validate(&self) -> Result<(), ApiError> {
+        let mut errors = FieldErrors::default();
+        self.check(&mut errors);
+        errors.into_result()
+    }
+}
+
+/// Extractor running [`Validate`] on the request extracted by `E`, e.g. `Valid<Json<T>>`.
+#[cfg(any(feature = "geonames_routes", feature = "duui"))]
+pub(crate) struct Valid<E>(pub E);
+
+#[cfg(any(feature = "geonames_routes", feature = "duui"))]
+impl<S, E, T> FromRequest<S> for Valid<E>
+where
+    S: Send + Sync,
+    E: FromRequest<S> + Deref<Target = T>,
+    T: Validate,
+{
+    type Rejection = axum::response::Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let extracted = E::from_request(request, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        extracted.validate().map_err(IntoResponse::into_response)?;
+        Ok(Self(extracted))
+    }
+}
+
+#[cfg(any(feature = "geonames_routes", feature = "duui"))]
+impl<S, E, T> FromRequestParts<S> for Valid<E>
+where
+    S: Send + Sync,
+    E: FromRequestParts<S> + Deref<Target = T>,
+    T: Validate,
+{
+    type Rejection = axum::response::Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let extracted = E::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        extracted.validate().map_err(IntoResponse::into_response)?;
+        Ok(Self(extracted))
+    }
+}
+
+#[cfg(any(feature = "geonames_routes", feature = "duui"))]
+impl<E: OperationInput> OperationInput for Valid<E> {
+    fn operation_input(ctx: &mut GenContext, operation: &mut Operation) {
+        E::operation_input(ctx, operation);
+    }
+
+    fn inferred_early_responses(
+        ctx: &mut GenContext,
+        operation: &mut Operation,
+    ) -> Vec<(Option<u16>, Response)> {
+        E::inferred_early_responses(ctx, operation)
+    }
+}
+
+/// Like `Json`, but rejects top-level fields that are not part of the JSON schema of `T`, which
+/// serde would silently ignore, e.g. misspelled options or options of another mode.
+#[cfg(feature = "duui")]
+pub(crate) struct StrictJson<T>(pub T);
+
+#[cfg(feature = "duui")]
+impl<T> Deref for StrictJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[cfg(feature = "duui")]
+impl<S, T> FromRequest<S> for StrictJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + JsonSchema + 'static,
+{
+    type Rejection = axum::response::Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<Value>::from_request(request, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let extracted = T::deserialize(&value).map_err(|e| {
+            ApiError::new(
+                ErrorCode::UnprocessableRequest,
+                format!("Failed to deserialize the JSON body into the target type: {e}"),
+            )
+            .into_response()
+        })?;
+
+        let mut errors = FieldErrors::default();
+        if let Value::Object(object) = &value {
+            let known = known_fields(schema_of::<T>(), object);
+            for field in object.keys().filter(|field| !known.contains(*field)) {
+                errors.add(field, "is not a known field");
+            }
+        }
+        errors.into_result().map_err(IntoResponse::into_response)?;
+        Ok(Self(extracted))
+    }
+}
+
+#[cfg(feature = "duui")]
+impl<T: JsonSchema> OperationInput for StrictJson<T> {
+    fn operation_input(ctx: &mut GenContext, operation: &mut Operation) {
+        Json::<T>::operation_input(ctx, operation);
+    }
+
+    fn inferred_early_responses(
+        ctx: &mut GenContext,
+        operation: &mut Operation,
+    ) -> Vec<(Option<u16>, Response)> {
+        Json::<T>::inferred_early_responses(ctx, operation)
+    }
+}
+
+/// The JSON schema of `T`, generated on first use and kept for the later requests.
+#[cfg(feature = "duui")]
+fn schema_of<T: JsonSchema + 'static>() -> &'static SchemaObject {
+    static SCHEMAS: OnceLock<Mutex<HashMap<TypeId, &'static SchemaObject>>> = OnceLock::new();
+    let mut schemas = SCHEMAS.get_or_init(Default::default).lock().unwrap();
+    schemas
+        .entry(TypeId::of::<T>())
+        .or_insert_with(|| Box::leak(Box::new(schemars::schema_for!(T).schema)))
+}
+
+/// The fields of an object schema, including those of the subschemas that apply to `object`.
+#[cfg(feature = "duui")]
+fn known_fields<'a>(schema: &'a SchemaObject, object: &Map<String, Value>) -> BTreeSet<&'a String> {
+    let mut fields: BTreeSet<&String> = schema
+        .object
+        .iter()
+        .flat_map(|validation| validation.properties.keys())
+        .collect();
+    let subschemas = schema
+        .subschemas
+        .iter()
+        .flat_map(|subschemas| [&subschemas.one_of, &subschemas.any_of, &subschemas.all_of])
+        .flatten()
+        .flatten();
+    for subschema in subschemas {
+        if let Schema::Object(subschema) = subschema {
+            if applies(subschema, object) {
+                fields.extend(known_fields(subschema, object));
+            }
+        }
+    }
+    fields
+}
+
+/// Whether the subschema applies to `object`: all of its properties with a single allowed value,
+/// like the tag of an internally tagged enum, have that value.
+#[cfg(feature = "duui")]
+fn applies(schema: &SchemaObject, object: &Map<String, Value>) -> bool {
+    schema
+        .object
+        .iter()
+        .flat_map(|validation| &validation.properties)
+        .all(|(name, property)| match property {
+            Schema::Object(SchemaObject {
+                enum_values: Some(values),
+                ..
+            }) if values.len() == 1 => object.get(name) == Some(&values[0]),
+            _ => true,
+        })
+}
@@ -0,0 +1,121 @@
+use std::sync::{Arc, RwLock};
+#[cfg(feature = "geonames_routes")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "geonames_routes")]
+use sha2::{Digest, Sha256};
+
+use crate::geonames::searcher::GeoNamesSearcher;
+use crate::routes::capabilities::Capabilities;
+use crate::routes::facets::Facets;
+use crate::routes::stats::IndexStats;
+
+/// The served index together with the summaries computed from it, which are replaced as a whole
+/// when the index is upgraded.
+#[derive(Clone)]
+pub(crate) struct ServedIndex {
+    pub searcher: Arc<GeoNamesSearcher>,
+    /// Identifies the served index and scoring in the ETags of cacheable responses.
+    #[cfg(feature = "geonames_routes")]
+    pub generation: String,
+    pub capabilities: Capabilities,
+    #[cfg(feature = "geonames_routes")]
+    pub facets: Facets,
+    pub stats: IndexStats,
+}
+
+impl ServedIndex {
+    pub fn new(searcher: GeoNamesSearcher, routes: Vec<String>, preliminary: bool) -> Self {
+        let mut capabilities = Capabilities::new(&searcher, routes);
+        capabilities.preliminary = preliminary;
+        let facets = Facets::new(searcher.geonames.values());
+        let stats = IndexStats::new(&searcher, &facets);
+        Self {
+            #[cfg(feature = "geonames_routes")]
+            generation: index_generation(&searcher),
+            searcher: Arc::new(searcher),
+            capabilities,
+            #[cfg(feature = "geonames_routes")]
+            facets,
+            stats,
+        }
+    }
+}
+
+/// Short hash identifying the served index and the settings that affect search results.
+///
+/// Artifacts are identified by the checksum of their data, so that all servers of the same
+/// artifact agree. Indexes built at startup are assumed to differ from the previous ones.
+#[cfg(feature = "geonames_routes")]
+fn index_generation(searcher: &GeoNamesSearcher) -> String {
+    let data = match searcher.manifest.as_ref() {
+        Some(manifest) => manifest.data_sha256.clone(),
+        None => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+            .to_string(),
+    };
+    let generation = format!(
+        "{}:{data}:{:?}",
+        env!("CARGO_PKG_VERSION"),
+        searcher.score_weights
+    );
+    format!("{:x}", Sha256::digest(generation))[..16].to_string()
+}
+
+/// Handle to the served index, shared by all requests.
+///
+/// Requests take a snapshot of the index when they start, so that a request in progress keeps
+/// searching the index it started with while newer requests already see its replacement.
+#[derive(Clone)]
+pub(crate) struct SharedIndex(Arc<RwLock<Arc<ServedIndex>>>);
+
+impl SharedIndex {
+    pub fn new(index: ServedIndex) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(index))))
+    }
+
+    /// A snapshot of the currently served index.
+    pub fn load(&self) -> Arc<ServedIndex> {
+        self.0.read().unwrap().clone()
+    }
+
+    /// Run `f` on the served index, making a replacement wait until it returns, e.g. so that
+    /// entries inserted into the overlay are not lost by a concurrent upgrade.
+    pub fn with_current<T>(&self, f: impl FnOnce(&ServedIndex) -> T) -> T {
+        f(&self.0.read().unwrap())
+    }
+
+    /// List the routes of the service in the capabilities, which are only known once the API is
+    /// finished.
+    pub fn set_routes(&self, routes: Vec<String>) {
+        let mut current = self.0.write().unwrap();
+        let mut index = ServedIndex::clone(&current);
+        index.capabilities.routes = routes;
+        *current = Arc::new(index);
+    }
+
+    /// Serve the given index from now on, keeping the entries inserted into the overlay of the
+    /// previous index unless the new index has entries with the same ids.
+    ///
+    /// The overlay is copied rather than moved, so that requests still searching the previous
+    /// index keep seeing its entries until they finish.
+    pub fn replace(&self, searcher: GeoNamesSearcher) {
+        let routes = self.load().capabilities.routes.clone();
+        let index = ServedIndex::new(searcher, routes, false);
+
+        let mut current = self.0.write().unwrap();
+        let mut overlay = current.searcher.overlay.read().unwrap().clone();
+        let dropped = overlay.retain(|id| !index.searcher.geonames.contains_key(&id));
+        if dropped > 0 {
+            tracing::warn!(
+                dropped,
+                "Dropped overlay entries that are part of the new index"
+            );
+        }
+        *index.searcher.overlay.write().unwrap() = overlay;
+        *current = Arc::new(index);
+        current.capabilities.log();
+    }
+}